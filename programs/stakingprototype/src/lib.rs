@@ -1,67 +1,391 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::clock::Clock;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_spl::token_interface::{self, get_mint_extension_data, Mint, MintTo, SetAuthority, TokenAccount, TokenInterface, TransferChecked};
+use anchor_spl::token_interface::spl_token_2022::instruction::AuthorityType;
+use spl_token_group_interface::state::TokenGroupMember;
 use anchor_spl::associated_token::AssociatedToken;
 
 declare_id!("A6wFmzoTbvudsizcaC8YrrfsuQJD8qf1WHvj1bv2y76u");
 
+/// Longest window (one year) the pool accumulator advances over in one update, and the
+/// upper bound for `StakingPool::max_accrual_seconds`. Clamping keeps the reward math
+/// from overflowing for long-idle pools.
+pub const MAX_ACCRUAL_SECONDS: i64 = 365 * 86400;
+
+/// Fixed-point scale of `StakingPool::acc_reward_per_share`.
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
+
+/// Backwards clock movement tolerated (and treated as no elapsed time) before
+/// instructions fail with `ClockWentBackwards`.
+pub const MAX_CLOCK_DRIFT_SECONDS: i64 = 60;
+
+/// Basis points in 100%.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Highest stake or unstake fee a pool may charge, in basis points.
+pub const MAX_FEE_BPS: u16 = 1_000;
+
+/// Highest share of a keeper-triggered compound the keeper may keep, in basis points.
+pub const MAX_KEEPER_TIP_BPS: u16 = 500;
+
+/// Shortest period a decaying rate schedule may use. Keeps the number of rate
+/// changes the accumulator walks in one update bounded.
+pub const MIN_DECAY_PERIOD_SECONDS: i64 = 86400;
+
+/// `StakingPool::rounding_mode` values: how settling rounds a reward's fraction of a token.
+/// Floor carries the fraction in `reward_remainder`; rounding up pays it out instead.
+pub const ROUNDING_FLOOR: u8 = 0;
+pub const ROUNDING_NEAREST: u8 = 1;
+pub const ROUNDING_CEIL: u8 = 2;
+
+/// Number of lock tiers a pool offers.
+pub const LOCK_TIER_COUNT: usize = 4;
+
+/// Lock tiers every pool starts with: no lock at 1x, 30 days at 1.25x, 90 days at 1.5x.
+/// The unused last slot has a zero multiplier and can't be selected.
+pub const DEFAULT_LOCK_TIERS: [LockTier; LOCK_TIER_COUNT] = [
+    LockTier { lock_days: 0, multiplier_bps: 10_000 },
+    LockTier { lock_days: 30, multiplier_bps: 12_500 },
+    LockTier { lock_days: 90, multiplier_bps: 15_000 },
+    LockTier { lock_days: 0, multiplier_bps: 0 },
+];
+
+/// Canonical address and bump of pool `pool_id`, matching the seeds every instruction checks.
+pub fn get_pool_address(pool_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"staking_pool", pool_id.to_le_bytes().as_ref()], &crate::ID)
+}
+
+/// Addresses of the stake and reward vaults `initialize` creates for `staking_pool`.
+pub fn get_pool_vault_addresses(staking_pool: &Pubkey) -> (Pubkey, Pubkey) {
+    let (stake_vault, _) = Pubkey::find_program_address(&[b"pool_stake", staking_pool.as_ref()], &crate::ID);
+    let (reward_vault, _) = Pubkey::find_program_address(&[b"pool_reward", staking_pool.as_ref()], &crate::ID);
+    (stake_vault, reward_vault)
+}
+
 #[program]
 pub mod stakingprototype {
     use super::*;
 
-    pub fn initialize(
-        ctx: Context<Initialize>,
-        reward_rate: u64,
-    ) -> Result<()> {
+    pub fn initialize(ctx: Context<Initialize>, pool_id: u64, params: InitializeParams) -> Result<()> {
+        let InitializeParams {
+            reward_rate,
+            lock_duration,
+            reward_rate_is_per_second,
+            min_stake_amount,
+            early_unstake_penalty_bps,
+            max_total_rewards,
+            cooldown_seconds,
+            max_total_staked,
+            max_reward_rate,
+            rate_timelock,
+            rewards_start_time,
+            rewards_end_time,
+            reward_rate_denominator,
+            shared_emission,
+            normalize_decimals,
+            reward_equals_stake,
+        } = params;
         let staking_pool = &mut ctx.accounts.staking_pool;
         let admin = &ctx.accounts.admin;
 
+        // `init` already rejects an existing pool; this keeps config safe if that ever changes
+        require!(!staking_pool.is_initialized, ErrorCode::AlreadyInitialized);
+        require!(
+            early_unstake_penalty_bps <= BPS_DENOMINATOR as u16,
+            ErrorCode::InvalidPenaltyBps
+        );
+        require!(
+            max_reward_rate == 0 || reward_rate <= max_reward_rate,
+            ErrorCode::RewardRateTooHigh
+        );
+        require!(
+            rewards_end_time == 0 || rewards_end_time > rewards_start_time,
+            ErrorCode::InvalidRewardsWindow
+        );
+
+        require!(reward_rate_denominator > 0, ErrorCode::InvalidRewardRateDenominator);
+
+        // Sharing one mint for stake and rewards has to be asked for explicitly
+        require!(
+            reward_equals_stake || ctx.accounts.reward_mint.key() != ctx.accounts.stake_mint.key(),
+            ErrorCode::MintCollision
+        );
+
+        staking_pool.pool_id = pool_id;
+        staking_pool.bump = ctx.bumps.staking_pool;
         staking_pool.admin = admin.key();
+        staking_pool.pending_admin = Pubkey::default();
         staking_pool.reward_rate = reward_rate;
+        staking_pool.reward_rate_denominator = reward_rate_denominator;
+        staking_pool.max_reward_rate = max_reward_rate;
+        staking_pool.rate_timelock = rate_timelock;
+        staking_pool.pending_reward_rate = 0;
+        staking_pool.pending_rate_effective_time = 0;
+        staking_pool.require_solvency_on_rate_change = false;
+        staking_pool.rewards_start_time = rewards_start_time;
+        staking_pool.rewards_end_time = rewards_end_time;
+        staking_pool.reward_rate_is_per_second = reward_rate_is_per_second;
+        staking_pool.shared_emission = shared_emission;
+        staking_pool.normalize_decimals = normalize_decimals;
+        staking_pool.reward_equals_stake = reward_equals_stake;
+        staking_pool.rounding_mode = ROUNDING_FLOOR;
+        staking_pool.rate_schedule_enabled = false;
+        staking_pool.initial_rate = 0;
+        staking_pool.decay_bps_per_period = 0;
+        staking_pool.period_seconds = 0;
+        staking_pool.schedule_start = 0;
+        staking_pool.lock_duration = lock_duration;
+        staking_pool.min_stake_amount = min_stake_amount;
+        staking_pool.early_unstake_penalty_bps = early_unstake_penalty_bps;
+        staking_pool.referral_bps = 0;
+        staking_pool.stake_fee_bps = 0;
+        staking_pool.unstake_fee_bps = 0;
+        staking_pool.fee_recipient = Pubkey::default();
+        staking_pool.nft_collection = Pubkey::default();
+        staking_pool.nft_boost_bps = 0;
+        staking_pool.boost_mint = Pubkey::default();
+        staking_pool.pool_boost_account = Pubkey::default();
+        staking_pool.boost_tokens_per_bps = 0;
+        staking_pool.max_boost_bps = 0;
+        staking_pool.total_boost_locked = 0;
+        staking_pool.keeper_tip_bps = 0;
+        staking_pool.lock_tiers = DEFAULT_LOCK_TIERS;
+        staking_pool.reward_rates = [0; LOCK_TIER_COUNT];
+        staking_pool.cooldown_seconds = cooldown_seconds;
+        staking_pool.claim_cooldown_seconds = 0;
+        staking_pool.unstake_grace_seconds = 0;
+        staking_pool.paused = false;
+        staking_pool.deposits_paused = false;
+        staking_pool.prevent_flash = false;
+        staking_pool.partial_fill_unstake = false;
+        staking_pool.require_whitelist = false;
+        staking_pool.blacklist_blocks_unstake = false;
+        staking_pool.receipt_required = false;
+        staking_pool.admin_can_extend_lock = false;
         staking_pool.total_staked = 0;
+        staking_pool.total_weighted_stake = 0;
+        staking_pool.total_pending_unstake = 0;
+        staking_pool.staker_count = 0;
+        staking_pool.max_total_staked = max_total_staked;
+        staking_pool.max_stake_per_user = 0;
+        staking_pool.max_positions_per_user = 0;
+        staking_pool.max_single_stake = 0;
+        staking_pool.total_rewards_funded = 0;
+        staking_pool.total_forfeited = 0;
+        staking_pool.max_total_rewards = max_total_rewards;
+        staking_pool.total_rewards_distributed = 0;
+        staking_pool.total_reward_debt = 0;
+        staking_pool.acc_reward_per_share = 0;
+        staking_pool.acc_reward_carry = 0;
+        staking_pool.tier_acc_reward_per_share = [0; LOCK_TIER_COUNT];
+        staking_pool.tier_acc_reward_carry = [0; LOCK_TIER_COUNT];
         staking_pool.last_update_time = Clock::get()?.unix_timestamp;
+        staking_pool.accrual_frozen_at = 0;
+        staking_pool.max_accrual_seconds = MAX_ACCRUAL_SECONDS;
         staking_pool.stake_mint = ctx.accounts.stake_mint.key();
         staking_pool.reward_mint = ctx.accounts.reward_mint.key();
+        staking_pool.stake_decimals = ctx.accounts.stake_mint.decimals;
+        staking_pool.reward_decimals = ctx.accounts.reward_mint.decimals;
         staking_pool.pool_stake_account = ctx.accounts.pool_stake_account.key();
         staking_pool.pool_reward_account = ctx.accounts.pool_reward_account.key();
+        staking_pool.reward_mint_2 = Pubkey::default();
+        staking_pool.pool_reward_account_2 = Pubkey::default();
+        staking_pool.reward_rate_2 = 0;
+        staking_pool.acc_reward_per_share_2 = 0;
+        staking_pool.acc_reward_carry_2 = 0;
+        staking_pool.total_reward_debt_2 = 0;
+        staking_pool.is_initialized = true;
 
-        msg!("Staking pool initialized with rate: {}", reward_rate);
+        msg!("Staking pool {} initialized with rate: {}", pool_id, reward_rate);
+        emit!(PoolInitialized {
+            pool: staking_pool.key(),
+            pool_id,
+            admin: staking_pool.admin,
+            stake_mint: staking_pool.stake_mint,
+            reward_mint: staking_pool.reward_mint,
+            pool_stake_account: staking_pool.pool_stake_account,
+            pool_reward_account: staking_pool.pool_reward_account,
+            reward_rate,
+        });
         Ok(())
     }
 
-    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
+    pub fn stake(
+        ctx: Context<Stake>,
+        _pool_id: u64,
+        position_id: u64,
+        amount: u64,
+        lock_days: u64,
+        referrer: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::ZeroAmount);
+
         let staking_pool = &mut ctx.accounts.staking_pool;
         let user_stake = &mut ctx.accounts.user_stake;
+        let user_account = &mut ctx.accounts.user_account;
+        let pool_member = &mut ctx.accounts.pool_member;
         let user = &ctx.accounts.user;
+        let position_owner = *position_owner_key(user, &ctx.accounts.position_owner);
         let clock = Clock::get()?;
 
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require!(!staking_pool.deposits_paused, ErrorCode::DepositsPaused);
+        require!(
+            staking_pool.max_single_stake == 0 || amount <= staking_pool.max_single_stake,
+            ErrorCode::StakeTooLarge
+        );
+
+        require_not_blacklisted(&ctx.accounts.blacklist)?;
+
+        if staking_pool.require_whitelist {
+            let whitelisted = ctx.accounts.whitelist.as_ref().is_some_and(|entry| entry.active);
+            require!(whitelisted, ErrorCode::NotWhitelisted);
+        }
+
+        let (reward_tier, lock_tier) = staking_pool
+            .lock_tiers
+            .iter()
+            .copied()
+            .enumerate()
+            .find(|(_, tier)| tier.multiplier_bps > 0 && tier.lock_days == lock_days)
+            .ok_or(ErrorCode::InvalidLockDays)?;
+
         // Update rewards for the pool before changes
-        let time_passed = clock.unix_timestamp - staking_pool.last_update_time;
-        if time_passed > 0 && staking_pool.total_staked > 0 {
-            // Update global state
-            staking_pool.last_update_time = clock.unix_timestamp;
+        update_pool(staking_pool, clock.unix_timestamp)?;
+
+        if let Some(referrer) = referrer {
+            require!(referrer != position_owner, ErrorCode::SelfReferral);
+        }
+
+        // Never treat an account recorded for someone else as this owner's position, even if
+        // it somehow sits at their address
+        require!(
+            stake_account_belongs_to(user_stake, &position_owner),
+            ErrorCode::Unauthorized
+        );
+
+        // Only the owner opens a position; afterwards its delegate may also top it up
+        let is_first_stake = user_stake.owner == Pubkey::default();
+        if is_first_stake {
+            require!(position_owner == user.key(), ErrorCode::NotOwnerOrDelegate);
+        } else {
+            require!(is_owner_or_delegate(user_stake, &user.key()), ErrorCode::NotOwnerOrDelegate);
         }
 
-        // Initialize user stake if this is their first time
-        if user_stake.owner == Pubkey::default() {
-            user_stake.owner = user.key();
+        // Initialize user stake if this is their first time; later stakes keep the original referrer
+        if is_first_stake {
+            // The wallet's positions are counted across every pool; 0 leaves them uncapped
+            require!(
+                staking_pool.max_positions_per_user == 0
+                    || user_account.positions < staking_pool.max_positions_per_user,
+                ErrorCode::TooManyPositions
+            );
+            user_account.owner = position_owner;
+            user_account.positions = user_account.positions.checked_add(1).ok_or(ErrorCode::ArithmeticError)?;
+            join_pool(staking_pool, pool_member, position_owner)?;
+            user_stake.owner = position_owner;
+            user_stake.delegate = None;
+            user_stake.reward_destination = None;
+            user_stake.receipt_mint = Pubkey::default();
+            user_stake.position_id = position_id;
+            user_stake.referrer = referrer.unwrap_or_default();
             user_stake.stake_amount = 0;
+            user_stake.max_stake_reached = 0;
             user_stake.reward_debt = 0;
+            user_stake.reward_remainder = 0;
+            user_stake.reward_debt_2 = 0;
+            user_stake.reward_remainder_2 = 0;
+            user_stake.total_claimed = 0;
+            user_stake.boost_bps = 0;
+            user_stake.nft_mint = Pubkey::default();
+            user_stake.governance_boost_bps = 0;
+            user_stake.boost_amount = 0;
+            user_stake.boost_unlock = 0;
             user_stake.last_stake_time = clock.unix_timestamp;
-        } else {
-            // Calculate pending rewards before updating stake
-            let pending_reward = calculate_pending_reward(
-                user_stake.stake_amount,
-                staking_pool.reward_rate,
-                clock.unix_timestamp - user_stake.last_stake_time,
-            )?;
-            
-            user_stake.reward_debt += pending_reward;
+            user_stake.average_entry_time = clock.unix_timestamp;
+            user_stake.unlock_time = clock.unix_timestamp;
+        }
+
+        let weight_before = weighted_stake(user_stake);
+        // Roll rewards earned by the existing stake into reward_debt
+        settle_rewards(staking_pool, user_stake)?;
+
+        // The chosen tier's multiplier and rate apply to the whole position from now on
+        let multiplier_raised = lock_tier.multiplier_bps > user_stake.lock_multiplier_bps;
+        user_stake.lock_multiplier_bps = lock_tier.multiplier_bps;
+        user_stake.reward_tier = reward_tier as u8;
+        user_stake.reward_checkpoint = staking_pool.tier_acc_reward_per_share[reward_tier];
+
+        // An NFT from the pool's collection boosts the position for as long as it stays
+        // escrowed with the pool, so one NFT can't boost two positions at once
+        match (
+            &ctx.accounts.nft_mint,
+            &ctx.accounts.nft_token_account,
+            &ctx.accounts.nft_escrow_account,
+            &ctx.accounts.nft_token_program,
+        ) {
+            (Some(nft_mint), Some(nft_token_account), Some(nft_escrow_account), Some(nft_token_program)) => {
+                require!(
+                    staking_pool.nft_collection != Pubkey::default()
+                        && user_stake.nft_mint == Pubkey::default()
+                        && position_owner == user.key()
+                        && nft_mint.decimals == 0
+                        && nft_token_account.mint == nft_mint.key()
+                        && nft_token_account.owner == position_owner
+                        && nft_token_account.amount == 1
+                        && nft_escrow_account.mint == nft_mint.key(),
+                    ErrorCode::InvalidNftBoost
+                );
+
+                let member = get_mint_extension_data::<TokenGroupMember>(&nft_mint.to_account_info())
+                    .map_err(|_| error!(ErrorCode::InvalidNftBoost))?;
+                require!(
+                    member.mint == nft_mint.key() && member.group == staking_pool.nft_collection,
+                    ErrorCode::InvalidNftBoost
+                );
+
+                let cpi_accounts = TransferChecked {
+                    from: nft_token_account.to_account_info(),
+                    mint: nft_mint.to_account_info(),
+                    to: nft_escrow_account.to_account_info(),
+                    authority: user.to_account_info(),
+                };
+                token_interface::transfer_checked(
+                    CpiContext::new(nft_token_program.to_account_info(), cpi_accounts),
+                    1,
+                    nft_mint.decimals,
+                )?;
+
+                user_stake.nft_mint = nft_mint.key();
+                user_stake.boost_bps = staking_pool.nft_boost_bps;
+            }
+            (None, None, None, None) => {}
+            _ => return err!(ErrorCode::InvalidNftBoost),
+        }
+
+        // Skim the stake fee straight from the user to the fee recipient
+        let fee = calculate_bps_share(amount, staking_pool.stake_fee_bps)?;
+        if fee > 0 {
+            let fee_token_account = ctx.accounts.fee_token_account.as_ref().ok_or(ErrorCode::InvalidFeeAccount)?;
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.user_token_account.to_account_info(),
+                mint: ctx.accounts.stake_mint.to_account_info(),
+                to: fee_token_account.to_account_info(),
+                authority: user.to_account_info(),
+            };
+
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+            token_interface::transfer_checked(cpi_ctx, fee, ctx.accounts.stake_mint.decimals)?;
+            msg!("Stake fee: {} tokens", fee);
         }
 
-        // Transfer tokens from user to pool
-        let cpi_accounts = Transfer {
+        // Transfer the rest from user to pool
+        let vault_balance_before = ctx.accounts.pool_stake_account.amount;
+        let cpi_accounts = TransferChecked {
             from: ctx.accounts.user_token_account.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
             to: ctx.accounts.pool_stake_account.to_account_info(),
             authority: user.to_account_info(),
         };
@@ -69,361 +393,5863 @@ pub mod stakingprototype {
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
         
-        token::transfer(cpi_ctx, amount)?;
+        let deposit = amount.checked_sub(fee).ok_or(ErrorCode::ArithmeticError)?;
+        token_interface::transfer_checked(cpi_ctx, deposit, ctx.accounts.stake_mint.decimals)?;
+
+        // Transfer-fee mints deliver less than `amount`, so credit only what arrived
+        ctx.accounts.pool_stake_account.reload()?;
+        let received = ctx.accounts.pool_stake_account.amount
+            .checked_sub(vault_balance_before)
+            .ok_or(ErrorCode::ArithmeticError)?;
 
         // Update stake amount
-        user_stake.stake_amount = user_stake.stake_amount.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
-        user_stake.last_stake_time = clock.unix_timestamp;
-        
+        user_stake.average_entry_time = blended_entry_time(user_stake, received, clock.unix_timestamp)?;
+        user_stake.stake_amount = user_stake.stake_amount.checked_add(received).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.max_stake_reached = user_stake.max_stake_reached.max(user_stake.stake_amount);
+        user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+        user_stake.last_stake_slot = clock.slot;
+
+        // A zero minimum disables the check
+        require!(
+            staking_pool.min_stake_amount == 0 || user_stake.stake_amount >= staking_pool.min_stake_amount,
+            ErrorCode::BelowMinimumStake
+        );
+
+        // A zero cap means unlimited
+        require!(
+            staking_pool.max_stake_per_user == 0 || user_stake.stake_amount <= staking_pool.max_stake_per_user,
+            ErrorCode::UserStakeCapExceeded
+        );
+
+        // Deposits lock from the position's stake-weighted entry time, using the longer of
+        // the pool lock and the chosen tier's lock, so topping up doesn't restart the whole lock.
+        // A top-up that raises the multiplier pays it on the whole stake, though, so then the
+        // whole stake serves the new lock from now
+        let tier_lock_seconds = i64::try_from(lock_tier.lock_days)
+            .ok()
+            .and_then(|days| days.checked_mul(86400))
+            .ok_or(ErrorCode::ArithmeticError)?;
+        let lock_start = if multiplier_raised { clock.unix_timestamp } else { user_stake.average_entry_time };
+        let new_unlock_time = lock_start
+            .checked_add(staking_pool.lock_duration.max(tier_lock_seconds))
+            .ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.unlock_time = user_stake.unlock_time.max(new_unlock_time);
+
         // Update total staked in pool
-        staking_pool.total_staked = staking_pool.total_staked.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        staking_pool.total_staked = staking_pool.total_staked.checked_add(received).ok_or(ErrorCode::ArithmeticError)?;
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+
+        // Only the first stake pays the referrer, straight into their own position's reward_debt
+        let referral_reward = calculate_bps_share(received, staking_pool.referral_bps)?;
+        if is_first_stake && user_stake.referrer != Pubkey::default() && referral_reward > 0 {
+            let referrer_stake = ctx.accounts.referrer_stake.as_mut().ok_or(ErrorCode::InvalidReferrer)?;
+            let (expected_referrer_stake, _) = Pubkey::find_program_address(
+                &[
+                    b"user-stake",
+                    staking_pool.key().as_ref(),
+                    user_stake.referrer.as_ref(),
+                    referrer_stake.position_id.to_le_bytes().as_ref(),
+                ],
+                &crate::ID,
+            );
+            require!(
+                referrer_stake.owner == user_stake.referrer && referrer_stake.key() == expected_referrer_stake,
+                ErrorCode::InvalidReferrer
+            );
+
+            referrer_stake.reward_debt = referrer_stake.reward_debt.checked_add(referral_reward as u128).ok_or(ErrorCode::ArithmeticError)?;
+            staking_pool.total_reward_debt = staking_pool.total_reward_debt.checked_add(referral_reward as u128).ok_or(ErrorCode::ArithmeticError)?;
+            msg!("Credited referrer {} with {} reward tokens", user_stake.referrer, referral_reward);
+        }
 
-        msg!("Staked {} tokens", amount);
+        // A zero cap means unlimited
+        require!(
+            staking_pool.max_total_staked == 0 || staking_pool.total_staked <= staking_pool.max_total_staked,
+            ErrorCode::PoolCapExceeded
+        );
+
+        msg!("Staked {} tokens", received);
+        emit!(StakeEvent {
+            user: position_owner,
+            amount: received,
+            stake_amount: user_stake.stake_amount,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
-    pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
+    pub fn unstake(ctx: Context<Unstake>, pool_id: u64, _position_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::ZeroAmount);
+
         // Get information before mutating staking_pool
         let pool_stake_account_info = ctx.accounts.pool_stake_account.to_account_info();
         let user_token_account_info = ctx.accounts.user_token_account.to_account_info();
         let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
         let token_program_info = ctx.accounts.token_program.to_account_info();
-        let bump = ctx.bumps.staking_pool;
+        let stake_mint_info = ctx.accounts.stake_mint.to_account_info();
+        let fee_token_account_info = ctx.accounts.fee_token_account.as_ref().map(|account| account.to_account_info());
+        let decimals = ctx.accounts.stake_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
         
         let staking_pool = &mut ctx.accounts.staking_pool;
         let user_stake = &mut ctx.accounts.user_stake;
         let clock = Clock::get()?;
 
+        // Pools with a cooldown only release stake through request_unstake/withdraw_unstaked
+        require!(staking_pool.cooldown_seconds == 0, ErrorCode::CooldownActive);
+        require!(
+            !staked_this_slot(staking_pool, user_stake, clock.slot),
+            ErrorCode::SameSlotStakeUnstake
+        );
+        require_may_unstake(staking_pool, &ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &ctx.accounts.user.key())?;
+
         require!(
             user_stake.stake_amount >= amount,
             ErrorCode::InsufficientStakeAmount
         );
 
-        // Calculate pending rewards before unstaking
-        let pending_reward = calculate_pending_reward(
-            user_stake.stake_amount,
-            staking_pool.reward_rate,
-            clock.unix_timestamp - user_stake.last_stake_time,
-        )?;
-        
-        user_stake.reward_debt += pending_reward;
+        // A vault short of the recorded stake would fail the transfer; in partial-fill mode
+        // pay out what's there and leave the rest staked. Forfeited penalties and tokens
+        // waiting out their cooldown aren't this position's to take
+        let requested = amount;
+        let amount = if staking_pool.partial_fill_unstake {
+            let available = ctx.accounts.pool_stake_account.amount
+                .saturating_sub(staking_pool.total_forfeited)
+                .saturating_sub(staking_pool.total_pending_unstake);
+            amount.min(available)
+        } else {
+            amount
+        };
+        require!(amount > 0, ErrorCode::ZeroAmount);
+        let shortfall = requested - amount;
+
+        // Early exits are only allowed when the pool charges a penalty for them, and
+        // never for positions earning a lock multiplier
+        let is_early = clock.unix_timestamp < user_stake.unlock_time;
+        let has_lock_boost = user_stake.lock_multiplier_bps > BPS_DENOMINATOR as u16;
+        require!(
+            !is_early || (staking_pool.early_unstake_penalty_bps > 0 && !has_lock_boost),
+            ErrorCode::StakeLocked
+        );
+
+        // Backing out right after staking is free
+        let penalty = if is_early && !within_unstake_grace(staking_pool, user_stake, clock.unix_timestamp)? {
+            calculate_bps_share(amount, staking_pool.early_unstake_penalty_bps)?
+        } else {
+            0
+        };
+        let payout = amount.checked_sub(penalty).ok_or(ErrorCode::ArithmeticError)?;
+        let fee = calculate_bps_share(payout, staking_pool.unstake_fee_bps)?;
+
+        // Settle pending rewards before unstaking
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
         
         // Update stake amount
+        let weight_before = weighted_stake(user_stake);
         user_stake.stake_amount = user_stake.stake_amount.checked_sub(amount).ok_or(ErrorCode::ArithmeticError)?;
-        user_stake.last_stake_time = clock.unix_timestamp;
+        user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
         
         // Update total staked in pool
         staking_pool.total_staked = staking_pool.total_staked.checked_sub(amount).ok_or(ErrorCode::ArithmeticError)?;
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+
+        // The penalty stays in pool_stake_account as forfeited tokens
+        staking_pool.total_forfeited = staking_pool.total_forfeited.checked_add(penalty).ok_or(ErrorCode::ArithmeticError)?;
         
         // Transfer tokens from pool to user
+        let pool_id_bytes = pool_id.to_le_bytes();
         let pool_signer_seeds = &[
             b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
             &[bump],
         ];
         let signer = &[&pool_signer_seeds[..]];
         
-        let cpi_accounts = Transfer {
+        transfer_unstake_fee(
+            fee,
+            fee_token_account_info,
+            &pool_stake_account_info,
+            &stake_mint_info,
+            &staking_pool_info,
+            &token_program_info,
+            signer,
+            decimals,
+        )?;
+
+        let cpi_accounts = TransferChecked {
             from: pool_stake_account_info,
+            mint: stake_mint_info,
             to: user_token_account_info,
             authority: staking_pool_info,
         };
         
-        token::transfer(
+        token_interface::transfer_checked(
             CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
-            amount
+            payout.checked_sub(fee).ok_or(ErrorCode::ArithmeticError)?,
+            decimals
         )?;
 
+        if penalty > 0 {
+            msg!("Early unstake penalty: {} tokens", penalty);
+        }
+        if shortfall > 0 {
+            msg!("Partially filled unstake: {} tokens short", shortfall);
+        }
         msg!("Unstaked {} tokens", amount);
+        emit!(UnstakeEvent {
+            user: ctx.accounts.user.key(),
+            amount,
+            stake_amount: ctx.accounts.user_stake.stake_amount,
+            shortfall,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
-    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+    /// Returns the whole position without touching the reward math, so users can always
+    /// exit even if settling would fail or the reward vault is empty. Every unclaimed
+    /// reward, settled or not, is forfeited.
+    pub fn emergency_unstake(ctx: Context<EmergencyUnstake>, pool_id: u64, _position_id: u64) -> Result<()> {
         // Get information before mutating staking_pool
-        let pool_reward_account_info = ctx.accounts.pool_reward_account.to_account_info();
-        let user_reward_account_info = ctx.accounts.user_reward_account.to_account_info();
+        let pool_stake_account_info = ctx.accounts.pool_stake_account.to_account_info();
+        let user_token_account_info = ctx.accounts.user_token_account.to_account_info();
         let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
         let token_program_info = ctx.accounts.token_program.to_account_info();
-        let bump = ctx.bumps.staking_pool;
-        
+        let stake_mint_info = ctx.accounts.stake_mint.to_account_info();
+        let fee_token_account_info = ctx.accounts.fee_token_account.as_ref().map(|account| account.to_account_info());
+        let decimals = ctx.accounts.stake_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+
         let staking_pool = &mut ctx.accounts.staking_pool;
         let user_stake = &mut ctx.accounts.user_stake;
         let clock = Clock::get()?;
 
-        // Calculate pending rewards
-        let pending_reward = calculate_pending_reward(
-            user_stake.stake_amount,
-            staking_pool.reward_rate,
-            clock.unix_timestamp - user_stake.last_stake_time,
-        )?;
-        
-        let total_reward = user_stake.reward_debt.checked_add(pending_reward).ok_or(ErrorCode::ArithmeticError)?;
-        
-        require!(total_reward > 0, ErrorCode::NoRewardsToClaim);
-        
-        // Reset reward debt
+        require_may_unstake(staking_pool, &ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &ctx.accounts.user.key())?;
+
+        let amount = user_stake.stake_amount;
+        require!(amount > 0, ErrorCode::InsufficientStakeAmount);
+        require!(
+            !staked_this_slot(staking_pool, user_stake, clock.slot),
+            ErrorCode::SameSlotStakeUnstake
+        );
+
+        // Forfeiting rewards doesn't skip the lock: early exits follow the same rules as
+        // `unstake` and pay its penalty (waived within the grace window)
+        let is_early = clock.unix_timestamp < user_stake.unlock_time;
+        let has_lock_boost = user_stake.lock_multiplier_bps > BPS_DENOMINATOR as u16;
+        require!(
+            !is_early || (staking_pool.early_unstake_penalty_bps > 0 && !has_lock_boost),
+            ErrorCode::StakeLocked
+        );
+        let penalty = if is_early && !within_unstake_grace(staking_pool, user_stake, clock.unix_timestamp)? {
+            calculate_bps_share(amount, staking_pool.early_unstake_penalty_bps)?
+        } else {
+            0
+        };
+        let payout = amount.checked_sub(penalty).ok_or(ErrorCode::ArithmeticError)?;
+
+        // Keep the accumulator current for everyone else when possible, but never let it
+        // block the exit; update_pool leaves the pool untouched when it fails
+        if update_pool(staking_pool, clock.unix_timestamp).is_err() {
+            msg!("Skipped reward accrual during emergency unstake");
+        }
+
+        // Drop the position's rewards and checkpoint it so nothing earned before now is ever paid
+        staking_pool.total_reward_debt = staking_pool.total_reward_debt.saturating_sub(user_stake.reward_debt);
+        staking_pool.total_reward_debt_2 = staking_pool.total_reward_debt_2.saturating_sub(user_stake.reward_debt_2);
         user_stake.reward_debt = 0;
-        user_stake.last_stake_time = clock.unix_timestamp;
-        
-        // Transfer reward tokens from pool to user
+        user_stake.reward_debt_2 = 0;
+        user_stake.reward_remainder = 0;
+        user_stake.reward_remainder_2 = 0;
+        user_stake.reward_checkpoint = staking_pool.tier_acc_reward_per_share[user_stake.reward_tier as usize];
+        user_stake.reward_checkpoint_2 = staking_pool.acc_reward_per_share_2;
+
+        let weight_before = weighted_stake(user_stake);
+        user_stake.stake_amount = 0;
+        user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+        staking_pool.total_staked = staking_pool.total_staked.saturating_sub(amount);
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+        staking_pool.total_forfeited = staking_pool.total_forfeited.checked_add(penalty).ok_or(ErrorCode::ArithmeticError)?;
+
+        // The cooldown still applies, so an emergency exit can't jump the withdrawal queue;
+        // the stake waits as pending_unstake and leaves through withdraw_unstaked
+        if staking_pool.cooldown_seconds > 0 {
+            user_stake.pending_unstake = user_stake.pending_unstake.checked_add(payout).ok_or(ErrorCode::ArithmeticError)?;
+            user_stake.cooldown_end = clock.unix_timestamp
+                .checked_add(staking_pool.cooldown_seconds)
+                .ok_or(ErrorCode::ArithmeticError)?;
+            staking_pool.total_pending_unstake = staking_pool.total_pending_unstake.checked_add(payout).ok_or(ErrorCode::ArithmeticError)?;
+
+            msg!("Emergency unstake of {} tokens queued until {}, forfeiting rewards", payout, user_stake.cooldown_end);
+            return Ok(());
+        }
+
+        let fee = calculate_bps_share(payout, staking_pool.unstake_fee_bps)?;
+
+        let pool_id_bytes = pool_id.to_le_bytes();
         let pool_signer_seeds = &[
             b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
             &[bump],
         ];
         let signer = &[&pool_signer_seeds[..]];
-        
-        let cpi_accounts = Transfer {
-            from: pool_reward_account_info,
-            to: user_reward_account_info,
+
+        transfer_unstake_fee(
+            fee,
+            fee_token_account_info,
+            &pool_stake_account_info,
+            &stake_mint_info,
+            &staking_pool_info,
+            &token_program_info,
+            signer,
+            decimals,
+        )?;
+
+        let cpi_accounts = TransferChecked {
+            from: pool_stake_account_info,
+            mint: stake_mint_info,
+            to: user_token_account_info,
             authority: staking_pool_info,
         };
-        
-        token::transfer(
+
+        token_interface::transfer_checked(
             CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
-            total_reward
+            payout.checked_sub(fee).ok_or(ErrorCode::ArithmeticError)?,
+            decimals
         )?;
 
-        msg!("Claimed {} reward tokens", total_reward);
+        if penalty > 0 {
+            msg!("Early unstake penalty: {} tokens", penalty);
+        }
+        msg!("Emergency unstaked {} tokens, forfeiting rewards", amount);
+        emit!(UnstakeEvent {
+            user: ctx.accounts.user.key(),
+            amount,
+            stake_amount: 0,
+            shortfall: 0,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
-    pub fn update_reward_rate(ctx: Context<UpdateRewardRate>, new_rate: u64) -> Result<()> {
+    /// Unstakes `amount` and pays out every settled reward in one instruction, so exiting
+    /// users sign and pay for a single transaction.
+    pub fn unstake_and_claim(ctx: Context<UnstakeAndClaim>, pool_id: u64, _position_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::ZeroAmount);
+
+        // Get information before mutating staking_pool
+        let pool_stake_account_info = ctx.accounts.pool_stake_account.to_account_info();
+        let pool_reward_account_info = ctx.accounts.pool_reward_account.to_account_info();
+        let user_token_account_info = ctx.accounts.user_token_account.to_account_info();
+        let user_reward_account_info = ctx.accounts.user_reward_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let reward_token_program_info = ctx.accounts.reward_token_program.to_account_info();
+        let stake_mint_info = ctx.accounts.stake_mint.to_account_info();
+        let reward_mint_info = ctx.accounts.reward_mint.to_account_info();
+        let fee_token_account_info = ctx.accounts.fee_token_account.as_ref().map(|account| account.to_account_info());
+        let stake_decimals = ctx.accounts.stake_mint.decimals;
+        let reward_decimals = ctx.accounts.reward_mint.decimals;
+        let reward_vault_balance = ctx.accounts.pool_reward_account.amount;
+        let bump = ctx.accounts.staking_pool.bump;
+
         let staking_pool = &mut ctx.accounts.staking_pool;
-        let admin = &ctx.accounts.admin;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
 
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require_not_blacklisted(&ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &ctx.accounts.user.key())?;
+
+        // Pools with a cooldown only release stake through request_unstake/withdraw_unstaked
+        require!(staking_pool.cooldown_seconds == 0, ErrorCode::CooldownActive);
         require!(
-            admin.key() == staking_pool.admin,
-            ErrorCode::Unauthorized
+            !staked_this_slot(staking_pool, user_stake, clock.slot),
+            ErrorCode::SameSlotStakeUnstake
         );
 
-        staking_pool.reward_rate = new_rate;
-        msg!("Updated reward rate to {}", new_rate);
+        require!(
+            user_stake.stake_amount >= amount,
+            ErrorCode::InsufficientStakeAmount
+        );
+
+        // The claim half is rate-limited like any other claim
+        require!(
+            claim_cooldown_over(staking_pool, user_stake.last_claim_time, clock.unix_timestamp)?,
+            ErrorCode::ClaimCooldownActive
+        );
+
+        // Same early-exit rules as unstake
+        let is_early = clock.unix_timestamp < user_stake.unlock_time;
+        let has_lock_boost = user_stake.lock_multiplier_bps > BPS_DENOMINATOR as u16;
+        require!(
+            !is_early || (staking_pool.early_unstake_penalty_bps > 0 && !has_lock_boost),
+            ErrorCode::StakeLocked
+        );
+
+        // Backing out right after staking is free
+        let penalty = if is_early && !within_unstake_grace(staking_pool, user_stake, clock.unix_timestamp)? {
+            calculate_bps_share(amount, staking_pool.early_unstake_penalty_bps)?
+        } else {
+            0
+        };
+        let payout = amount.checked_sub(penalty).ok_or(ErrorCode::ArithmeticError)?;
+        let fee = calculate_bps_share(payout, staking_pool.unstake_fee_bps)?;
+
+        // Settle once; both halves below work from the same snapshot
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        let mut total_reward = to_token_amount(user_stake.reward_debt)?;
+
+        // Pay out no more than what is left of the emission budget; the rest stays owed
+        if staking_pool.max_total_rewards > 0 {
+            let remaining_budget = staking_pool.max_total_rewards.saturating_sub(staking_pool.total_rewards_distributed);
+            total_reward = total_reward.min(remaining_budget);
+        }
+
+        require!(
+            reward_vault_balance >= total_reward,
+            ErrorCode::InsufficientRewardFunds
+        );
+
+        let weight_before = weighted_stake(user_stake);
+        user_stake.stake_amount = user_stake.stake_amount.checked_sub(amount).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+        staking_pool.total_staked = staking_pool.total_staked.checked_sub(amount).ok_or(ErrorCode::ArithmeticError)?;
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+        staking_pool.total_forfeited = staking_pool.total_forfeited.checked_add(penalty).ok_or(ErrorCode::ArithmeticError)?;
+
+        user_stake.reward_debt -= total_reward as u128;
+        staking_pool.total_reward_debt = staking_pool.total_reward_debt.checked_sub(total_reward as u128).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.total_claimed = user_stake.total_claimed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.last_claim_time = clock.unix_timestamp;
+        staking_pool.total_rewards_distributed = staking_pool.total_rewards_distributed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        transfer_unstake_fee(
+            fee,
+            fee_token_account_info,
+            &pool_stake_account_info,
+            &stake_mint_info,
+            &staking_pool_info,
+            &token_program_info,
+            signer,
+            stake_decimals,
+        )?;
+
+        let cpi_accounts = TransferChecked {
+            from: pool_stake_account_info,
+            mint: stake_mint_info,
+            to: user_token_account_info,
+            authority: staking_pool_info.clone(),
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            payout.checked_sub(fee).ok_or(ErrorCode::ArithmeticError)?,
+            stake_decimals
+        )?;
+
+        // Exiting shouldn't fail just because nothing has accrued yet
+        if total_reward > 0 {
+            let cpi_accounts = TransferChecked {
+                from: pool_reward_account_info,
+                mint: reward_mint_info,
+                to: user_reward_account_info,
+                authority: staking_pool_info,
+            };
+
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(reward_token_program_info, cpi_accounts, signer),
+                total_reward,
+                reward_decimals
+            )?;
+        }
+
+        if penalty > 0 {
+            msg!("Early unstake penalty: {} tokens", penalty);
+        }
+        msg!("Unstaked {} tokens and claimed {} reward tokens", amount, total_reward);
+        emit!(UnstakeEvent {
+            user: ctx.accounts.user.key(),
+            amount,
+            stake_amount: ctx.accounts.user_stake.stake_amount,
+            shortfall: 0,
+            timestamp: clock.unix_timestamp,
+        });
+        emit!(ClaimEvent {
+            user: ctx.accounts.user.key(),
+            amount: total_reward,
+            stake_amount: ctx.accounts.user_stake.stake_amount,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
-}
 
-fn calculate_pending_reward(stake_amount: u64, reward_rate: u64, time_passed: i64) -> Result<u64> {
-    if time_passed <= 0 || stake_amount == 0 {
-        return Ok(0);
-    }
+    pub fn request_unstake(ctx: Context<RequestUnstake>, _pool_id: u64, _position_id: u64, amount: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
 
-    // Convert time_passed to seconds in a day (86400 seconds in a day)
-    let days = time_passed.checked_div(86400).unwrap_or(0) as u64;
-    let remainder_seconds = time_passed.checked_rem(86400).unwrap_or(0) as u64;
-    
-    // Calculate full days of rewards
-    let mut reward = stake_amount
-        .checked_mul(reward_rate)
-        .ok_or(ErrorCode::ArithmeticError)?
-        .checked_mul(days)
-        .ok_or(ErrorCode::ArithmeticError)?;
+        require_may_unstake(staking_pool, &ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &ctx.accounts.user.key())?;
+        require!(
+            amount > 0 && user_stake.stake_amount >= amount,
+            ErrorCode::InsufficientStakeAmount
+        );
+        require!(clock.unix_timestamp >= user_stake.unlock_time, ErrorCode::StakeLocked);
+        require!(
+            !staked_this_slot(staking_pool, user_stake, clock.slot),
+            ErrorCode::SameSlotStakeUnstake
+        );
 
-    // Add partial day rewards (pro-rated)
-    if remainder_seconds > 0 {
-        let partial_reward = stake_amount
-            .checked_mul(reward_rate)
-            .ok_or(ErrorCode::ArithmeticError)?
-            .checked_mul(remainder_seconds)
-            .ok_or(ErrorCode::ArithmeticError)?
-            .checked_div(86400)
+        // Settle what the position earned so far; the pending portion earns nothing after this
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        let weight_before = weighted_stake(user_stake);
+        user_stake.stake_amount = user_stake.stake_amount.checked_sub(amount).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.pending_unstake = user_stake.pending_unstake.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+
+        // Each request restarts the cooldown for everything pending
+        user_stake.cooldown_end = clock.unix_timestamp
+            .checked_add(staking_pool.cooldown_seconds)
             .ok_or(ErrorCode::ArithmeticError)?;
-        
-        reward = reward.checked_add(partial_reward).ok_or(ErrorCode::ArithmeticError)?;
-    }
 
-    Ok(reward)
-}
+        staking_pool.total_staked = staking_pool.total_staked.checked_sub(amount).ok_or(ErrorCode::ArithmeticError)?;
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+        staking_pool.total_pending_unstake = staking_pool.total_pending_unstake.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = admin, 
-        space = 8 + StakingPool::SIZE,
-        seeds = [b"staking_pool"],
-        bump
-    )]
-    pub staking_pool: Account<'info, StakingPool>,
-    
-    #[account(mut)]
-    pub admin: Signer<'info>,
-    
-    pub stake_mint: Account<'info, Mint>,
-    pub reward_mint: Account<'info, Mint>,
-    
-    #[account(
-        mut,
-        constraint = pool_stake_account.mint == stake_mint.key(),
-        constraint = pool_stake_account.owner == staking_pool.key()
-    )]
-    pub pool_stake_account: Account<'info, TokenAccount>,
-    
-    #[account(
-        mut,
-        constraint = pool_reward_account.mint == reward_mint.key(),
-        constraint = pool_reward_account.owner == staking_pool.key()
-    )]
-    pub pool_reward_account: Account<'info, TokenAccount>,
-    
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>,
-}
+        msg!("Requested unstake of {} tokens, withdrawable at {}", amount, user_stake.cooldown_end);
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct Stake<'info> {
-    #[account(
+    pub fn withdraw_unstaked(ctx: Context<WithdrawUnstaked>, pool_id: u64, _position_id: u64) -> Result<()> {
+        // Get information before mutating user_stake
+        let pool_stake_account_info = ctx.accounts.pool_stake_account.to_account_info();
+        let user_token_account_info = ctx.accounts.user_token_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let stake_mint_info = ctx.accounts.stake_mint.to_account_info();
+        let fee_token_account_info = ctx.accounts.fee_token_account.as_ref().map(|account| account.to_account_info());
+        let decimals = ctx.accounts.stake_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
+
+        require_may_unstake(staking_pool, &ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &ctx.accounts.user.key())?;
+
+        let amount = user_stake.pending_unstake;
+        require!(amount > 0, ErrorCode::InsufficientStakeAmount);
+        require!(clock.unix_timestamp >= user_stake.cooldown_end, ErrorCode::CooldownActive);
+
+        user_stake.pending_unstake = 0;
+        staking_pool.total_pending_unstake = staking_pool.total_pending_unstake.checked_sub(amount).ok_or(ErrorCode::ArithmeticError)?;
+        let fee = calculate_bps_share(amount, staking_pool.unstake_fee_bps)?;
+
+        // Transfer tokens from pool to user
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        transfer_unstake_fee(
+            fee,
+            fee_token_account_info,
+            &pool_stake_account_info,
+            &stake_mint_info,
+            &staking_pool_info,
+            &token_program_info,
+            signer,
+            decimals,
+        )?;
+
+        let cpi_accounts = TransferChecked {
+            from: pool_stake_account_info,
+            mint: stake_mint_info,
+            to: user_token_account_info,
+            authority: staking_pool_info,
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            amount.checked_sub(fee).ok_or(ErrorCode::ArithmeticError)?,
+            decimals
+        )?;
+
+        msg!("Withdrew {} unstaked tokens", amount);
+        emit!(UnstakeEvent {
+            user: ctx.accounts.user.key(),
+            amount,
+            stake_amount: ctx.accounts.user_stake.stake_amount,
+            shortfall: 0,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn compound(ctx: Context<Compound>, pool_id: u64, position_id: u64) -> Result<()> {
+        restake_rewards(ctx, pool_id, position_id, u64::MAX)
+    }
+
+    pub fn restake_rewards(ctx: Context<Compound>, pool_id: u64, _position_id: u64, amount: u64) -> Result<()> {
+        // Get information before mutating staking_pool
+        let pool_reward_account_info = ctx.accounts.pool_reward_account.to_account_info();
+        let pool_stake_account_info = ctx.accounts.pool_stake_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let reward_mint_info = ctx.accounts.reward_mint.to_account_info();
+        let decimals = ctx.accounts.reward_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
+
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require_not_blacklisted(&ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &ctx.accounts.user.key())?;
+        require!(
+            staking_pool.stake_mint == staking_pool.reward_mint,
+            ErrorCode::CompoundMintMismatch
+        );
+
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        // `amount` is a u64, so this fits even when the whole debt doesn't
+        let mut total_reward = to_token_amount((amount as u128).min(user_stake.reward_debt))?;
+
+        // Restaking counts against the emission budget just like a claim
+        if staking_pool.max_total_rewards > 0 {
+            let remaining_budget = staking_pool.max_total_rewards.saturating_sub(staking_pool.total_rewards_distributed);
+            total_reward = total_reward.min(remaining_budget);
+        }
+
+        require!(total_reward > 0, ErrorCode::NoRewardsToClaim);
+        require!(
+            ctx.accounts.pool_reward_account.amount >= total_reward,
+            ErrorCode::InsufficientRewardFunds
+        );
+
+        // Only what was restaked leaves the debt; anything the budget held back stays owed
+        user_stake.reward_debt -= total_reward as u128;
+        staking_pool.total_reward_debt = staking_pool.total_reward_debt.checked_sub(total_reward as u128).ok_or(ErrorCode::ArithmeticError)?;
+        let weight_before = weighted_stake(user_stake);
+        user_stake.average_entry_time = blended_entry_time(user_stake, total_reward, clock.unix_timestamp)?;
+        user_stake.stake_amount = user_stake.stake_amount.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.max_stake_reached = user_stake.max_stake_reached.max(user_stake.stake_amount);
+        user_stake.total_claimed = user_stake.total_claimed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+
+        staking_pool.total_staked = staking_pool.total_staked.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+        staking_pool.total_rewards_distributed = staking_pool.total_rewards_distributed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+
+        // Restaked rewards count toward the same caps as a deposit
+        require!(
+            staking_pool.max_stake_per_user == 0 || user_stake.stake_amount <= staking_pool.max_stake_per_user,
+            ErrorCode::UserStakeCapExceeded
+        );
+        require!(
+            staking_pool.max_total_staked == 0 || staking_pool.total_staked <= staking_pool.max_total_staked,
+            ErrorCode::PoolCapExceeded
+        );
+
+        // Move the rewards into the stake vault so unstakes stay fully backed
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: pool_reward_account_info,
+            mint: reward_mint_info,
+            to: pool_stake_account_info,
+            authority: staking_pool_info,
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            total_reward,
+            decimals
+        )?;
+
+        msg!("Restaked {} reward tokens", total_reward);
+        emit!(StakeEvent {
+            user: ctx.accounts.user.key(),
+            amount: total_reward,
+            stake_amount: ctx.accounts.user_stake.stake_amount,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    /// Claims a position's rewards and stakes them into the caller's position in another
+    /// pool whose stake mint is this pool's reward mint.
+    pub fn claim_and_stake_into(
+        ctx: Context<ClaimAndStakeInto>,
+        pool_id: u64,
+        _position_id: u64,
+        target_pool_id: u64,
+        _target_position_id: u64,
+    ) -> Result<()> {
+        // Get information before mutating either pool
+        let pool_reward_account_info = ctx.accounts.pool_reward_account.to_account_info();
+        let target_pool_stake_account_info = ctx.accounts.target_pool_stake_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let reward_mint_info = ctx.accounts.reward_mint.to_account_info();
+        let decimals = ctx.accounts.reward_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+        let user_key = ctx.accounts.user.key();
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let target_pool = &mut ctx.accounts.target_pool;
+        let target_user_stake = &mut ctx.accounts.target_user_stake;
+        let clock = Clock::get()?;
+
+        // Both pools are written back at the end, so the same pool can't be both
+        require!(pool_id != target_pool_id, ErrorCode::InvalidAccountAlias);
+        require!(
+            target_pool.stake_mint == staking_pool.reward_mint,
+            ErrorCode::CompoundMintMismatch
+        );
+        require!(!staking_pool.paused && !target_pool.paused, ErrorCode::PoolPaused);
+        require_not_blacklisted(&ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &user_key)?;
+        require!(!target_pool.deposits_paused, ErrorCode::DepositsPaused);
+        require_not_blacklisted(&ctx.accounts.target_blacklist)?;
+        if target_pool.require_whitelist {
+            let whitelisted = ctx.accounts.target_whitelist.as_ref().is_some_and(|entry| entry.active);
+            require!(whitelisted, ErrorCode::NotWhitelisted);
+        }
+        require!(reward_destination(user_stake) == user_key, ErrorCode::RewardDestinationMismatch);
+        require!(
+            claim_cooldown_over(staking_pool, user_stake.last_claim_time, clock.unix_timestamp)?,
+            ErrorCode::ClaimCooldownActive
+        );
+
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        let mut total_reward = to_token_amount(user_stake.reward_debt)?;
+
+        // Routing counts against the emission budget just like a claim
+        if staking_pool.max_total_rewards > 0 {
+            let remaining_budget = staking_pool.max_total_rewards.saturating_sub(staking_pool.total_rewards_distributed);
+            total_reward = total_reward.min(remaining_budget);
+        }
+
+        require!(total_reward > 0, ErrorCode::NoRewardsToClaim);
+        require!(
+            ctx.accounts.pool_reward_account.amount >= total_reward,
+            ErrorCode::InsufficientRewardFunds
+        );
+        require!(
+            target_pool.max_single_stake == 0 || total_reward <= target_pool.max_single_stake,
+            ErrorCode::StakeTooLarge
+        );
+
+        user_stake.reward_debt -= total_reward as u128;
+        staking_pool.total_reward_debt = staking_pool.total_reward_debt.checked_sub(total_reward as u128).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.total_claimed = user_stake.total_claimed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+        user_stake.last_claim_time = clock.unix_timestamp;
+        staking_pool.total_rewards_distributed = staking_pool.total_rewards_distributed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+
+        // Only the source pool signs; the target vault just receives
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let vault_balance_before = ctx.accounts.target_pool_stake_account.amount;
+        let cpi_accounts = TransferChecked {
+            from: pool_reward_account_info,
+            mint: reward_mint_info,
+            to: target_pool_stake_account_info,
+            authority: staking_pool_info,
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            total_reward,
+            decimals
+        )?;
+
+        // Transfer-fee mints deliver less than was sent, so credit only what arrived
+        ctx.accounts.target_pool_stake_account.reload()?;
+        let received = ctx.accounts.target_pool_stake_account.amount
+            .checked_sub(vault_balance_before)
+            .ok_or(ErrorCode::ArithmeticError)?;
+
+        update_pool(target_pool, clock.unix_timestamp)?;
+        settle_rewards(target_pool, target_user_stake)?;
+
+        let weight_before = weighted_stake(target_user_stake);
+        target_user_stake.average_entry_time = blended_entry_time(target_user_stake, received, clock.unix_timestamp)?;
+        target_user_stake.stake_amount = target_user_stake.stake_amount.checked_add(received).ok_or(ErrorCode::ArithmeticError)?;
+        target_user_stake.max_stake_reached = target_user_stake.max_stake_reached.max(target_user_stake.stake_amount);
+        target_user_stake.last_stake_time = clock.unix_timestamp.max(target_user_stake.last_stake_time);
+        target_user_stake.last_stake_slot = clock.slot;
+
+        // Routed rewards lock like a deposit under the target position's current tier
+        let tier_lock_seconds = i64::try_from(target_pool.lock_tiers[target_user_stake.reward_tier as usize].lock_days)
+            .ok()
+            .and_then(|days| days.checked_mul(86400))
+            .ok_or(ErrorCode::ArithmeticError)?;
+        let new_unlock_time = target_user_stake.average_entry_time
+            .checked_add(target_pool.lock_duration.max(tier_lock_seconds))
+            .ok_or(ErrorCode::ArithmeticError)?;
+        target_user_stake.unlock_time = target_user_stake.unlock_time.max(new_unlock_time);
+        target_pool.total_staked = target_pool.total_staked.checked_add(received).ok_or(ErrorCode::ArithmeticError)?;
+        reweigh_position(target_pool, weight_before, target_user_stake)?;
+
+        // The target's deposit limits apply as if the user had staked
+        require!(
+            target_pool.min_stake_amount == 0 || target_user_stake.stake_amount >= target_pool.min_stake_amount,
+            ErrorCode::BelowMinimumStake
+        );
+        require!(
+            target_pool.max_stake_per_user == 0 || target_user_stake.stake_amount <= target_pool.max_stake_per_user,
+            ErrorCode::UserStakeCapExceeded
+        );
+        require!(
+            target_pool.max_total_staked == 0 || target_pool.total_staked <= target_pool.max_total_staked,
+            ErrorCode::PoolCapExceeded
+        );
+
+        msg!("Claimed {} reward tokens and staked {} into pool {}", total_reward, received, target_pool_id);
+        emit!(StakeEvent {
+            user: user_key,
+            amount: received,
+            stake_amount: target_user_stake.stake_amount,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_auto_compound(ctx: Context<SetAutoCompound>, _pool_id: u64, _position_id: u64, enabled: bool) -> Result<()> {
+        ctx.accounts.user_stake.auto_compound = enabled;
+        msg!("Set auto-compound to {} for {}", enabled, ctx.accounts.user.key());
+        Ok(())
+    }
+
+    /// Lets `delegate` stake into and claim for this position. `None` removes the delegate.
+    pub fn set_delegate(ctx: Context<SetDelegate>, _pool_id: u64, _position_id: u64, delegate: Option<Pubkey>) -> Result<()> {
+        require_not_blacklisted(&ctx.accounts.blacklist)?;
+        ctx.accounts.user_stake.delegate = delegate;
+        msg!("Set delegate to {:?} for {}", delegate, ctx.accounts.user.key());
+        Ok(())
+    }
+
+    pub fn set_reward_destination(
+        ctx: Context<SetRewardDestination>,
+        _pool_id: u64,
+        _position_id: u64,
+        destination: Option<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.user_stake.reward_destination = destination;
+        msg!("Set reward destination to {:?} for {}", destination, ctx.accounts.user.key());
+        Ok(())
+    }
+
+    /// Moves a position to `new_owner`, e.g. after a wallet change, without unstaking. The
+    /// stake, lock, and unsettled rewards carry over to the same position id.
+    pub fn transfer_position(ctx: Context<TransferPosition>, _pool_id: u64, _position_id: u64, new_owner: Pubkey) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &ctx.accounts.user_stake;
+        let new_user_stake = &mut ctx.accounts.new_user_stake;
+        let user = &ctx.accounts.user;
+
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require!(
+            !is_blacklisted(&ctx.accounts.blacklist) && !is_blacklisted(&ctx.accounts.new_owner_blacklist),
+            ErrorCode::Blacklisted
+        );
+        // The new owner must be able to stake here themselves, and a sold receipt stays with the position
+        if staking_pool.require_whitelist {
+            let whitelisted = ctx.accounts.new_owner_whitelist.as_ref().is_some_and(|entry| entry.active);
+            require!(whitelisted, ErrorCode::NotWhitelisted);
+        }
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &user.key())?;
+
+        // Never overwrite a position the new owner already holds at this id
+        require!(
+            new_owner != user.key() && new_user_stake.owner == Pubkey::default(),
+            ErrorCode::PositionExists
+        );
+
+        let new_user_account = &mut ctx.accounts.new_user_account;
+        require!(
+            staking_pool.max_positions_per_user == 0
+                || new_user_account.positions < staking_pool.max_positions_per_user,
+            ErrorCode::TooManyPositions
+        );
+        new_user_account.owner = new_owner;
+        new_user_account.positions = new_user_account.positions.checked_add(1).ok_or(ErrorCode::ArithmeticError)?;
+        let user_account = &mut ctx.accounts.user_account;
+        user_account.positions = user_account.positions.checked_sub(1).ok_or(ErrorCode::ArithmeticError)?;
+        leave_pool(staking_pool, &mut ctx.accounts.pool_member)?;
+        join_pool(staking_pool, &mut ctx.accounts.new_pool_member, new_owner)?;
+
+        // The old owner's delegate and reward destination don't follow the position
+        new_user_stake.set_inner(UserStake {
+            owner: new_owner,
+            delegate: None,
+            reward_destination: None,
+            ..(**user_stake).clone()
+        });
+
+        msg!("Transferred position {} from {} to {}", user_stake.position_id, user.key(), new_owner);
+        Ok(())
+    }
+
+    /// Mints the position's 1-of-1 receipt token to its owner. On pools with
+    /// `receipt_required`, unstaking, claiming and restaking need the signer to hold it, and
+    /// whoever holds it may claim the position's rewards.
+    pub fn mint_receipt(ctx: Context<MintReceipt>, pool_id: u64, _position_id: u64) -> Result<()> {
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let receipt_mint_info = ctx.accounts.receipt_mint.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let bump = ctx.accounts.staking_pool.bump;
+
+        // A transferred position keeps the receipt it already has
+        require!(
+            ctx.accounts.user_stake.receipt_mint == Pubkey::default(),
+            ErrorCode::ReceiptAlreadyMinted
+        );
+
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let cpi_accounts = MintTo {
+            mint: receipt_mint_info.clone(),
+            to: ctx.accounts.receipt_token_account.to_account_info(),
+            authority: staking_pool_info.clone(),
+        };
+        token_interface::mint_to(
+            CpiContext::new_with_signer(token_program_info.clone(), cpi_accounts, signer),
+            1
+        )?;
+
+        // Dropping the mint authority fixes the supply at one
+        let cpi_accounts = SetAuthority {
+            current_authority: staking_pool_info,
+            account_or_mint: receipt_mint_info,
+        };
+        token_interface::set_authority(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            AuthorityType::MintTokens,
+            None
+        )?;
+
+        ctx.accounts.user_stake.receipt_mint = ctx.accounts.receipt_mint.key();
+        msg!("Minted receipt {} for {}", ctx.accounts.receipt_mint.key(), ctx.accounts.user.key());
+        Ok(())
+    }
+
+    /// Compounds an opted-in position on its owner's behalf, paying the caller a tip.
+    pub fn keeper_compound(ctx: Context<KeeperCompound>, pool_id: u64, _position_id: u64) -> Result<()> {
+        // Get information before mutating staking_pool
+        let pool_reward_account_info = ctx.accounts.pool_reward_account.to_account_info();
+        let pool_stake_account_info = ctx.accounts.pool_stake_account.to_account_info();
+        let keeper_reward_account_info = ctx.accounts.keeper_reward_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let reward_mint_info = ctx.accounts.reward_mint.to_account_info();
+        let decimals = ctx.accounts.reward_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
+
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require_not_blacklisted(&ctx.accounts.blacklist)?;
+        // Keepers only compound while the owner still holds the receipt, so they never
+        // restake rewards that now belong to someone else
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &user_stake.owner)?;
+        require!(user_stake.auto_compound, ErrorCode::AutoCompoundDisabled);
+        require!(
+            staking_pool.stake_mint == staking_pool.reward_mint,
+            ErrorCode::CompoundMintMismatch
+        );
+
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        let mut total_reward = to_token_amount(user_stake.reward_debt)?;
+
+        // Keeper compounds count against the emission budget just like a claim
+        if staking_pool.max_total_rewards > 0 {
+            let remaining_budget = staking_pool.max_total_rewards.saturating_sub(staking_pool.total_rewards_distributed);
+            total_reward = total_reward.min(remaining_budget);
+        }
+
+        require!(total_reward > 0, ErrorCode::NoRewardsToClaim);
+        require!(
+            ctx.accounts.pool_reward_account.amount >= total_reward,
+            ErrorCode::InsufficientRewardFunds
+        );
+
+        // The keeper's tip comes out of the user's rewards; the rest is restaked
+        let tip = calculate_bps_share(total_reward, staking_pool.keeper_tip_bps)?;
+        let restaked = total_reward.checked_sub(tip).ok_or(ErrorCode::ArithmeticError)?;
+
+        user_stake.reward_debt -= total_reward as u128;
+        staking_pool.total_reward_debt = staking_pool.total_reward_debt.checked_sub(total_reward as u128).ok_or(ErrorCode::ArithmeticError)?;
+        let weight_before = weighted_stake(user_stake);
+        user_stake.average_entry_time = blended_entry_time(user_stake, restaked, clock.unix_timestamp)?;
+        user_stake.stake_amount = user_stake.stake_amount.checked_add(restaked).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.max_stake_reached = user_stake.max_stake_reached.max(user_stake.stake_amount);
+        user_stake.total_claimed = user_stake.total_claimed.checked_add(restaked).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+
+        staking_pool.total_staked = staking_pool.total_staked.checked_add(restaked).ok_or(ErrorCode::ArithmeticError)?;
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+        staking_pool.total_rewards_distributed = staking_pool.total_rewards_distributed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+
+        // Restaked rewards count toward the same caps as a deposit
+        require!(
+            staking_pool.max_stake_per_user == 0 || user_stake.stake_amount <= staking_pool.max_stake_per_user,
+            ErrorCode::UserStakeCapExceeded
+        );
+        require!(
+            staking_pool.max_total_staked == 0 || staking_pool.total_staked <= staking_pool.max_total_staked,
+            ErrorCode::PoolCapExceeded
+        );
+
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        // Move the restaked rewards into the stake vault so unstakes stay fully backed
+        let cpi_accounts = TransferChecked {
+            from: pool_reward_account_info.clone(),
+            mint: reward_mint_info.clone(),
+            to: pool_stake_account_info,
+            authority: staking_pool_info.clone(),
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info.clone(), cpi_accounts, signer),
+            restaked,
+            decimals
+        )?;
+
+        if tip > 0 {
+            let cpi_accounts = TransferChecked {
+                from: pool_reward_account_info,
+                mint: reward_mint_info,
+                to: keeper_reward_account_info,
+                authority: staking_pool_info,
+            };
+
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+                tip,
+                decimals
+            )?;
+        }
+
+        msg!("Keeper compounded {} reward tokens with a {} token tip", restaked, tip);
+        emit!(StakeEvent {
+            user: ctx.accounts.user_stake.owner,
+            amount: restaked,
+            stake_amount: ctx.accounts.user_stake.stake_amount,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>, pool_id: u64, position_id: u64) -> Result<()> {
+        claim_rewards_partial(ctx, pool_id, position_id, u64::MAX)
+    }
+
+    pub fn claim_rewards_partial(ctx: Context<ClaimRewards>, pool_id: u64, _position_id: u64, amount: u64) -> Result<()> {
+        // Get information before mutating staking_pool
+        let pool_reward_account_info = ctx.accounts.pool_reward_account.to_account_info();
+        let user_reward_account_info = ctx.accounts.user_reward_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let reward_mint_info = ctx.accounts.reward_mint.to_account_info();
+        let decimals = ctx.accounts.reward_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+        
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
+
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require_not_blacklisted(&ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &ctx.accounts.user.key())?;
+
+        // Only the claim is rate-limited; rewards keep accruing through the cooldown
+        require!(
+            claim_cooldown_over(staking_pool, user_stake.last_claim_time, clock.unix_timestamp)?,
+            ErrorCode::ClaimCooldownActive
+        );
+
+        // Settle pending rewards
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        require!(user_stake.reward_debt > 0, ErrorCode::NoRewardsToClaim);
+
+        // `amount` is a u64, so this fits even when the whole debt doesn't
+        let mut total_reward = to_token_amount((amount as u128).min(user_stake.reward_debt))?;
+
+        // Pay out no more than what is left of the emission budget; the rest stays owed
+        if staking_pool.max_total_rewards > 0 {
+            let remaining_budget = staking_pool.max_total_rewards.saturating_sub(staking_pool.total_rewards_distributed);
+            total_reward = total_reward.min(remaining_budget);
+        }
+        
+        require!(total_reward > 0, ErrorCode::NoRewardsToClaim);
+
+        // Fail cleanly instead of deep inside the token CPI when the pool is underfunded
+        require!(
+            ctx.accounts.pool_reward_account.amount >= total_reward,
+            ErrorCode::InsufficientRewardFunds
+        );
+        
+        // Only the paid amount leaves the debt; whatever wasn't asked for or fit the budget stays owed
+        user_stake.reward_debt -= total_reward as u128;
+        staking_pool.total_reward_debt = staking_pool.total_reward_debt.checked_sub(total_reward as u128).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.total_claimed = user_stake.total_claimed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+        user_stake.last_claim_time = clock.unix_timestamp;
+
+        staking_pool.total_rewards_distributed = staking_pool.total_rewards_distributed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+        
+        // Transfer reward tokens from pool to user
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+        
+        let cpi_accounts = TransferChecked {
+            from: pool_reward_account_info,
+            mint: reward_mint_info,
+            to: user_reward_account_info,
+            authority: staking_pool_info,
+        };
+        
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            total_reward,
+            decimals
+        )?;
+
+        msg!("Claimed {} reward tokens", total_reward);
+        emit!(ClaimEvent {
+            user: ctx.accounts.user_stake.owner,
+            amount: total_reward,
+            stake_amount: ctx.accounts.user_stake.stake_amount,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    /// Pays out the position's rewards from the pool's second reward stream.
+    pub fn claim_rewards_2(ctx: Context<ClaimRewards2>, pool_id: u64, _position_id: u64) -> Result<()> {
+        // Get information before mutating staking_pool
+        let pool_reward_account_info = ctx.accounts.pool_reward_account_2.to_account_info();
+        let user_reward_account_info = ctx.accounts.user_reward_account_2.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let reward_mint_info = ctx.accounts.reward_mint_2.to_account_info();
+        let decimals = ctx.accounts.reward_mint_2.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
+
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require_not_blacklisted(&ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &ctx.accounts.user.key())?;
+        require!(
+            staking_pool.reward_mint_2 != Pubkey::default(),
+            ErrorCode::SecondRewardNotConfigured
+        );
+        require!(
+            claim_cooldown_over(staking_pool, user_stake.last_claim_time_2, clock.unix_timestamp)?,
+            ErrorCode::ClaimCooldownActive
+        );
+
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        let total_reward = to_token_amount(user_stake.reward_debt_2)?;
+        require!(total_reward > 0, ErrorCode::NoRewardsToClaim);
+        require!(
+            ctx.accounts.pool_reward_account_2.amount >= total_reward,
+            ErrorCode::InsufficientRewardFunds
+        );
+
+        user_stake.reward_debt_2 = 0;
+        user_stake.last_claim_time_2 = clock.unix_timestamp;
+        staking_pool.total_reward_debt_2 = staking_pool.total_reward_debt_2.checked_sub(total_reward as u128).ok_or(ErrorCode::ArithmeticError)?;
+
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: pool_reward_account_info,
+            mint: reward_mint_info,
+            to: user_reward_account_info,
+            authority: staking_pool_info,
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            total_reward,
+            decimals
+        )?;
+
+        msg!("Claimed {} second-stream reward tokens", total_reward);
+        emit!(ClaimEvent {
+            user: ctx.accounts.user_stake.owner,
+            amount: total_reward,
+            stake_amount: ctx.accounts.user_stake.stake_amount,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    /// Claims every position passed in `remaining_accounts` with a single transfer.
+    pub fn claim_all<'info>(ctx: Context<'_, '_, 'info, 'info, ClaimAll<'info>>, pool_id: u64) -> Result<()> {
+        // Get information before mutating staking_pool
+        let pool_reward_account_info = ctx.accounts.pool_reward_account.to_account_info();
+        let user_reward_account_info = ctx.accounts.user_reward_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let reward_mint_info = ctx.accounts.reward_mint.to_account_info();
+        let decimals = ctx.accounts.reward_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+        let staking_pool_key = ctx.accounts.staking_pool.key();
+        let user_key = ctx.accounts.user.key();
+        let reward_account_owner = ctx.accounts.user_reward_account.owner;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let clock = Clock::get()?;
+
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require_not_blacklisted(&ctx.accounts.blacklist)?;
+
+        update_pool(staking_pool, clock.unix_timestamp)?;
+
+        // On pools that require receipts, each position is followed by the signer's account holding its receipt
+        let stride = if staking_pool.receipt_required { 2 } else { 1 };
+        require!(ctx.remaining_accounts.len() % stride == 0, ErrorCode::InvalidPositionAccount);
+
+        // Settle each position, rejecting anything that isn't one of the signer's positions in this pool
+        let mut positions: Vec<Account<'info, UserStake>> = Vec::with_capacity(ctx.remaining_accounts.len() / stride);
+        let mut total_debt: u128 = 0;
+        let mut total_stake: u64 = 0;
+        for accounts in ctx.remaining_accounts.chunks(stride) {
+            let account_info = &accounts[0];
+            require!(
+                account_info.is_writable && !positions.iter().any(|position| position.key() == account_info.key()),
+                ErrorCode::InvalidPositionAccount
+            );
+
+            let mut user_stake = Account::<UserStake>::try_from(account_info)?;
+            require!(user_stake.owner == user_key, ErrorCode::Unauthorized);
+            let receipt_token_account = accounts.get(1).map(InterfaceAccount::<TokenAccount>::try_from).transpose()?;
+            require_receipt(staking_pool, &user_stake, &receipt_token_account, &user_key)?;
+            require!(reward_destination(&user_stake) == reward_account_owner, ErrorCode::RewardDestinationMismatch);
+            require!(
+                claim_cooldown_over(staking_pool, user_stake.last_claim_time, clock.unix_timestamp)?,
+                ErrorCode::ClaimCooldownActive
+            );
+
+            let (expected_key, _) = Pubkey::find_program_address(
+                &[
+                    b"user-stake",
+                    staking_pool_key.as_ref(),
+                    user_key.as_ref(),
+                    user_stake.position_id.to_le_bytes().as_ref(),
+                ],
+                ctx.program_id,
+            );
+            require!(expected_key == account_info.key(), ErrorCode::InvalidPositionAccount);
+
+            settle_rewards(staking_pool, &mut user_stake)?;
+            total_debt = total_debt.checked_add(user_stake.reward_debt).ok_or(ErrorCode::ArithmeticError)?;
+            total_stake = total_stake.checked_add(user_stake.stake_amount).ok_or(ErrorCode::ArithmeticError)?;
+            positions.push(user_stake);
+        }
+
+        require!(total_debt > 0, ErrorCode::NoRewardsToClaim);
+
+        // Pay out no more than what is left of the emission budget; the rest stays owed
+        let mut total_reward = to_token_amount(total_debt)?;
+        if staking_pool.max_total_rewards > 0 {
+            let remaining_budget = staking_pool.max_total_rewards.saturating_sub(staking_pool.total_rewards_distributed);
+            total_reward = total_reward.min(remaining_budget);
+        }
+
+        require!(total_reward > 0, ErrorCode::NoRewardsToClaim);
+
+        // Fail cleanly instead of deep inside the token CPI when the pool is underfunded
+        require!(
+            ctx.accounts.pool_reward_account.amount >= total_reward,
+            ErrorCode::InsufficientRewardFunds
+        );
+
+        // The payout is credited in order; positions it doesn't reach keep their debt
+        let mut unallocated = total_reward;
+        for user_stake in positions.iter_mut() {
+            let paid = to_token_amount(user_stake.reward_debt.min(unallocated as u128))?;
+            unallocated -= paid;
+            user_stake.total_claimed = user_stake.total_claimed.checked_add(paid).ok_or(ErrorCode::ArithmeticError)?;
+            user_stake.reward_debt -= paid as u128;
+            user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+            user_stake.last_claim_time = clock.unix_timestamp;
+            user_stake.exit(ctx.program_id)?;
+        }
+        staking_pool.total_reward_debt = staking_pool.total_reward_debt.checked_sub(total_reward as u128).ok_or(ErrorCode::ArithmeticError)?;
+        staking_pool.total_rewards_distributed = staking_pool.total_rewards_distributed.checked_add(total_reward).ok_or(ErrorCode::ArithmeticError)?;
+
+        // Transfer reward tokens from pool to user
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: pool_reward_account_info,
+            mint: reward_mint_info,
+            to: user_reward_account_info,
+            authority: staking_pool_info,
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            total_reward,
+            decimals
+        )?;
+
+        msg!("Claimed {} reward tokens across {} positions", total_reward, positions.len());
+        emit!(ClaimEvent {
+            user: user_key,
+            amount: total_reward,
+            stake_amount: total_stake,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn fund_rewards(ctx: Context<FundRewards>, _pool_id: u64, amount: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+
+        // Transfer reward tokens from funder to pool
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.pool_reward_account.to_account_info(),
+            authority: ctx.accounts.funder.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+
+        staking_pool.total_rewards_funded = staking_pool.total_rewards_funded.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+
+        msg!("Funded {} reward tokens", amount);
+        Ok(())
+    }
+
+    /// Drops `amount` reward tokens on everyone currently staked, in proportion to their stake.
+    pub fn distribute(ctx: Context<Distribute>, _pool_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::ZeroAmount);
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+        let clock = Clock::get()?;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(staking_pool.total_weighted_stake > 0, ErrorCode::NoStakers);
+
+        // Bring continuous accrual up to date so the drop is a clean step on top of it
+        update_pool(staking_pool, clock.unix_timestamp)?;
+
+        let vault_balance_before = ctx.accounts.pool_reward_account.amount;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.admin_token_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.pool_reward_account.to_account_info(),
+            authority: admin.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+
+        // Transfer-fee mints deliver less than `amount`, so only share out what arrived
+        ctx.accounts.pool_reward_account.reload()?;
+        let received = ctx.accounts.pool_reward_account.amount
+            .checked_sub(vault_balance_before)
+            .ok_or(ErrorCode::ArithmeticError)?;
+
+        // Positions settle with their multiplier applied, so split by weighted stake; each
+        // one then earns received * weight / total_weighted_stake
+        let rate_denominator = rate_denominator(staking_pool)?;
+        let increment = (received as u128)
+            .checked_mul(REWARD_PRECISION * BPS_DENOMINATOR as u128)
+            .and_then(|n| n.checked_mul(rate_denominator))
+            .ok_or(ErrorCode::ArithmeticError)?
+            / staking_pool.total_weighted_stake;
+
+        // Every tier shares the drop equally
+        staking_pool.acc_reward_per_share = staking_pool.acc_reward_per_share.checked_add(increment).ok_or(ErrorCode::ArithmeticError)?;
+        for acc_reward_per_share in staking_pool.tier_acc_reward_per_share.iter_mut() {
+            *acc_reward_per_share = acc_reward_per_share.checked_add(increment).ok_or(ErrorCode::ArithmeticError)?;
+        }
+        staking_pool.total_rewards_funded = staking_pool.total_rewards_funded.checked_add(received).ok_or(ErrorCode::ArithmeticError)?;
+
+        msg!("Distributed {} reward tokens across {} staked", received, staking_pool.total_staked);
+        Ok(())
+    }
+
+    /// Credits many existing positions from one admin transfer, e.g. for an airdrop of
+    /// pre-staked balances. `amounts[i]` goes to the position in `remaining_accounts[i]`.
+    pub fn seed_stake<'info>(ctx: Context<'_, '_, 'info, 'info, SeedStake<'info>>, _pool_id: u64, amounts: Vec<u64>) -> Result<()> {
+        let staking_pool_key = ctx.accounts.staking_pool.key();
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+        let clock = Clock::get()?;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require!(
+            !amounts.is_empty() && amounts.len() == ctx.remaining_accounts.len(),
+            ErrorCode::InvalidPositionAccount
+        );
+        require!(amounts.iter().all(|amount| *amount > 0), ErrorCode::ZeroAmount);
+
+        let total = amounts
+            .iter()
+            .try_fold(0u64, |total, amount| total.checked_add(*amount))
+            .ok_or(ErrorCode::ArithmeticError)?;
+
+        let vault_balance_before = ctx.accounts.pool_stake_account.amount;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.admin_token_account.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+            to: ctx.accounts.pool_stake_account.to_account_info(),
+            authority: admin.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        token_interface::transfer_checked(cpi_ctx, total, ctx.accounts.stake_mint.decimals)?;
+
+        // Every seeded token must be backed, so a transfer fee that shorts the vault fails the batch
+        ctx.accounts.pool_stake_account.reload()?;
+        let received = ctx.accounts.pool_stake_account.amount
+            .checked_sub(vault_balance_before)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        require!(received == total, ErrorCode::SeedAmountMismatch);
+
+        // Settle against the rate so far, so seeded stake only earns from now on
+        update_pool(staking_pool, clock.unix_timestamp)?;
+
+        let mut seeded: Vec<Pubkey> = Vec::with_capacity(amounts.len());
+        for (account_info, amount) in ctx.remaining_accounts.iter().zip(amounts.iter().copied()) {
+            require!(
+                account_info.is_writable && !seeded.contains(account_info.key),
+                ErrorCode::InvalidPositionAccount
+            );
+
+            let mut user_stake = Account::<UserStake>::try_from(account_info)?;
+            let (expected_key, _) = Pubkey::find_program_address(
+                &[
+                    b"user-stake",
+                    staking_pool_key.as_ref(),
+                    user_stake.owner.as_ref(),
+                    user_stake.position_id.to_le_bytes().as_ref(),
+                ],
+                ctx.program_id,
+            );
+            require!(expected_key == account_info.key(), ErrorCode::InvalidPositionAccount);
+
+            settle_rewards(staking_pool, &mut user_stake)?;
+
+            // Seeded tokens enter like a deposit, under the position's current tier lock
+            let weight_before = weighted_stake(&user_stake);
+            user_stake.average_entry_time = blended_entry_time(&user_stake, amount, clock.unix_timestamp)?;
+            user_stake.stake_amount = user_stake.stake_amount.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+            user_stake.max_stake_reached = user_stake.max_stake_reached.max(user_stake.stake_amount);
+            user_stake.last_stake_time = clock.unix_timestamp.max(user_stake.last_stake_time);
+            user_stake.last_stake_slot = clock.slot;
+
+            let tier_lock_seconds = i64::try_from(staking_pool.lock_tiers[user_stake.reward_tier as usize].lock_days)
+                .ok()
+                .and_then(|days| days.checked_mul(86400))
+                .ok_or(ErrorCode::ArithmeticError)?;
+            let new_unlock_time = user_stake.average_entry_time
+                .checked_add(staking_pool.lock_duration.max(tier_lock_seconds))
+                .ok_or(ErrorCode::ArithmeticError)?;
+            user_stake.unlock_time = user_stake.unlock_time.max(new_unlock_time);
+            reweigh_position(staking_pool, weight_before, &user_stake)?;
+
+            user_stake.exit(ctx.program_id)?;
+            seeded.push(account_info.key());
+
+            emit!(StakeEvent {
+                user: user_stake.owner,
+                amount,
+                stake_amount: user_stake.stake_amount,
+                timestamp: clock.unix_timestamp,
+            });
+        }
+
+        staking_pool.total_staked = staking_pool.total_staked.checked_add(total).ok_or(ErrorCode::ArithmeticError)?;
+
+        msg!("Seeded {} tokens across {} positions", total, seeded.len());
+        Ok(())
+    }
+
+    pub fn withdraw_surplus_rewards(ctx: Context<WithdrawSurplusRewards>, pool_id: u64, amount: u64) -> Result<()> {
+        let staking_pool = &ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        // Settled-but-unclaimed rewards stay behind for their owners
+        // Never more than the vault balance, so narrowing back to u64 can't truncate
+        let surplus = (ctx.accounts.pool_reward_account.amount as u128).saturating_sub(staking_pool.total_reward_debt) as u64;
+        require!(amount <= surplus, ErrorCode::InsufficientRewardFunds);
+
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[staking_pool.bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.pool_reward_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.admin_reward_account.to_account_info(),
+            authority: ctx.accounts.staking_pool.to_account_info(),
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer),
+            amount,
+            ctx.accounts.reward_mint.decimals
+        )?;
+
+        msg!("Withdrew {} surplus reward tokens", amount);
+        Ok(())
+    }
+
+    /// Sends stake tokens the pool doesn't owe anyone, such as forfeited early-unstake
+    /// penalties, from `pool_stake_account` to the admin.
+    pub fn sweep_dust(ctx: Context<SweepDust>, pool_id: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        // Active stake and tokens waiting out their cooldown both stay behind
+        let owed = staking_pool.total_staked
+            .checked_add(staking_pool.total_pending_unstake)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        let dust = ctx.accounts.pool_stake_account.amount.saturating_sub(owed);
+        require!(dust > 0, ErrorCode::NoDustToSweep);
+
+        // Forfeited penalties leave the vault with the sweep
+        staking_pool.total_forfeited = 0;
+
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[staking_pool.bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.pool_stake_account.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+            to: ctx.accounts.admin_token_account.to_account_info(),
+            authority: ctx.accounts.staking_pool.to_account_info(),
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer),
+            dust,
+            ctx.accounts.stake_mint.decimals
+        )?;
+
+        msg!("Swept {} stake tokens of dust", dust);
+        Ok(())
+    }
+
+    /// Points the pool at a new reward mint and vault. Only allowed while no settled
+    /// rewards are owed, since those would otherwise be paid in the new mint.
+    pub fn set_reward_mint(ctx: Context<SetRewardMint>, _pool_id: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        require!(staking_pool.total_reward_debt == 0, ErrorCode::OutstandingRewards);
+        require!(
+            staking_pool.reward_equals_stake || ctx.accounts.new_reward_mint.key() != staking_pool.stake_mint,
+            ErrorCode::MintCollision
+        );
+
+        staking_pool.reward_mint = ctx.accounts.new_reward_mint.key();
+        staking_pool.reward_decimals = ctx.accounts.new_reward_mint.decimals;
+        staking_pool.pool_reward_account = ctx.accounts.new_pool_reward_account.key();
+
+        msg!("Set reward mint to {}", staking_pool.reward_mint);
+        Ok(())
+    }
+
+    pub fn slash(ctx: Context<Slash>, pool_id: u64, _position_id: u64, amount: u64) -> Result<()> {
+        // Get information before mutating staking_pool
+        let pool_stake_account_info = ctx.accounts.pool_stake_account.to_account_info();
+        let treasury_token_account_info = ctx.accounts.treasury_token_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let stake_mint_info = ctx.accounts.stake_mint.to_account_info();
+        let decimals = ctx.accounts.stake_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let admin = &ctx.accounts.admin;
+        let clock = Clock::get()?;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        // Tokens waiting out the unstake cooldown can still be slashed, so requesting an
+        // unstake doesn't put them out of reach
+        let slashable = user_stake.stake_amount
+            .checked_add(user_stake.pending_unstake)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        require!(slashable >= amount, ErrorCode::InsufficientStakeAmount);
+
+        // Rewards earned before the slash stay with the user
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        // Active stake goes first, then whatever is pending
+        let from_stake = amount.min(user_stake.stake_amount);
+        let from_pending = amount - from_stake;
+
+        let weight_before = weighted_stake(user_stake);
+        user_stake.stake_amount = user_stake.stake_amount.checked_sub(from_stake).ok_or(ErrorCode::ArithmeticError)?;
+        staking_pool.total_staked = staking_pool.total_staked.checked_sub(from_stake).ok_or(ErrorCode::ArithmeticError)?;
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+
+        user_stake.pending_unstake = user_stake.pending_unstake.checked_sub(from_pending).ok_or(ErrorCode::ArithmeticError)?;
+        staking_pool.total_pending_unstake = staking_pool.total_pending_unstake.checked_sub(from_pending).ok_or(ErrorCode::ArithmeticError)?;
+
+        // Transfer slashed tokens from pool to treasury
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: pool_stake_account_info,
+            mint: stake_mint_info,
+            to: treasury_token_account_info,
+            authority: staking_pool_info,
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            amount,
+            decimals
+        )?;
+
+        msg!("Slashed {} tokens from {}", amount, ctx.accounts.user_stake.owner);
+        emit!(SlashEvent {
+            user: ctx.accounts.user_stake.owner,
+            amount,
+            stake_amount: ctx.accounts.user_stake.stake_amount,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    /// Moves a position's unlock time, e.g. to undo a mistaken lock (admin only). Locks can
+    /// only be shortened unless the pool has `admin_can_extend_lock` set.
+    pub fn adjust_lock(ctx: Context<AdjustLock>, _pool_id: u64, _position_id: u64, new_unlock_time: i64) -> Result<()> {
+        let staking_pool = &ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let admin = &ctx.accounts.admin;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(new_unlock_time >= now, ErrorCode::InvalidUnlockTime);
+        require!(
+            staking_pool.admin_can_extend_lock || new_unlock_time <= user_stake.unlock_time,
+            ErrorCode::LockExtensionNotAllowed
+        );
+
+        let old_unlock_time = user_stake.unlock_time;
+        user_stake.unlock_time = new_unlock_time;
+
+        msg!("Adjusted unlock time for {} from {} to {}", user_stake.owner, old_unlock_time, new_unlock_time);
+        emit!(LockAdjusted {
+            user: user_stake.owner,
+            position_id: user_stake.position_id,
+            old_unlock_time,
+            new_unlock_time,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    /// Resets `total_staked` to what `pool_stake_account` actually backs, after setting
+    /// aside forfeited penalties and tokens waiting out their cooldown. Only the unweighted
+    /// total is corrected: `total_weighted_stake` is the sum of the positions' own weights,
+    /// and tokens sent straight to the vault belong to no position.
+    pub fn reconcile_total_staked(ctx: Context<ReconcileTotalStaked>, _pool_id: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        // Accrual and distribute split by total_weighted_stake, which stays as it is, so
+        // there's nothing to settle first
+        let backed = ctx.accounts.pool_stake_account.amount
+            .checked_sub(staking_pool.total_forfeited)
+            .and_then(|amount| amount.checked_sub(staking_pool.total_pending_unstake))
+            .ok_or(ErrorCode::ArithmeticError)?;
+
+        let delta = backed as i128 - staking_pool.total_staked as i128;
+        staking_pool.total_staked = backed;
+
+        msg!("Reconciled total_staked to {} (delta {})", backed, delta);
+        Ok(())
+    }
+
+    pub fn propose_reward_rate(ctx: Context<ProposeRewardRate>, _pool_id: u64, new_rate: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            staking_pool.max_reward_rate == 0 || new_rate <= staking_pool.max_reward_rate,
+            ErrorCode::RewardRateTooHigh
+        );
+
+        // A new proposal replaces any earlier one and restarts the timelock
+        let effective_time = Clock::get()?
+            .unix_timestamp
+            .checked_add(staking_pool.rate_timelock)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        staking_pool.pending_reward_rate = new_rate;
+        staking_pool.pending_rate_effective_time = effective_time;
+        msg!("Proposed reward rate {} effective at {}", new_rate, effective_time);
+        Ok(())
+    }
+
+    /// Caps how much time a single pool update may accrue over. Accrual up to now uses
+    /// the old cap, so the new one only applies to future idle stretches.
+    pub fn set_max_accrual_seconds(ctx: Context<SetMaxAccrualSeconds>, _pool_id: u64, max_accrual_seconds: i64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            max_accrual_seconds > 0 && max_accrual_seconds <= MAX_ACCRUAL_SECONDS,
+            ErrorCode::InvalidMaxAccrualSeconds
+        );
+
+        update_pool(staking_pool, Clock::get()?.unix_timestamp)?;
+
+        staking_pool.max_accrual_seconds = max_accrual_seconds;
+        msg!("Set max accrual window to {} seconds", max_accrual_seconds);
+        Ok(())
+    }
+
+    pub fn apply_reward_rate(ctx: Context<ApplyRewardRate>, _pool_id: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            staking_pool.pending_rate_effective_time != 0,
+            ErrorCode::NoPendingRewardRate
+        );
+        require!(
+            now >= staking_pool.pending_rate_effective_time,
+            ErrorCode::TimelockNotElapsed
+        );
+
+        // Opted-in pools must hold at least a day of rewards at the new rate before it applies
+        if staking_pool.require_solvency_on_rate_change {
+            let liability = daily_liability(staking_pool, staking_pool.pending_reward_rate)?;
+            require!(
+                ctx.accounts.pool_reward_account.amount as u128 >= liability,
+                ErrorCode::InsufficientRewardFunds
+            );
+        }
+
+        // Accrue everything earned at the old rate so the new one only applies going forward
+        update_pool(staking_pool, now)?;
+
+        // A flat rate replaces any decaying schedule
+        let new_rate = staking_pool.pending_reward_rate;
+        staking_pool.reward_rate = new_rate;
+        staking_pool.rate_schedule_enabled = false;
+        staking_pool.pending_reward_rate = 0;
+        staking_pool.pending_rate_effective_time = 0;
+        msg!("Updated reward rate to {}", new_rate);
+        Ok(())
+    }
+
+    /// Toggles whether `apply_reward_rate` needs a day of funding at the new rate first.
+    pub fn set_require_solvency_on_rate_change(ctx: Context<SetRequireSolvencyOnRateChange>, _pool_id: u64, required: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.require_solvency_on_rate_change = required;
+        msg!("Solvency required on rate change: {}", required);
+        Ok(())
+    }
+
+    /// Sets the flat rate positions in lock tier `tier` earn; 0 falls back to the base rate.
+    pub fn set_tier_rate(ctx: Context<SetTierRate>, _pool_id: u64, tier: u8, rate: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!((tier as usize) < LOCK_TIER_COUNT, ErrorCode::InvalidTier);
+        require!(
+            staking_pool.max_reward_rate == 0 || rate <= staking_pool.max_reward_rate,
+            ErrorCode::RewardRateTooHigh
+        );
+
+        // Accrue everything earned at the old rate so the new one only applies going forward
+        update_pool(staking_pool, now)?;
+
+        staking_pool.reward_rates[tier as usize] = rate;
+        msg!("Set tier {} reward rate to {}", tier, rate);
+        Ok(())
+    }
+
+    pub fn set_rate_schedule(
+        ctx: Context<SetRateSchedule>,
+        _pool_id: u64,
+        initial_rate: u64,
+        decay_bps_per_period: u16,
+        period_seconds: i64,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            decay_bps_per_period <= BPS_DENOMINATOR as u16 && period_seconds >= MIN_DECAY_PERIOD_SECONDS,
+            ErrorCode::InvalidRateSchedule
+        );
+        // The schedule only decays, so bounding its starting rate bounds all of it
+        require!(
+            staking_pool.max_reward_rate == 0 || initial_rate <= staking_pool.max_reward_rate,
+            ErrorCode::RewardRateTooHigh
+        );
+
+        // Accrue everything earned under the old rate before the schedule starts
+        let now = Clock::get()?.unix_timestamp;
+        update_pool(staking_pool, now)?;
+
+        staking_pool.rate_schedule_enabled = true;
+        staking_pool.initial_rate = initial_rate;
+        staking_pool.decay_bps_per_period = decay_bps_per_period;
+        staking_pool.period_seconds = period_seconds;
+        staking_pool.schedule_start = staking_pool.last_update_time;
+
+        msg!(
+            "Rate schedule: {} decaying {} bps every {} seconds",
+            initial_rate,
+            decay_bps_per_period,
+            period_seconds
+        );
+        Ok(())
+    }
+
+    pub fn set_min_stake(ctx: Context<SetMinStake>, _pool_id: u64, amount: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.min_stake_amount = amount;
+        msg!("Updated minimum stake to {}", amount);
+        Ok(())
+    }
+
+    pub fn set_max_total_staked(ctx: Context<SetMaxTotalStaked>, _pool_id: u64, amount: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.max_total_staked = amount;
+        msg!("Updated total stake cap to {}", amount);
+        Ok(())
+    }
+
+    pub fn set_max_stake_per_user(ctx: Context<SetMaxStakePerUser>, _pool_id: u64, amount: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.max_stake_per_user = amount;
+        msg!("Updated per-user stake cap to {}", amount);
+        Ok(())
+    }
+
+    pub fn set_max_single_stake(ctx: Context<SetMaxSingleStake>, _pool_id: u64, amount: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.max_single_stake = amount;
+        msg!("Updated single-stake limit to {}", amount);
+        Ok(())
+    }
+
+    pub fn set_max_positions_per_user(ctx: Context<SetMaxPositionsPerUser>, _pool_id: u64, max_positions: u16) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.max_positions_per_user = max_positions;
+        msg!("Updated per-wallet position cap to {}", max_positions);
+        Ok(())
+    }
+
+    pub fn set_require_whitelist(ctx: Context<SetRequireWhitelist>, _pool_id: u64, required: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.require_whitelist = required;
+        msg!("Whitelist required: {}", required);
+        Ok(())
+    }
+
+    pub fn set_referral_bps(ctx: Context<SetReferralBps>, _pool_id: u64, referral_bps: u16) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            referral_bps <= BPS_DENOMINATOR as u16,
+            ErrorCode::InvalidReferralBps
+        );
+
+        staking_pool.referral_bps = referral_bps;
+        msg!("Updated referral reward to {} bps", referral_bps);
+        Ok(())
+    }
+
+    pub fn set_fees(
+        ctx: Context<SetFees>,
+        _pool_id: u64,
+        stake_fee_bps: u16,
+        unstake_fee_bps: u16,
+        fee_recipient: Pubkey,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            stake_fee_bps <= MAX_FEE_BPS && unstake_fee_bps <= MAX_FEE_BPS,
+            ErrorCode::FeeTooHigh
+        );
+
+        staking_pool.stake_fee_bps = stake_fee_bps;
+        staking_pool.unstake_fee_bps = unstake_fee_bps;
+        staking_pool.fee_recipient = fee_recipient;
+        msg!(
+            "Set stake fee {} bps, unstake fee {} bps, recipient {}",
+            stake_fee_bps,
+            unstake_fee_bps,
+            fee_recipient
+        );
+        Ok(())
+    }
+
+    pub fn set_nft_boost(ctx: Context<SetNftBoost>, _pool_id: u64, nft_collection: Pubkey, nft_boost_bps: u16) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(nft_boost_bps <= BPS_DENOMINATOR as u16, ErrorCode::InvalidNftBoost);
+
+        staking_pool.nft_collection = nft_collection;
+        staking_pool.nft_boost_bps = nft_boost_bps;
+        msg!("Set NFT boost of {} bps for collection {}", nft_boost_bps, nft_collection);
+        Ok(())
+    }
+
+    /// Returns the position's escrowed NFT to its owner and drops the NFT boost.
+    pub fn withdraw_nft_boost(ctx: Context<WithdrawNftBoost>, pool_id: u64, _position_id: u64) -> Result<()> {
+        // Get information before mutating staking_pool
+        let nft_escrow_account_info = ctx.accounts.nft_escrow_account.to_account_info();
+        let user_nft_account_info = ctx.accounts.user_nft_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let nft_mint_info = ctx.accounts.nft_mint.to_account_info();
+        let decimals = ctx.accounts.nft_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
+
+        require_may_unstake(staking_pool, &ctx.accounts.blacklist)?;
+        require_receipt(staking_pool, user_stake, &ctx.accounts.receipt_token_account, &ctx.accounts.user.key())?;
+        require!(user_stake.nft_mint != Pubkey::default(), ErrorCode::InvalidNftBoost);
+
+        // Rewards up to now still earn the boost
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        let weight_before = weighted_stake(user_stake);
+        user_stake.nft_mint = Pubkey::default();
+        user_stake.boost_bps = 0;
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: nft_escrow_account_info,
+            mint: nft_mint_info,
+            to: user_nft_account_info,
+            authority: staking_pool_info,
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            1,
+            decimals,
+        )?;
+
+        msg!("Returned NFT {} to {}", ctx.accounts.nft_mint.key(), ctx.accounts.user.key());
+        Ok(())
+    }
+
+    pub fn set_boost_config(
+        ctx: Context<SetBoostConfig>,
+        _pool_id: u64,
+        boost_tokens_per_bps: u64,
+        max_boost_bps: u16,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(max_boost_bps <= BPS_DENOMINATOR as u16, ErrorCode::InvalidBoostConfig);
+        // The boost vault is tied to its mint, so the mint can't change once chosen
+        require!(
+            staking_pool.boost_mint == Pubkey::default() || staking_pool.boost_mint == ctx.accounts.boost_mint.key(),
+            ErrorCode::InvalidBoostConfig
+        );
+
+        staking_pool.boost_mint = ctx.accounts.boost_mint.key();
+        staking_pool.pool_boost_account = ctx.accounts.pool_boost_account.key();
+        staking_pool.boost_tokens_per_bps = boost_tokens_per_bps;
+        staking_pool.max_boost_bps = max_boost_bps;
+        msg!(
+            "Set governance boost of 1 bps per {} tokens, up to {} bps",
+            boost_tokens_per_bps,
+            max_boost_bps
+        );
+        Ok(())
+    }
+
+    pub fn boost_lock(ctx: Context<BoostLock>, _pool_id: u64, _position_id: u64, amount: u64, duration: i64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
+
+        require!(!staking_pool.paused, ErrorCode::PoolPaused);
+        require!(
+            staking_pool.boost_mint != Pubkey::default() && staking_pool.boost_tokens_per_bps > 0,
+            ErrorCode::InvalidBoostConfig
+        );
+        require!(amount > 0, ErrorCode::ZeroAmount);
+        require!(duration > 0, ErrorCode::InvalidBoostConfig);
+
+        // Rewards up to now accrue at the old multiplier
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.user_boost_account.to_account_info(),
+            mint: ctx.accounts.boost_mint.to_account_info(),
+            to: ctx.accounts.pool_boost_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.boost_mint.decimals)?;
+
+        // Adding to a lock never shortens it
+        let unlock = clock.unix_timestamp.checked_add(duration).ok_or(ErrorCode::ArithmeticError)?;
+        let weight_before = weighted_stake(user_stake);
+        user_stake.boost_amount = user_stake.boost_amount.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+        user_stake.boost_unlock = user_stake.boost_unlock.max(unlock);
+        user_stake.governance_boost_bps = governance_boost_bps(staking_pool, user_stake.boost_amount);
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+        staking_pool.total_boost_locked = staking_pool.total_boost_locked.checked_add(amount).ok_or(ErrorCode::ArithmeticError)?;
+
+        msg!(
+            "Locked {} boost tokens until {} for a {} bps boost",
+            amount,
+            user_stake.boost_unlock,
+            user_stake.governance_boost_bps
+        );
+        Ok(())
+    }
+
+    pub fn boost_unlock_withdraw(ctx: Context<BoostUnlockWithdraw>, pool_id: u64, _position_id: u64) -> Result<()> {
+        // Get information before mutating staking_pool
+        let pool_boost_account_info = ctx.accounts.pool_boost_account.to_account_info();
+        let user_boost_account_info = ctx.accounts.user_boost_account.to_account_info();
+        let staking_pool_info = ctx.accounts.staking_pool.to_account_info();
+        let token_program_info = ctx.accounts.token_program.to_account_info();
+        let boost_mint_info = ctx.accounts.boost_mint.to_account_info();
+        let decimals = ctx.accounts.boost_mint.decimals;
+        let bump = ctx.accounts.staking_pool.bump;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let clock = Clock::get()?;
+
+        require_may_unstake(staking_pool, &ctx.accounts.blacklist)?;
+        let amount = user_stake.boost_amount;
+        require!(amount > 0, ErrorCode::ZeroAmount);
+        require!(clock.unix_timestamp >= user_stake.boost_unlock, ErrorCode::BoostLocked);
+
+        // Rewards up to now still earn the boost
+        update_pool(staking_pool, clock.unix_timestamp)?;
+        settle_rewards(staking_pool, user_stake)?;
+
+        let weight_before = weighted_stake(user_stake);
+        user_stake.boost_amount = 0;
+        user_stake.boost_unlock = 0;
+        user_stake.governance_boost_bps = 0;
+        reweigh_position(staking_pool, weight_before, user_stake)?;
+        staking_pool.total_boost_locked = staking_pool.total_boost_locked.checked_sub(amount).ok_or(ErrorCode::ArithmeticError)?;
+
+        let pool_id_bytes = pool_id.to_le_bytes();
+        let pool_signer_seeds = &[
+            b"staking_pool".as_ref(),
+            pool_id_bytes.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&pool_signer_seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: pool_boost_account_info,
+            mint: boost_mint_info,
+            to: user_boost_account_info,
+            authority: staking_pool_info,
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(token_program_info, cpi_accounts, signer),
+            amount,
+            decimals,
+        )?;
+
+        msg!("Returned {} boost tokens", amount);
+        Ok(())
+    }
+
+    pub fn set_keeper_tip_bps(ctx: Context<SetKeeperTipBps>, _pool_id: u64, keeper_tip_bps: u16) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        require!(keeper_tip_bps <= MAX_KEEPER_TIP_BPS, ErrorCode::InvalidKeeperTipBps);
+
+        staking_pool.keeper_tip_bps = keeper_tip_bps;
+        msg!("Set keeper tip to {} bps", keeper_tip_bps);
+        Ok(())
+    }
+
+    /// Configures the second reward stream's mint, vault, and rate. Switching to a
+    /// different mint requires every second-stream reward to be claimed first.
+    pub fn set_second_reward(ctx: Context<SetSecondReward>, _pool_id: u64, reward_rate_2: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+        let new_mint = ctx.accounts.reward_mint_2.key();
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            staking_pool.reward_mint_2 == Pubkey::default()
+                || staking_pool.reward_mint_2 == new_mint
+                || staking_pool.total_reward_debt_2 == 0,
+            ErrorCode::OutstandingRewards
+        );
+
+        // Accrue everything earned at the old rate so the new one only applies going forward
+        update_pool(staking_pool, Clock::get()?.unix_timestamp)?;
+
+        staking_pool.reward_mint_2 = new_mint;
+        staking_pool.pool_reward_account_2 = ctx.accounts.pool_reward_account_2.key();
+        staking_pool.reward_rate_2 = reward_rate_2;
+        msg!("Set second reward stream to {} at rate {}", new_mint, reward_rate_2);
+        Ok(())
+    }
+
+    pub fn set_claim_cooldown(ctx: Context<SetClaimCooldown>, _pool_id: u64, claim_cooldown_seconds: i64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.claim_cooldown_seconds = claim_cooldown_seconds;
+        msg!("Set claim cooldown to {} seconds", claim_cooldown_seconds);
+        Ok(())
+    }
+
+    pub fn set_unstake_grace(ctx: Context<SetUnstakeGrace>, _pool_id: u64, unstake_grace_seconds: i64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.unstake_grace_seconds = unstake_grace_seconds;
+        msg!("Set unstake grace period to {} seconds", unstake_grace_seconds);
+        Ok(())
+    }
+
+    pub fn set_rounding_mode(ctx: Context<SetRoundingMode>, _pool_id: u64, rounding_mode: u8) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+        require!(rounding_mode <= ROUNDING_CEIL, ErrorCode::InvalidRoundingMode);
+
+        staking_pool.rounding_mode = rounding_mode;
+        msg!("Updated reward rounding mode to {}", rounding_mode);
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<SetPaused>, _pool_id: u64, paused: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        if staking_pool.paused != paused {
+            staking_pool.paused = paused;
+            msg!("Staking pool {}", if paused { "paused" } else { "unpaused" });
+        }
+        Ok(())
+    }
+
+    /// Stops or restarts reward accrual. Time spent frozen never earns, and a rate
+    /// schedule resumes decaying from where it stopped.
+    pub fn set_accrual_frozen(ctx: Context<SetAccrualFrozen>, _pool_id: u64, frozen: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        if frozen == (staking_pool.accrual_frozen_at > 0) {
+            return Ok(());
+        }
+
+        // Freezing accrues up to now; unfreezing accrues nothing, since the window ends at the freeze
+        update_pool(staking_pool, now)?;
+
+        if frozen {
+            staking_pool.accrual_frozen_at = now;
+            msg!("Froze reward accrual at {}", now);
+        } else {
+            let frozen_for = elapsed_since(now, staking_pool.accrual_frozen_at)?;
+            staking_pool.schedule_start = staking_pool.schedule_start.checked_add(frozen_for).ok_or(ErrorCode::ArithmeticError)?;
+            staking_pool.accrual_frozen_at = 0;
+            msg!("Unfroze reward accrual after {} seconds", frozen_for);
+        }
+        Ok(())
+    }
+
+    pub fn set_deposits_paused(ctx: Context<SetDepositsPaused>, _pool_id: u64, paused: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        if staking_pool.deposits_paused != paused {
+            staking_pool.deposits_paused = paused;
+            msg!("Deposits {}", if paused { "paused" } else { "resumed" });
+        }
+        Ok(())
+    }
+
+    pub fn set_prevent_flash(ctx: Context<SetPreventFlash>, _pool_id: u64, enabled: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.prevent_flash = enabled;
+        msg!("Set same-slot stake/unstake protection to {}", enabled);
+        Ok(())
+    }
+
+    pub fn set_partial_fill_unstake(ctx: Context<SetPartialFillUnstake>, _pool_id: u64, enabled: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.partial_fill_unstake = enabled;
+        msg!("Set partial-fill unstakes to {}", enabled);
+        Ok(())
+    }
+
+    pub fn add_to_whitelist(ctx: Context<AddToWhitelist>, _pool_id: u64, user: Pubkey) -> Result<()> {
+        let staking_pool = &ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        let whitelist = &mut ctx.accounts.whitelist;
+        whitelist.user = user;
+        whitelist.active = true;
+        msg!("Whitelisted {}", user);
+        Ok(())
+    }
+
+    pub fn remove_from_whitelist(ctx: Context<RemoveFromWhitelist>, _pool_id: u64, user: Pubkey) -> Result<()> {
+        let staking_pool = &ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        ctx.accounts.whitelist.active = false;
+        msg!("Removed {} from whitelist", user);
+        Ok(())
+    }
+
+    pub fn add_to_blacklist(ctx: Context<AddToBlacklist>, _pool_id: u64, owner: Pubkey) -> Result<()> {
+        let staking_pool = &ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        ctx.accounts.blacklist.user = owner;
+        msg!("Blacklisted {}", owner);
+        Ok(())
+    }
+
+    /// Closes the wallet's `Blacklist` entry, returning its rent to the admin.
+    pub fn remove_from_blacklist(ctx: Context<RemoveFromBlacklist>, _pool_id: u64, owner: Pubkey) -> Result<()> {
+        let staking_pool = &ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        msg!("Removed {} from blacklist", owner);
+        Ok(())
+    }
+
+    pub fn set_admin_can_extend_lock(ctx: Context<SetAdminCanExtendLock>, _pool_id: u64, enabled: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.admin_can_extend_lock = enabled;
+        msg!("Admin can extend locks: {}", enabled);
+        Ok(())
+    }
+
+    pub fn set_receipt_required(ctx: Context<SetReceiptRequired>, _pool_id: u64, required: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.receipt_required = required;
+        msg!("Receipt required: {}", required);
+        Ok(())
+    }
+
+    pub fn set_blacklist_blocks_unstake(ctx: Context<SetBlacklistBlocksUnstake>, _pool_id: u64, enabled: bool) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.blacklist_blocks_unstake = enabled;
+        msg!("Set blacklist unstake blocking to {}", enabled);
+        Ok(())
+    }
+
+    pub fn propose_admin(ctx: Context<ProposeAdmin>, _pool_id: u64, new_admin: Pubkey) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let admin = &ctx.accounts.admin;
+
+        require!(
+            admin.key() == staking_pool.admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.pending_admin = new_admin;
+        msg!("Proposed new admin {}", new_admin);
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>, _pool_id: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let new_admin = &ctx.accounts.new_admin;
+
+        require!(
+            staking_pool.pending_admin != Pubkey::default()
+                && new_admin.key() == staking_pool.pending_admin,
+            ErrorCode::Unauthorized
+        );
+
+        staking_pool.admin = new_admin.key();
+        staking_pool.pending_admin = Pubkey::default();
+        msg!("Admin transferred to {}", new_admin.key());
+        Ok(())
+    }
+
+    /// Records the wallet's stake across all its positions in this pool for the current
+    /// epoch. Every position is passed in `remaining_accounts` and summed in this one call;
+    /// the record is written once and never changes.
+    pub fn snapshot<'info>(ctx: Context<'_, '_, 'info, 'info, Snapshot<'info>>, _pool_id: u64, epoch: u64) -> Result<()> {
+        let staking_pool_key = ctx.accounts.staking_pool.key();
+        let user_key = ctx.accounts.user.key();
+        let snapshot = &mut ctx.accounts.snapshot;
+        let clock = Clock::get()?;
+
+        // Only the running epoch can be recorded, so today's stake can't be filed for a later one
+        require!(epoch == clock.epoch, ErrorCode::InvalidSnapshotEpoch);
+        require!(snapshot.owner == Pubkey::default(), ErrorCode::SnapshotExists);
+
+        // Every one of the wallet's positions in this pool must be passed, each once
+        require!(
+            ctx.remaining_accounts.len() == ctx.accounts.pool_member.positions as usize,
+            ErrorCode::InvalidPositionAccount
+        );
+        let mut positions: Vec<Account<'info, UserStake>> = Vec::with_capacity(ctx.remaining_accounts.len());
+        let mut stake_amount: u64 = 0;
+        let mut weighted: u128 = 0;
+        let mut counted: u16 = 0;
+        for account_info in ctx.remaining_accounts.iter() {
+            require!(
+                account_info.is_writable && !positions.iter().any(|position| position.key() == account_info.key()),
+                ErrorCode::InvalidPositionAccount
+            );
+
+            let mut user_stake = Account::<UserStake>::try_from(account_info)?;
+            require!(user_stake.owner == user_key, ErrorCode::Unauthorized);
+            let (expected_key, _) = Pubkey::find_program_address(
+                &[
+                    b"user-stake",
+                    staking_pool_key.as_ref(),
+                    user_key.as_ref(),
+                    user_stake.position_id.to_le_bytes().as_ref(),
+                ],
+                ctx.program_id,
+            );
+            require!(expected_key == account_info.key(), ErrorCode::InvalidPositionAccount);
+
+            // A position transferred in after its old owner's snapshot was already counted there
+            if user_stake.snapshot_epoch != Some(epoch) {
+                user_stake.snapshot_epoch = Some(epoch);
+                stake_amount = stake_amount.checked_add(user_stake.stake_amount).ok_or(ErrorCode::ArithmeticError)?;
+                weighted = weighted.checked_add(weighted_stake(&user_stake)).ok_or(ErrorCode::ArithmeticError)?;
+                counted = counted.checked_add(1).ok_or(ErrorCode::ArithmeticError)?;
+            }
+            positions.push(user_stake);
+        }
+        for user_stake in positions.iter() {
+            user_stake.exit(ctx.program_id)?;
+        }
+
+        snapshot.set_inner(StakeSnapshot {
+            owner: user_key,
+            staking_pool: staking_pool_key,
+            epoch,
+            stake_amount,
+            weighted_stake: weighted,
+            positions: counted,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        msg!("Snapshot for epoch {}: {} staked across {} positions", epoch, stake_amount, counted);
+        Ok(())
+    }
+
+    pub fn close_user_stake(ctx: Context<CloseUserStake>, _pool_id: u64, _position_id: u64) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let user_stake = &ctx.accounts.user_stake;
+
+        require!(
+            user_stake.stake_amount == 0
+                && user_stake.pending_unstake == 0
+                && user_stake.reward_debt == 0
+                && user_stake.reward_debt_2 == 0
+                && user_stake.boost_amount == 0
+                && user_stake.nft_mint == Pubkey::default(),
+            ErrorCode::StakeAccountNotEmpty
+        );
+
+        // The account is wiped on close, so a later stake at this PDA counts as new again
+        leave_pool(staking_pool, &mut ctx.accounts.pool_member)?;
+        let user_account = &mut ctx.accounts.user_account;
+        user_account.positions = user_account.positions.checked_sub(1).ok_or(ErrorCode::ArithmeticError)?;
+
+        msg!("Closed stake account for {}", ctx.accounts.user.key());
+        Ok(())
+    }
+
+    pub fn get_pending_rewards(ctx: Context<GetPendingRewards>, _pool_id: u64, _position_id: u64) -> Result<u128> {
+        let staking_pool = &ctx.accounts.staking_pool;
+        let user_stake = &ctx.accounts.user_stake;
+        let clock = Clock::get()?;
+
+        let (pending_reward, _) = pending_reward_for(
+            staking_pool,
+            user_stake,
+            tier_reward_per_share_at(staking_pool, user_stake.reward_tier, clock.unix_timestamp)?,
+        )?;
+
+        let total_reward = user_stake.reward_debt.checked_add(pending_reward).ok_or(ErrorCode::ArithmeticError)?;
+        Ok(total_reward)
+    }
+
+    /// Seconds until the position unlocks, or 0 once it can be unstaked without penalty.
+    pub fn get_unlock_info(ctx: Context<GetUnlockInfo>, _pool_id: u64, _position_id: u64) -> Result<i64> {
+        let clock = Clock::get()?;
+        Ok(ctx.accounts.user_stake.unlock_time.saturating_sub(clock.unix_timestamp).max(0))
+    }
+
+    /// Settled rewards the pool owes across all positions, i.e. `total_reward_debt`.
+    /// Rewards accrued since each position last settled aren't included.
+    pub fn get_total_liability(ctx: Context<GetTotalLiability>, _pool_id: u64) -> Result<u128> {
+        Ok(ctx.accounts.staking_pool.total_reward_debt)
+    }
+
+    pub fn get_apr(ctx: Context<GetApr>, _pool_id: u64) -> Result<u64> {
+        let staking_pool = &ctx.accounts.staking_pool;
+
+        let reward_rate = if staking_pool.rate_schedule_enabled {
+            scheduled_rate_at(staking_pool, Clock::get()?.unix_timestamp)?
+        } else {
+            staking_pool.reward_rate
+        };
+
+        apr_bps(staking_pool, reward_rate)
+    }
+
+    /// Returns the base rate the pool accrues at right now, in `reward_rate` units.
+    pub fn get_effective_rate(ctx: Context<GetEffectiveRate>, _pool_id: u64) -> Result<u64> {
+        effective_rate_at(&ctx.accounts.staking_pool, Clock::get()?.unix_timestamp)
+    }
+
+    pub fn get_pool_stats(ctx: Context<GetPoolStats>, _pool_id: u64) -> Result<PoolStats> {
+        let staking_pool = &ctx.accounts.staking_pool;
+
+        // Report the rate in effect right now, which differs from reward_rate under a schedule
+        let reward_rate = if staking_pool.rate_schedule_enabled {
+            scheduled_rate_at(staking_pool, Clock::get()?.unix_timestamp)?
+        } else {
+            staking_pool.reward_rate
+        };
+
+        Ok(PoolStats {
+            total_staked: staking_pool.total_staked,
+            reward_rate,
+            last_update_time: staking_pool.last_update_time,
+            total_rewards_distributed: staking_pool.total_rewards_distributed,
+            reward_pool_balance: ctx.accounts.pool_reward_account.amount,
+        })
+    }
+
+    /// Decimals and a per-whole-token daily rate, so wallets don't have to redo the unit math.
+    pub fn get_display_info(ctx: Context<GetDisplayInfo>, _pool_id: u64) -> Result<DisplayInfo> {
+        let staking_pool = &ctx.accounts.staking_pool;
+        let reward_rate = effective_rate_at(staking_pool, Clock::get()?.unix_timestamp)?;
+
+        Ok(DisplayInfo {
+            stake_decimals: staking_pool.stake_decimals,
+            reward_decimals: staking_pool.reward_decimals,
+            total_staked: staking_pool.total_staked,
+            reward_per_token_per_day: daily_reward_per_whole_token(staking_pool, reward_rate)?,
+        })
+    }
+}
+
+/// Wallet a stake is made for: `position_owner` when a delegate signs, otherwise the signer.
+fn position_owner_key<'a, 'info>(user: &'a Signer<'info>, position_owner: &'a Option<UncheckedAccount<'info>>) -> &'a Pubkey {
+    position_owner.as_ref().map_or(user.key, |owner| owner.key)
+}
+
+/// Wallet whose token accounts receive `user_stake`'s rewards: its `reward_destination`
+/// when set, otherwise the owner.
+fn reward_destination(user_stake: &UserStake) -> Pubkey {
+    user_stake.reward_destination.unwrap_or(user_stake.owner)
+}
+
+/// Whether `blacklist` holds a `Blacklist` entry. Its address is fixed by the seeds, so an
+/// empty account means the wallet was never listed or has since been removed.
+fn is_blacklisted(blacklist: &AccountInfo) -> bool {
+    blacklist.owner == &crate::ID && !blacklist.data_is_empty()
+}
+
+/// Fails with `Blacklisted` when the owner's entry exists. Every path that stakes, pays out
+/// or restakes rewards, or hands the position to someone else runs it.
+fn require_not_blacklisted(blacklist: &AccountInfo) -> Result<()> {
+    require!(!is_blacklisted(blacklist), ErrorCode::Blacklisted);
+    Ok(())
+}
+
+/// Like `require_not_blacklisted` for paths that only return principal; locking a listed
+/// wallet's principal in is opt-in through `blacklist_blocks_unstake`.
+fn require_may_unstake(staking_pool: &StakingPool, blacklist: &AccountInfo) -> Result<()> {
+    require!(
+        !staking_pool.blacklist_blocks_unstake || !is_blacklisted(blacklist),
+        ErrorCode::Blacklisted
+    );
+    Ok(())
+}
+
+/// Whether `holder` holds the position's receipt in `receipt_token_account`. Always true
+/// on pools that don't require receipts.
+fn holds_receipt(
+    staking_pool: &StakingPool,
+    user_stake: &UserStake,
+    receipt_token_account: &Option<InterfaceAccount<TokenAccount>>,
+    holder: &Pubkey,
+) -> bool {
+    if !staking_pool.receipt_required {
+        return true;
+    }
+    receipt_token_account.as_ref().is_some_and(|account| {
+        user_stake.receipt_mint != Pubkey::default()
+            && account.mint == user_stake.receipt_mint
+            && account.owner == *holder
+            && account.amount == 1
+    })
+}
+
+/// Fails with `ReceiptRequired` unless `holder` holds the position's receipt. Every path
+/// that unstakes, claims or restakes a position runs it.
+fn require_receipt(
+    staking_pool: &StakingPool,
+    user_stake: &UserStake,
+    receipt_token_account: &Option<InterfaceAccount<TokenAccount>>,
+    holder: &Pubkey,
+) -> Result<()> {
+    require!(
+        holds_receipt(staking_pool, user_stake, receipt_token_account, holder),
+        ErrorCode::ReceiptRequired
+    );
+    Ok(())
+}
+
+/// Whether `user_stake` is unclaimed or already recorded for `owner`.
+fn stake_account_belongs_to(user_stake: &UserStake, owner: &Pubkey) -> bool {
+    user_stake.owner == Pubkey::default() || user_stake.owner == *owner
+}
+
+/// Whether `signer` may stake into or claim for `user_stake`.
+fn is_owner_or_delegate(user_stake: &UserStake, signer: &Pubkey) -> bool {
+    user_stake.owner == *signer || user_stake.delegate == Some(*signer)
+}
+
+/// Whether `signer` may claim for `user_stake`. On pools that require receipts anyone may
+/// try, since the claim belongs to whoever holds the receipt and `require_receipt` decides.
+fn may_claim(staking_pool: &StakingPool, user_stake: &UserStake, signer: &Pubkey) -> bool {
+    staking_pool.receipt_required || is_owner_or_delegate(user_stake, signer)
+}
+
+/// Wallet paid when `signer` claims for `user_stake`: a receipt holder other than the
+/// owner or delegate is paid directly, otherwise the position's reward destination.
+fn claim_recipient(staking_pool: &StakingPool, user_stake: &UserStake, signer: &Pubkey) -> Pubkey {
+    if staking_pool.receipt_required && !is_owner_or_delegate(user_stake, signer) {
+        *signer
+    } else {
+        reward_destination(user_stake)
+    }
+}
+
+/// Seconds elapsed from `since` to `now`, treating small backwards drift as zero.
+fn elapsed_since(now: i64, since: i64) -> Result<i64> {
+    let delta = now.checked_sub(since).ok_or(ErrorCode::ArithmeticError)?;
+    if delta < 0 {
+        require!(delta >= -MAX_CLOCK_DRIFT_SECONDS, ErrorCode::ClockWentBackwards);
+        return Ok(0);
+    }
+    Ok(delta)
+}
+
+/// Accumulator growth over `time_passed` seconds before the per-day division,
+/// i.e. `reward_rate * seconds * REWARD_PRECISION`.
+///
+/// `reward_rate` is per staked token per day, or per staked token per second when the
+/// pool is in per-second mode. The per-second product grows 86400x faster than the
+/// daily one, so large rates overflow sooner and surface as `ArithmeticError`.
+fn accrual_numerator(reward_rate: u64, time_passed: i64) -> Result<u128> {
+    if time_passed <= 0 {
+        return Ok(0);
+    }
+
+    // Never accrue over more than MAX_ACCRUAL_SECONDS in one update
+    let time_passed = time_passed.min(MAX_ACCRUAL_SECONDS) as u128;
+
+    let numerator = (reward_rate as u128)
+        .checked_mul(time_passed)
+        .ok_or(ErrorCode::ArithmeticError)?
+        .checked_mul(REWARD_PRECISION)
+        .ok_or(ErrorCode::ArithmeticError)?;
+    Ok(numerator)
+}
+
+/// Base rate `update_pool` would accrue at `timestamp`: 0 outside the rewards window,
+/// otherwise the scheduled rate on decaying pools and `reward_rate` on flat ones.
+fn effective_rate_at(staking_pool: &StakingPool, timestamp: i64) -> Result<u64> {
+    let before_start = timestamp < staking_pool.rewards_start_time;
+    let after_end = staking_pool.rewards_end_time > 0 && timestamp >= staking_pool.rewards_end_time;
+    let frozen = staking_pool.accrual_frozen_at > 0;
+    if before_start || after_end || frozen {
+        return Ok(0);
+    }
+    if staking_pool.rate_schedule_enabled {
+        scheduled_rate_at(staking_pool, timestamp)
+    } else {
+        Ok(staking_pool.reward_rate)
+    }
+}
+
+/// Rate a decaying schedule pays at `timestamp`: `initial_rate` reduced by
+/// `decay_bps_per_period` once for every full period since `schedule_start`.
+fn scheduled_rate_at(staking_pool: &StakingPool, timestamp: i64) -> Result<u64> {
+    let periods = timestamp.saturating_sub(staking_pool.schedule_start).max(0) / staking_pool.period_seconds;
+
+    // Fixed-point (REWARD_PRECISION) exponentiation by squaring of the retained share.
+    // Both operands never exceed REWARD_PRECISION, so the products fit in u128.
+    let retained = (BPS_DENOMINATOR - staking_pool.decay_bps_per_period as u64) as u128 * REWARD_PRECISION
+        / BPS_DENOMINATOR as u128;
+    let mut base = retained;
+    let mut factor = REWARD_PRECISION;
+    let mut exponent = periods as u64;
+    while exponent > 0 && factor > 0 {
+        if exponent & 1 == 1 {
+            factor = factor * base / REWARD_PRECISION;
+        }
+        base = base * base / REWARD_PRECISION;
+        exponent >>= 1;
+    }
+
+    let rate = (staking_pool.initial_rate as u128)
+        .checked_mul(factor)
+        .ok_or(ErrorCode::ArithmeticError)?
+        / REWARD_PRECISION;
+    u64::try_from(rate).map_err(|_| error!(ErrorCode::ArithmeticError))
+}
+
+/// `accrual_numerator` over `time_passed` seconds from `start` under the pool's
+/// decaying schedule, applying each period's rate to its own slice.
+fn scheduled_accrual_numerator(staking_pool: &StakingPool, start: i64, time_passed: i64) -> Result<u128> {
+    let end = start
+        .checked_add(time_passed.min(MAX_ACCRUAL_SECONDS))
+        .ok_or(ErrorCode::ArithmeticError)?;
+
+    let mut numerator: u128 = 0;
+    let mut cursor = start;
+    while cursor < end {
+        let rate = scheduled_rate_at(staking_pool, cursor)?;
+        if rate == 0 {
+            break;
+        }
+
+        // The current rate holds until the next period boundary
+        let periods = cursor.saturating_sub(staking_pool.schedule_start).max(0) / staking_pool.period_seconds;
+        let next_boundary = staking_pool.schedule_start
+            .checked_add((periods + 1).checked_mul(staking_pool.period_seconds).ok_or(ErrorCode::ArithmeticError)?)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        let segment_end = next_boundary.min(end);
+
+        let segment = accrual_numerator(rate, segment_end - cursor)?;
+        numerator = numerator.checked_add(segment).ok_or(ErrorCode::ArithmeticError)?;
+        cursor = segment_end;
+    }
+    Ok(numerator)
+}
+
+/// Start and length of the time since `last_update_time` that earns rewards.
+/// Time before `rewards_start_time` is skipped, so pre-launch deposits don't earn
+/// retroactively, and time after a non-zero `rewards_end_time` earns nothing. The
+/// window is capped at `max_accrual_seconds` so an idle pool can't overflow the math.
+/// While accrual is frozen the window ends at `accrual_frozen_at`.
+fn accrual_window(staking_pool: &StakingPool, now: i64) -> Result<(i64, i64)> {
+    let elapsed = elapsed_since(now, staking_pool.last_update_time)?;
+    let mut end = staking_pool.last_update_time.checked_add(elapsed).ok_or(ErrorCode::ArithmeticError)?;
+    if staking_pool.rewards_end_time > 0 {
+        end = end.min(staking_pool.rewards_end_time);
+    }
+    if staking_pool.accrual_frozen_at > 0 {
+        end = end.min(staking_pool.accrual_frozen_at);
+    }
+    let start = staking_pool.last_update_time.max(staking_pool.rewards_start_time);
+    let time_passed = end.saturating_sub(start).max(0).min(staking_pool.max_accrual_seconds);
+    Ok((start, time_passed))
+}
+
+/// The pool's `acc_reward_per_share` brought forward to `now`, along with the new
+/// `acc_reward_carry`, without mutating the pool.
+///
+/// Daily rates divide by 86400 here. The part of the numerator that doesn't divide
+/// evenly is carried to the next update instead of being dropped, so many short
+/// updates accrue exactly as much as one long one.
+fn accrue_reward_per_share(staking_pool: &StakingPool, now: i64) -> Result<(u128, u64)> {
+    let (start, time_passed) = accrual_window(staking_pool, now)?;
+    let numerator = base_accrual_numerator(staking_pool, start, time_passed)?;
+
+    add_accrual(
+        staking_pool.acc_reward_per_share,
+        staking_pool.acc_reward_carry,
+        emission_share(staking_pool, numerator)?,
+        staking_pool.reward_rate_is_per_second,
+    )
+}
+
+/// Accrual numerator of the pool's base rate: the decaying schedule when enabled,
+/// otherwise the flat `reward_rate`.
+fn base_accrual_numerator(staking_pool: &StakingPool, start: i64, time_passed: i64) -> Result<u128> {
+    if staking_pool.rate_schedule_enabled {
+        scheduled_accrual_numerator(staking_pool, start, time_passed)
+    } else {
+        accrual_numerator(staking_pool.reward_rate, time_passed)
+    }
+}
+
+/// Per-tier counterpart of `accrue_reward_per_share`. Tiers with a `reward_rates` entry
+/// accrue at that flat rate; the rest follow the base rate, so their accumulator matches
+/// `acc_reward_per_share`. Positions settle against their own tier's accumulator.
+fn accrue_tier_reward_per_share(
+    staking_pool: &StakingPool,
+    now: i64,
+) -> Result<([u128; LOCK_TIER_COUNT], [u64; LOCK_TIER_COUNT])> {
+    let (start, time_passed) = accrual_window(staking_pool, now)?;
+    let base_numerator = base_accrual_numerator(staking_pool, start, time_passed)?;
+
+    let mut acc_reward_per_share = staking_pool.tier_acc_reward_per_share;
+    let mut carry = staking_pool.tier_acc_reward_carry;
+    for tier in 0..LOCK_TIER_COUNT {
+        let numerator = match staking_pool.reward_rates[tier] {
+            0 => base_numerator,
+            rate => accrual_numerator(rate, time_passed)?,
+        };
+        (acc_reward_per_share[tier], carry[tier]) = add_accrual(
+            acc_reward_per_share[tier],
+            carry[tier],
+            emission_share(staking_pool, numerator)?,
+            staking_pool.reward_rate_is_per_second,
+        )?;
+    }
+    Ok((acc_reward_per_share, carry))
+}
+
+/// Second-stream counterpart of `accrue_reward_per_share`, accruing `reward_rate_2` over
+/// the same window. The second stream always pays a flat rate.
+fn accrue_reward_per_share_2(staking_pool: &StakingPool, now: i64) -> Result<(u128, u64)> {
+    let (_, time_passed) = accrual_window(staking_pool, now)?;
+    let numerator = accrual_numerator(staking_pool.reward_rate_2, time_passed)?;
+
+    add_accrual(
+        staking_pool.acc_reward_per_share_2,
+        staking_pool.acc_reward_carry_2,
+        emission_share(staking_pool, numerator)?,
+        staking_pool.reward_rate_is_per_second,
+    )
+}
+
+/// The part of an accrual numerator each staked token earns. In shared-emission mode the
+/// rate is the whole pool's emission, split by weight like `distribute` since positions
+/// settle with their multiplier applied; nothing accrues while the pool is empty.
+/// Otherwise every token earns the full rate.
+fn emission_share(staking_pool: &StakingPool, numerator: u128) -> Result<u128> {
+    match unboosted_token_share(staking_pool) {
+        Some((token_weight, total_weight)) => Ok(numerator
+            .checked_mul(token_weight)
+            .ok_or(ErrorCode::ArithmeticError)?
+            / total_weight),
+        None => Ok(0),
+    }
+}
+
+/// Share of the rate one unboosted staked token earns, as a fraction. Shared-emission
+/// pools give it `BPS_DENOMINATOR` out of `total_weighted_stake`, and `None` while empty;
+/// other pools pay every token the full rate.
+fn unboosted_token_share(staking_pool: &StakingPool) -> Option<(u128, u128)> {
+    if !staking_pool.shared_emission {
+        return Some((1, 1));
+    }
+    match staking_pool.total_weighted_stake {
+        0 => None,
+        total_weighted_stake => Some((BPS_DENOMINATOR as u128, total_weighted_stake)),
+    }
+}
+
+/// Adds an accrual numerator to an accumulator, converting daily rates to seconds and
+/// returning the new accumulator and carry.
+fn add_accrual(acc_reward_per_share: u128, carry: u64, numerator: u128, per_second: bool) -> Result<(u128, u64)> {
+    let (increment, carry) = if per_second {
+        (numerator, 0)
+    } else {
+        // Convert the per-day rate to the elapsed seconds (86400 seconds in a day)
+        let numerator = numerator
+            .checked_add(carry as u128)
+            .ok_or(ErrorCode::ArithmeticError)?;
+        (numerator / 86400, (numerator % 86400) as u64)
+    };
+
+    let acc_reward_per_share = acc_reward_per_share
+        .checked_add(increment)
+        .ok_or(ErrorCode::ArithmeticError)?;
+    Ok((acc_reward_per_share, carry))
+}
+
+/// Tier `tier`'s accumulator brought forward to `now` without mutating the pool.
+fn tier_reward_per_share_at(staking_pool: &StakingPool, tier: u8, now: i64) -> Result<u128> {
+    Ok(accrue_tier_reward_per_share(staking_pool, now)?.0[tier as usize])
+}
+
+/// Accrues rewards up to `now` so later rate or stake changes only affect future time.
+fn update_pool(staking_pool: &mut StakingPool, now: i64) -> Result<()> {
+    let (acc_reward_per_share, carry) = accrue_reward_per_share(staking_pool, now)?;
+    let (tier_acc_reward_per_share, tier_carry) = accrue_tier_reward_per_share(staking_pool, now)?;
+    let (acc_reward_per_share_2, carry_2) = accrue_reward_per_share_2(staking_pool, now)?;
+    staking_pool.acc_reward_per_share = acc_reward_per_share;
+    staking_pool.acc_reward_carry = carry;
+    staking_pool.tier_acc_reward_per_share = tier_acc_reward_per_share;
+    staking_pool.tier_acc_reward_carry = tier_carry;
+    staking_pool.acc_reward_per_share_2 = acc_reward_per_share_2;
+    staking_pool.acc_reward_carry_2 = carry_2;
+    staking_pool.last_update_time = now.max(staking_pool.last_update_time);
+    Ok(())
+}
+
+/// What reward rates are divided by: `reward_rate_denominator`, times `10^stake_decimals`
+/// when the pool normalizes decimals so rates are per whole staked token.
+fn rate_denominator(staking_pool: &StakingPool) -> Result<u128> {
+    let scale = if staking_pool.normalize_decimals {
+        10u128.checked_pow(staking_pool.stake_decimals as u32).ok_or(ErrorCode::ArithmeticError)?
+    } else {
+        1
+    };
+    (staking_pool.reward_rate_denominator as u128)
+        .checked_mul(scale)
+        .ok_or_else(|| error!(ErrorCode::ArithmeticError))
+}
+
+/// Yearly rewards per staked token at `reward_rate`, in bps, for an unboosted position.
+/// Both sides are counted in whole tokens, so the result assumes a stake token is worth
+/// one reward token. Shared-emission pools split the rate by weight and report 0 while
+/// empty.
+fn apr_bps(staking_pool: &StakingPool, reward_rate: u64) -> Result<u64> {
+    let periods_per_year: u128 = if staking_pool.reward_rate_is_per_second { 365 * 86400 } else { 365 };
+    let Some((token_weight, total_weight)) = unboosted_token_share(staking_pool) else {
+        return Ok(0);
+    };
+    let stake_scale = 10u128.checked_pow(staking_pool.stake_decimals as u32).ok_or(ErrorCode::ArithmeticError)?;
+    let reward_scale = 10u128.checked_pow(staking_pool.reward_decimals as u32).ok_or(ErrorCode::ArithmeticError)?;
+
+    let yearly = (reward_rate as u128)
+        .checked_mul(periods_per_year)
+        .and_then(|n| n.checked_mul(BPS_DENOMINATOR as u128))
+        .and_then(|n| n.checked_mul(stake_scale))
+        .and_then(|n| n.checked_mul(token_weight))
+        .ok_or(ErrorCode::ArithmeticError)?;
+    let denominator = rate_denominator(staking_pool)?
+        .checked_mul(total_weight)
+        .and_then(|n| n.checked_mul(reward_scale))
+        .ok_or(ErrorCode::ArithmeticError)?;
+
+    u64::try_from(yearly / denominator).map_err(|_| error!(ErrorCode::ArithmeticError))
+}
+
+/// Reward tokens one day at `reward_rate` pays the pool's current `total_staked` at 1x,
+/// rounded down. Shared-emission pools pay the rate once, however much is staked, and
+/// nothing while empty. Lock multipliers and boosts aren't included.
+fn daily_liability(staking_pool: &StakingPool, reward_rate: u64) -> Result<u128> {
+    let periods_per_day: u128 = if staking_pool.reward_rate_is_per_second { 86400 } else { 1 };
+    let stakers: u128 = match (staking_pool.shared_emission, staking_pool.total_staked) {
+        (_, 0) => return Ok(0),
+        (true, _) => 1,
+        (false, total_staked) => total_staked as u128,
+    };
+
+    let daily = (reward_rate as u128)
+        .checked_mul(periods_per_day)
+        .and_then(|n| n.checked_mul(stakers))
+        .ok_or(ErrorCode::ArithmeticError)?;
+    Ok(daily / rate_denominator(staking_pool)?)
+}
+
+/// Reward base units one whole staked token (`10^stake_decimals` base units) earns in a
+/// day at `reward_rate` with no multiplier, rounded down. Shared-emission pools split the
+/// rate by weight and report 0 while empty.
+fn daily_reward_per_whole_token(staking_pool: &StakingPool, reward_rate: u64) -> Result<u128> {
+    let periods_per_day: u128 = if staking_pool.reward_rate_is_per_second { 86400 } else { 1 };
+    let Some((token_weight, total_weight)) = unboosted_token_share(staking_pool) else {
+        return Ok(0);
+    };
+    let stake_scale = 10u128.checked_pow(staking_pool.stake_decimals as u32).ok_or(ErrorCode::ArithmeticError)?;
+
+    let daily = (reward_rate as u128)
+        .checked_mul(periods_per_day)
+        .and_then(|n| n.checked_mul(stake_scale))
+        .and_then(|n| n.checked_mul(token_weight))
+        .ok_or(ErrorCode::ArithmeticError)?;
+    let denominator = rate_denominator(staking_pool)?
+        .checked_mul(total_weight)
+        .ok_or(ErrorCode::ArithmeticError)?;
+    Ok(daily / denominator)
+}
+
+/// Whole tokens earned by `stake_amount` since the accumulator stood at
+/// `reward_checkpoint`, scaled by `multiplier_bps` and divided by `rate_denominator`.
+///
+/// `remainder` is the fraction of a token left over from earlier settlements, in units
+/// of 1 / (REWARD_PRECISION * BPS_DENOMINATOR * rate_denominator) tokens. It's
+/// added in before rounding, and the new fraction is returned alongside the reward.
+/// `rounding_mode` decides whether that fraction is carried (floor) or paid as a whole
+/// token, leaving nothing to carry; nearest rounds halves up.
+///
+/// All math is done in `u128` and the reward stays `u128`, so settling never overflows
+/// on account of the payout size; claims narrow it with `to_token_amount`.
+fn calculate_pending_reward(
+    stake_amount: u64,
+    acc_reward_per_share: u128,
+    reward_checkpoint: u128,
+    multiplier_bps: u32,
+    rate_denominator: u128,
+    remainder: u128,
+    rounding_mode: u8,
+) -> Result<(u128, u128)> {
+    let scaled = (stake_amount as u128)
+        .checked_mul(
+            acc_reward_per_share
+                .checked_sub(reward_checkpoint)
+                .ok_or(ErrorCode::ArithmeticError)?,
+        )
+        .ok_or(ErrorCode::ArithmeticError)?
+        .checked_mul(multiplier_bps as u128)
+        .ok_or(ErrorCode::ArithmeticError)?
+        .checked_add(remainder)
+        .ok_or(ErrorCode::ArithmeticError)?;
+
+    let denominator = (REWARD_PRECISION * BPS_DENOMINATOR as u128)
+        .checked_mul(rate_denominator)
+        .filter(|denominator| *denominator > 0)
+        .ok_or(ErrorCode::ArithmeticError)?;
+    let (reward, fraction) = (scaled / denominator, scaled % denominator);
+
+    let round_up = match rounding_mode {
+        ROUNDING_NEAREST => fraction > 0 && fraction >= denominator - fraction,
+        ROUNDING_CEIL => fraction > 0,
+        _ => false,
+    };
+    if round_up {
+        Ok((reward.checked_add(1).ok_or(ErrorCode::ArithmeticError)?, 0))
+    } else {
+        Ok((reward, fraction))
+    }
+}
+
+/// Narrows a settled reward to a token amount at claim time, failing with
+/// `ArithmeticError` if it's more than a single transfer can move.
+fn to_token_amount(reward: u128) -> Result<u64> {
+    reward.try_into().map_err(|_| error!(ErrorCode::ArithmeticError))
+}
+
+/// Rewards `user_stake` has earned since its checkpoint, including its lock multiplier
+/// and NFT boost, and the fraction of a token that would carry over.
+fn pending_reward_for(
+    staking_pool: &StakingPool,
+    user_stake: &UserStake,
+    acc_reward_per_share: u128,
+) -> Result<(u128, u128)> {
+    calculate_pending_reward(
+        user_stake.stake_amount,
+        acc_reward_per_share,
+        user_stake.reward_checkpoint,
+        position_multiplier_bps(user_stake),
+        rate_denominator(staking_pool)?,
+        user_stake.reward_remainder,
+        staking_pool.rounding_mode,
+    )
+}
+
+/// A position's reward multiplier. The NFT and governance boosts add to the lock
+/// multiplier, e.g. 1.5x + 10% + 5% pays 1.65x.
+fn position_multiplier_bps(user_stake: &UserStake) -> u32 {
+    user_stake.lock_multiplier_bps as u32 + user_stake.boost_bps as u32 + user_stake.governance_boost_bps as u32
+}
+
+/// Counts another of `owner`'s positions in the pool, and the wallet itself in
+/// `staker_count` if it's their first.
+fn join_pool(staking_pool: &mut StakingPool, pool_member: &mut PoolMember, owner: Pubkey) -> Result<()> {
+    if pool_member.positions == 0 {
+        staking_pool.staker_count = staking_pool.staker_count.checked_add(1).ok_or(ErrorCode::ArithmeticError)?;
+    }
+    pool_member.owner = owner;
+    pool_member.positions = pool_member.positions.checked_add(1).ok_or(ErrorCode::ArithmeticError)?;
+    Ok(())
+}
+
+/// Uncounts one of a wallet's positions in the pool, and the wallet once it has none left.
+fn leave_pool(staking_pool: &mut StakingPool, pool_member: &mut PoolMember) -> Result<()> {
+    pool_member.positions = pool_member.positions.checked_sub(1).ok_or(ErrorCode::ArithmeticError)?;
+    if pool_member.positions == 0 {
+        staking_pool.staker_count = staking_pool.staker_count.checked_sub(1).ok_or(ErrorCode::ArithmeticError)?;
+    }
+    Ok(())
+}
+
+/// A position's stake weighted by its multiplier, as summed into `total_weighted_stake`.
+fn weighted_stake(user_stake: &UserStake) -> u128 {
+    user_stake.stake_amount as u128 * position_multiplier_bps(user_stake) as u128
+}
+
+/// Swaps a position's old weight for its current one in `total_weighted_stake`.
+fn reweigh_position(staking_pool: &mut StakingPool, weight_before: u128, user_stake: &UserStake) -> Result<()> {
+    staking_pool.total_weighted_stake = staking_pool.total_weighted_stake
+        .saturating_sub(weight_before)
+        .checked_add(weighted_stake(user_stake))
+        .ok_or(ErrorCode::ArithmeticError)?;
+    Ok(())
+}
+
+/// Whether the position was staked into in this slot on a pool that forbids leaving in the
+/// same slot, which would let borrowed tokens inflate `total_staked` for a single transaction.
+fn staked_this_slot(staking_pool: &StakingPool, user_stake: &UserStake, slot: u64) -> bool {
+    staking_pool.prevent_flash && user_stake.last_stake_slot == slot
+}
+
+/// Boost earned by `boost_amount` locked governance tokens: one bps per
+/// `boost_tokens_per_bps`, capped at the pool's `max_boost_bps`.
+fn governance_boost_bps(staking_pool: &StakingPool, boost_amount: u64) -> u16 {
+    if staking_pool.boost_tokens_per_bps == 0 {
+        return 0;
+    }
+    (boost_amount / staking_pool.boost_tokens_per_bps).min(staking_pool.max_boost_bps as u64) as u16
+}
+
+/// Moves rewards earned since the user's last checkpoint into `reward_debt` and
+/// the pool's `total_reward_debt`, keeping the leftover fraction in `reward_remainder`,
+/// and does the same for the second reward stream's `_2` fields.
+/// Call after `update_pool` and before changing `stake_amount`.
+fn settle_rewards(staking_pool: &mut StakingPool, user_stake: &mut UserStake) -> Result<()> {
+    let acc_reward_per_share = staking_pool.tier_acc_reward_per_share[user_stake.reward_tier as usize];
+    let (pending_reward, remainder) = pending_reward_for(staking_pool, user_stake, acc_reward_per_share)?;
+
+    user_stake.reward_debt = user_stake.reward_debt.checked_add(pending_reward).ok_or(ErrorCode::ArithmeticError)?;
+    user_stake.reward_checkpoint = acc_reward_per_share;
+    user_stake.reward_remainder = remainder;
+    staking_pool.total_reward_debt = staking_pool.total_reward_debt.checked_add(pending_reward).ok_or(ErrorCode::ArithmeticError)?;
+
+    // The second stream settles alongside the first; it stays at zero until configured
+    let acc_reward_per_share_2 = staking_pool.acc_reward_per_share_2;
+    let (pending_reward_2, remainder_2) = calculate_pending_reward(
+        user_stake.stake_amount,
+        acc_reward_per_share_2,
+        user_stake.reward_checkpoint_2,
+        position_multiplier_bps(user_stake),
+        rate_denominator(staking_pool)?,
+        user_stake.reward_remainder_2,
+        staking_pool.rounding_mode,
+    )?;
+
+    user_stake.reward_debt_2 = user_stake.reward_debt_2.checked_add(pending_reward_2).ok_or(ErrorCode::ArithmeticError)?;
+    user_stake.reward_checkpoint_2 = acc_reward_per_share_2;
+    user_stake.reward_remainder_2 = remainder_2;
+    staking_pool.total_reward_debt_2 = staking_pool.total_reward_debt_2.checked_add(pending_reward_2).ok_or(ErrorCode::ArithmeticError)?;
+    Ok(())
+}
+
+/// Stake-weighted average of the position's entry time and `now` after adding `added`
+/// tokens, rounded down. An empty position simply enters at `now`.
+fn blended_entry_time(user_stake: &UserStake, added: u64, now: i64) -> Result<i64> {
+    let existing = user_stake.stake_amount as i128;
+    let total = existing + added as i128;
+    if existing == 0 || total == 0 {
+        return Ok(now);
+    }
+
+    let weighted = (user_stake.average_entry_time as i128)
+        .checked_mul(existing)
+        .and_then(|weighted| weighted.checked_add((now as i128).checked_mul(added as i128)?))
+        .ok_or(ErrorCode::ArithmeticError)?;
+    i64::try_from(weighted.div_euclid(total)).map_err(|_| error!(ErrorCode::ArithmeticError))
+}
+
+/// Whether a reward stream last claimed at `last_claim_time` may be claimed again: always
+/// when the pool has no claim cooldown, otherwise once `claim_cooldown_seconds` have passed.
+/// Each stream keeps its own time, so claiming one never holds up the other.
+fn claim_cooldown_over(staking_pool: &StakingPool, last_claim_time: i64, now: i64) -> Result<bool> {
+    if staking_pool.claim_cooldown_seconds <= 0 {
+        return Ok(true);
+    }
+    Ok(elapsed_since(now, last_claim_time)? >= staking_pool.claim_cooldown_seconds)
+}
+
+/// True while a position is inside the pool's penalty-free window after its last stake.
+fn within_unstake_grace(staking_pool: &StakingPool, user_stake: &UserStake, now: i64) -> Result<bool> {
+    if staking_pool.unstake_grace_seconds <= 0 {
+        return Ok(false);
+    }
+    Ok(elapsed_since(now, user_stake.last_stake_time)? < staking_pool.unstake_grace_seconds)
+}
+
+/// `bps` basis points of `amount`, rounded down. Used for penalties and referral rewards.
+fn calculate_bps_share(amount: u64, bps: u16) -> Result<u64> {
+    let share = (amount as u128)
+        .checked_mul(bps as u128)
+        .ok_or(ErrorCode::ArithmeticError)?
+        .checked_div(BPS_DENOMINATOR as u128)
+        .ok_or(ErrorCode::ArithmeticError)?;
+
+    let share = u64::try_from(share).map_err(|_| ErrorCode::ArithmeticError)?;
+    Ok(share)
+}
+
+/// Sends an unstake fee from the pool's stake vault to the fee recipient's account.
+/// Does nothing when `fee` is zero; otherwise the fee account must have been passed.
+#[allow(clippy::too_many_arguments)]
+fn transfer_unstake_fee<'info>(
+    fee: u64,
+    fee_token_account: Option<AccountInfo<'info>>,
+    pool_stake_account: &AccountInfo<'info>,
+    stake_mint: &AccountInfo<'info>,
+    staking_pool: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    signer: &[&[&[u8]]],
+    decimals: u8,
+) -> Result<()> {
+    if fee == 0 {
+        return Ok(());
+    }
+
+    let cpi_accounts = TransferChecked {
+        from: pool_stake_account.clone(),
+        mint: stake_mint.clone(),
+        to: fee_token_account.ok_or(ErrorCode::InvalidFeeAccount)?,
+        authority: staking_pool.clone(),
+    };
+
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(token_program.clone(), cpi_accounts, signer),
+        fee,
+        decimals
+    )?;
+    msg!("Unstake fee: {} tokens", fee);
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = admin, 
+        space = 8 + StakingPool::SIZE,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    
+    /// Created here as a PDA owned by the pool, so it can't be set up with the wrong owner
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"pool_stake", staking_pool.key().as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = staking_pool,
+        token::token_program = token_program
+    )]
+    pub pool_stake_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"pool_reward", staking_pool.key().as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = staking_pool,
+        token::token_program = reward_token_program
+    )]
+    pub pool_reward_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    
+    pub system_program: Program<'info, System>,
+    /// Token program of `stake_mint`
+    pub token_program: Interface<'info, TokenInterface>,
+    /// Token program of `reward_mint`; the same as `token_program` unless the mints differ
+    pub reward_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct Stake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+    
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), position_owner_key(&user, &position_owner).as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        space = 8 + UserStake::SIZE
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    /// Counts the owner's open positions across all pools
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [b"user-account", position_owner_key(&user, &position_owner).as_ref()],
+        bump,
+        space = 8 + UserAccount::SIZE
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    /// Counts the owner's open positions in this pool, so `staker_count` counts wallets
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [b"pool-member", staking_pool.key().as_ref(), position_owner_key(&user, &position_owner).as_ref()],
+        bump,
+        space = 8 + PoolMember::SIZE
+    )]
+    pub pool_member: Account<'info, PoolMember>,
+    
+    /// Authorizes the token transfer and owns the position (or is its delegate)
+    pub user: Signer<'info>,
+
+    /// Pays rent for `user_stake`, `user_account` and `pool_member` when they're created; usually `user`, or a sponsor
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Only used as a PDA seed. The position's owner when its delegate signs; omit when the owner stakes
+    pub position_owner: Option<UncheckedAccount<'info>>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.key() != pool_stake_account.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = user_token_account.mint == staking_pool.stake_mint,
+        constraint = user_token_account.owner == user.key()
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = pool_stake_account.mint == staking_pool.stake_mint,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        constraint = stake_mint.key() == staking_pool.stake_mint
+    )]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// NFT from the pool's collection; pass together with the other `nft_` accounts for the boost
+    pub nft_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    /// The user's account holding `nft_mint`
+    #[account(mut)]
+    pub nft_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The pool's account for `nft_mint` that holds the NFT while it boosts the position
+    #[account(
+        mut,
+        constraint = nft_escrow_account.owner == staking_pool.key() @ ErrorCode::InvalidNftBoost
+    )]
+    pub nft_escrow_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program that owns `nft_mint`
+    pub nft_token_program: Option<Interface<'info, TokenInterface>>,
+
+    /// The fee recipient's stake-mint account; only needed when the pool charges this fee
+    #[account(
+        mut,
+        constraint = fee_token_account.mint == staking_pool.stake_mint @ ErrorCode::InvalidFeeAccount,
+        constraint = fee_token_account.owner == staking_pool.fee_recipient @ ErrorCode::InvalidFeeAccount
+    )]
+    pub fee_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The referrer's position to credit; only needed on a first stake with a referrer
+    #[account(mut)]
+    pub referrer_stake: Option<Account<'info, UserStake>>,
+
+    /// Only needed when the pool requires a whitelist
+    #[account(
+        seeds = [b"whitelist", staking_pool.key().as_ref(), position_owner_key(&user, &position_owner).as_ref()],
+        bump
+    )]
+    pub whitelist: Option<Account<'info, Whitelist>>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), position_owner_key(&user, &position_owner).as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct Unstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.key() != pool_stake_account.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = user_token_account.mint == staking_pool.stake_mint,
+        constraint = user_token_account.owner == user.key(),
+        constraint = !user_token_account.is_frozen() @ ErrorCode::DestinationAccountFrozen
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = pool_stake_account.mint == staking_pool.stake_mint,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        constraint = stake_mint.key() == staking_pool.stake_mint
+    )]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// The fee recipient's stake-mint account; only needed when the pool charges this fee
+    #[account(
+        mut,
+        constraint = fee_token_account.mint == staking_pool.stake_mint @ ErrorCode::InvalidFeeAccount,
+        constraint = fee_token_account.owner == staking_pool.fee_recipient @ ErrorCode::InvalidFeeAccount
+    )]
+    pub fee_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct EmergencyUnstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.key() != pool_stake_account.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = user_token_account.mint == staking_pool.stake_mint,
+        constraint = user_token_account.owner == user.key(),
+        constraint = !user_token_account.is_frozen() @ ErrorCode::DestinationAccountFrozen
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_stake_account.mint == staking_pool.stake_mint,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = stake_mint.key() == staking_pool.stake_mint
+    )]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// The fee recipient's stake-mint account; only needed when the pool charges this fee
+    #[account(
+        mut,
+        constraint = fee_token_account.mint == staking_pool.stake_mint @ ErrorCode::InvalidFeeAccount,
+        constraint = fee_token_account.owner == staking_pool.fee_recipient @ ErrorCode::InvalidFeeAccount
+    )]
+    pub fee_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct UnstakeAndClaim<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.key() != pool_stake_account.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = user_token_account.mint == staking_pool.stake_mint,
+        constraint = user_token_account.owner == user.key(),
+        constraint = !user_token_account.is_frozen() @ ErrorCode::DestinationAccountFrozen
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_reward_account.key() != pool_reward_account.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = user_reward_account.mint == staking_pool.reward_mint,
+        constraint = user_reward_account.owner == reward_destination(&user_stake) @ ErrorCode::RewardDestinationMismatch,
+        constraint = !user_reward_account.is_frozen() @ ErrorCode::DestinationAccountFrozen
+    )]
+    pub user_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_stake_account.mint == staking_pool.stake_mint,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_reward_account.mint == staking_pool.reward_mint,
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = stake_mint.key() == staking_pool.stake_mint
+    )]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = reward_mint.key() == staking_pool.reward_mint
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// The fee recipient's stake-mint account; only needed when the pool charges this fee
+    #[account(
+        mut,
+        constraint = fee_token_account.mint == staking_pool.stake_mint @ ErrorCode::InvalidFeeAccount,
+        constraint = fee_token_account.owner == staking_pool.fee_recipient @ ErrorCode::InvalidFeeAccount
+    )]
+    pub fee_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+    /// Token program of `stake_mint`
+    pub token_program: Interface<'info, TokenInterface>,
+    /// Token program of `reward_mint`, which may differ from the stake mint's
+    pub reward_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct RequestUnstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub user: Signer<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct WithdrawUnstaked<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.key() != pool_stake_account.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = user_token_account.mint == staking_pool.stake_mint,
+        constraint = user_token_account.owner == user.key(),
+        constraint = !user_token_account.is_frozen() @ ErrorCode::DestinationAccountFrozen
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_stake_account.mint == staking_pool.stake_mint,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = stake_mint.key() == staking_pool.stake_mint
+    )]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    /// The fee recipient's stake-mint account; only needed when the pool charges this fee
+    #[account(
+        mut,
+        constraint = fee_token_account.mint == staking_pool.stake_mint @ ErrorCode::InvalidFeeAccount,
+        constraint = fee_token_account.owner == staking_pool.fee_recipient @ ErrorCode::InvalidFeeAccount
+    )]
+    pub fee_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct Compound<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub user: Signer<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = reward_mint.key() == staking_pool.reward_mint
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64, target_pool_id: u64, target_position_id: u64)]
+pub struct ClaimAndStakeInto<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Box<Account<'info, UserStake>>,
+
+    pub user: Signer<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = reward_mint.key() == staking_pool.reward_mint
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    /// Pool the rewards are staked into
+    #[account(
+        mut,
+        seeds = [b"staking_pool", target_pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub target_pool: Box<Account<'info, StakingPool>>,
+
+    /// The caller's existing position in `target_pool`
+    #[account(
+        mut,
+        seeds = [b"user-stake", target_pool.key().as_ref(), user.key().as_ref(), target_position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = target_user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub target_user_stake: Box<Account<'info, UserStake>>,
+
+    /// Only needed when the target pool requires a whitelist
+    #[account(
+        seeds = [b"whitelist", target_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub target_whitelist: Option<Account<'info, Whitelist>>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the caller's entry in the target pool
+    #[account(
+        seeds = [b"blacklist", target_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub target_blacklist: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = target_pool_stake_account.key() == target_pool.pool_stake_account
+    )]
+    pub target_pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct SetAutoCompound<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct SetRewardDestination<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct SetDelegate<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub user: Signer<'info>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64, new_owner: Pubkey)]
+pub struct TransferPosition<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized,
+        close = user
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    /// The position's new address under `new_owner`; it must not hold a position yet
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), new_owner.as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        space = 8 + UserStake::SIZE
+    )]
+    pub new_user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"user-account", user.key().as_ref()],
+        bump
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [b"user-account", new_owner.as_ref()],
+        bump,
+        space = 8 + UserAccount::SIZE
+    )]
+    pub new_user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"pool-member", staking_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub pool_member: Account<'info, PoolMember>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [b"pool-member", staking_pool.key().as_ref(), new_owner.as_ref()],
+        bump,
+        space = 8 + PoolMember::SIZE
+    )]
+    pub new_pool_member: Account<'info, PoolMember>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the new owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), new_owner.as_ref()],
+        bump
+    )]
+    pub new_owner_blacklist: UncheckedAccount<'info>,
+
+    /// Only needed when the pool requires a whitelist
+    #[account(
+        seeds = [b"whitelist", staking_pool.key().as_ref(), new_owner.as_ref()],
+        bump
+    )]
+    pub new_owner_whitelist: Option<Account<'info, Whitelist>>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct MintReceipt<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    /// One receipt mint per position address, so it can only be created once
+    #[account(
+        init,
+        payer = user,
+        seeds = [b"receipt", user_stake.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = staking_pool,
+        mint::token_program = token_program
+    )]
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct KeeperCompound<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    /// Any opted-in position; the seeds use its recorded owner since the owner doesn't sign
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user_stake.owner.as_ref(), position_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub keeper: Signer<'info>,
+
+    /// The owner's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = keeper_reward_account.mint == staking_pool.reward_mint,
+        constraint = keeper_reward_account.owner == keeper.key()
+    )]
+    pub keeper_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = reward_mint.key() == staking_pool.reward_mint
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct ClaimRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user_stake.owner.as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = may_claim(&staking_pool, &user_stake, &user.key()) @ ErrorCode::NotOwnerOrDelegate
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    /// The position's owner or its delegate, or the receipt holder on pools that require receipts
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Only the ATA authority. Must be the position's reward destination (the owner unless set),
+    /// or the signer when it claims as the receipt holder
+    #[account(
+        address = claim_recipient(&staking_pool, &user_stake, &user.key()) @ ErrorCode::RewardDestinationMismatch
+    )]
+    pub reward_owner: UncheckedAccount<'info>,
+
+    /// The destination's reward ATA, created on the first claim; claims by a delegate still pay the owner
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = reward_mint,
+        associated_token::authority = reward_owner,
+        associated_token::token_program = token_program,
+        constraint = user_reward_account.key() != pool_reward_account.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = !user_reward_account.is_frozen() @ ErrorCode::DestinationAccountFrozen
+    )]
+    pub user_reward_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = pool_reward_account.mint == staking_pool.reward_mint,
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account,
+        // Even when the mints match, rewards never come out of staked principal
+        constraint = pool_reward_account.key() != staking_pool.pool_stake_account @ ErrorCode::InvalidAccountAlias
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        constraint = reward_mint.key() == staking_pool.reward_mint
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct ClaimRewards2<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user_stake.owner.as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = may_claim(&staking_pool, &user_stake, &user.key()) @ ErrorCode::NotOwnerOrDelegate
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    /// The position's owner or its delegate, or the receipt holder on pools that require receipts
+    pub user: Signer<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    /// The claim recipient's account for the second reward mint
+    #[account(
+        mut,
+        constraint = user_reward_account_2.key() != pool_reward_account_2.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = user_reward_account_2.mint == staking_pool.reward_mint_2,
+        constraint = user_reward_account_2.owner == claim_recipient(&staking_pool, &user_stake, &user.key()) @ ErrorCode::RewardDestinationMismatch,
+        constraint = !user_reward_account_2.is_frozen() @ ErrorCode::DestinationAccountFrozen
+    )]
+    pub user_reward_account_2: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_reward_account_2.key() == staking_pool.pool_reward_account_2
+    )]
+    pub pool_reward_account_2: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = reward_mint_2.key() == staking_pool.reward_mint_2
+    )]
+    pub reward_mint_2: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct ClaimAll<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the signer's entry, who owns every claimed position
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    /// Must belong to every position's reward destination; checked per position
+    #[account(
+        mut,
+        constraint = user_reward_account.key() != pool_reward_account.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = user_reward_account.mint == staking_pool.reward_mint,
+        constraint = !user_reward_account.is_frozen() @ ErrorCode::DestinationAccountFrozen
+    )]
+    pub user_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_reward_account.mint == staking_pool.reward_mint,
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = reward_mint.key() == staking_pool.reward_mint
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, epoch: u64)]
+pub struct Snapshot<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    /// How many positions the wallet holds here, so none can be left out
+    #[account(
+        seeds = [b"pool-member", staking_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub pool_member: Account<'info, PoolMember>,
+
+    /// The wallet's record for this pool and epoch; an existing one fails with `SnapshotExists`
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [b"snapshot", staking_pool.key().as_ref(), user.key().as_ref(), epoch.to_le_bytes().as_ref()],
+        bump,
+        space = 8 + StakeSnapshot::SIZE
+    )]
+    pub snapshot: Account<'info, StakeSnapshot>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct CloseUserStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized,
+        close = user
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    #[account(
+        mut,
+        seeds = [b"user-account", user.key().as_ref()],
+        bump
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"pool-member", staking_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub pool_member: Account<'info, PoolMember>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct FundRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub funder: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = funder_token_account.mint == staking_pool.reward_mint,
+        constraint = funder_token_account.owner == funder.key()
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_reward_account.mint == staking_pool.reward_mint,
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = reward_mint.key() == staking_pool.reward_mint
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct Distribute<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = admin_token_account.mint == staking_pool.reward_mint,
+        constraint = admin_token_account.owner == admin.key()
+    )]
+    pub admin_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = reward_mint.key() == staking_pool.reward_mint
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SeedStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = admin_token_account.mint == staking_pool.stake_mint,
+        constraint = admin_token_account.owner == admin.key()
+    )]
+    pub admin_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = stake_mint.key() == staking_pool.stake_mint
+    )]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct WithdrawSurplusRewards<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = admin_reward_account.mint == staking_pool.reward_mint
+    )]
+    pub admin_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_reward_account.mint == staking_pool.reward_mint,
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = reward_mint.key() == staking_pool.reward_mint
+    )]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SweepDust<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = admin_token_account.key() != pool_stake_account.key() @ ErrorCode::InvalidAccountAlias,
+        constraint = admin_token_account.mint == staking_pool.stake_mint
+    )]
+    pub admin_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_stake_account.mint == staking_pool.stake_mint,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = stake_mint.key() == staking_pool.stake_mint
+    )]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetRewardMint<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub admin: Signer<'info>,
+
+    pub new_reward_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = new_pool_reward_account.mint == new_reward_mint.key(),
+        constraint = new_pool_reward_account.owner == staking_pool.key(),
+        constraint = new_pool_reward_account.key() != staking_pool.pool_stake_account @ ErrorCode::InvalidAccountAlias
+    )]
+    pub new_pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct Slash<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user_stake.owner.as_ref(), position_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = treasury_token_account.mint == staking_pool.stake_mint
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = pool_stake_account.mint == staking_pool.stake_mint,
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = stake_mint.key() == staking_pool.stake_mint
+    )]
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct AdjustLock<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user_stake.owner.as_ref(), position_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct ReconcileTotalStaked<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+
+    #[account(
+        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+    )]
+    pub pool_stake_account: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct ProposeRewardRate<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetMaxAccrualSeconds<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct ApplyRewardRate<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+
+    /// Only read when the pool requires solvency on rate changes
+    #[account(
+        constraint = pool_reward_account.key() == staking_pool.pool_reward_account
+    )]
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetTierRate<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetRateSchedule<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetMinStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetMaxTotalStaked<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetMaxStakePerUser<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetRequireWhitelist<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetReferralBps<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetNftBoost<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct WithdrawNftBoost<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub user: Signer<'info>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    /// The signer's account holding the position's receipt; only needed when the pool requires receipts
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = user_stake.nft_mint @ ErrorCode::InvalidNftBoost)]
+    pub nft_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_nft_account.mint == nft_mint.key(),
+        constraint = user_nft_account.owner == user.key()
+    )]
+    pub user_nft_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = nft_escrow_account.mint == nft_mint.key() @ ErrorCode::InvalidNftBoost,
+        constraint = nft_escrow_account.owner == staking_pool.key() @ ErrorCode::InvalidNftBoost
+    )]
+    pub nft_escrow_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetBoostConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// Governance token users lock for the boost
+    pub boost_mint: InterfaceAccount<'info, Mint>,
+
+    /// Holds locked governance tokens; created by the first call
+    #[account(
+        init_if_needed,
+        payer = admin,
+        seeds = [b"pool_boost", staking_pool.key().as_ref()],
+        bump,
+        token::mint = boost_mint,
+        token::authority = staking_pool,
+        token::token_program = token_program
+    )]
+    pub pool_boost_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct BoostLock<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user_boost_account.mint == staking_pool.boost_mint,
+        constraint = user_boost_account.owner == user.key()
+    )]
+    pub user_boost_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        address = staking_pool.pool_boost_account
+    )]
+    pub pool_boost_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = staking_pool.boost_mint)]
+    pub boost_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct BoostUnlockWithdraw<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump,
+        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Account<'info, UserStake>,
+
+    pub user: Signer<'info>,
+
+    /// CHECK: Only read for whether it exists; the seeds pin it to the owner's entry
+    #[account(
+        seeds = [b"blacklist", staking_pool.key().as_ref(), user_stake.owner.as_ref()],
+        bump
+    )]
+    pub blacklist: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = user_boost_account.mint == staking_pool.boost_mint,
+        constraint = user_boost_account.owner == user.key()
+    )]
+    pub user_boost_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        address = staking_pool.pool_boost_account
+    )]
+    pub pool_boost_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = staking_pool.boost_mint)]
+    pub boost_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetKeeperTipBps<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetSecondReward<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    pub admin: Signer<'info>,
+
+    pub reward_mint_2: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = pool_reward_account_2.mint == reward_mint_2.key(),
+        constraint = pool_reward_account_2.owner == staking_pool.key()
+    )]
+    pub pool_reward_account_2: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetClaimCooldown<'info> {
+    #[account(
         mut,
-        seeds = [b"staking_pool"],
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
         bump
     )]
     pub staking_pool: Account<'info, StakingPool>,
-    
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetPreventFlash<'info> {
     #[account(
-        init_if_needed,
-        payer = user,
-        seeds = [b"user-stake", user.key().as_ref()],
-        bump,
-        space = 8 + UserStake::SIZE
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
     )]
-    pub user_stake: Account<'info, UserStake>,
-    
-    #[account(mut)]
-    pub user: Signer<'info>,
-    
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetPartialFillUnstake<'info> {
     #[account(
         mut,
-        constraint = user_token_account.mint == staking_pool.stake_mint,
-        constraint = user_token_account.owner == user.key()
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
     )]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetUnstakeGrace<'info> {
     #[account(
         mut,
-        constraint = pool_stake_account.mint == staking_pool.stake_mint,
-        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
     )]
-    pub pool_stake_account: Account<'info, TokenAccount>,
-    
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>,
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct Unstake<'info> {
+#[instruction(pool_id: u64)]
+pub struct SetMaxPositionsPerUser<'info> {
     #[account(
         mut,
-        seeds = [b"staking_pool"],
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
         bump
     )]
     pub staking_pool: Account<'info, StakingPool>,
-    
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetMaxSingleStake<'info> {
     #[account(
         mut,
-        seeds = [b"user-stake", user.key().as_ref()],
-        bump,
-        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
     )]
-    pub user_stake: Account<'info, UserStake>,
-    
-    #[account(mut)]
-    pub user: Signer<'info>,
-    
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetBlacklistBlocksUnstake<'info> {
     #[account(
         mut,
-        constraint = user_token_account.mint == staking_pool.stake_mint,
-        constraint = user_token_account.owner == user.key()
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
     )]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetRoundingMode<'info> {
     #[account(
         mut,
-        constraint = pool_stake_account.mint == staking_pool.stake_mint,
-        constraint = pool_stake_account.key() == staking_pool.pool_stake_account
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
     )]
-    pub pool_stake_account: Account<'info, TokenAccount>,
-    
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetRequireSolvencyOnRateChange<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetReceiptRequired<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetAdminCanExtendLock<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetPaused<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetAccrualFrozen<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct SetDepositsPaused<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, user: Pubkey)]
+pub struct AddToWhitelist<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        seeds = [b"whitelist", staking_pool.key().as_ref(), user.as_ref()],
+        bump,
+        space = 8 + Whitelist::SIZE
+    )]
+    pub whitelist: Account<'info, Whitelist>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct ClaimRewards<'info> {
+#[instruction(pool_id: u64, user: Pubkey)]
+pub struct RemoveFromWhitelist<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
     #[account(
         mut,
-        seeds = [b"staking_pool"],
+        seeds = [b"whitelist", staking_pool.key().as_ref(), user.as_ref()],
+        bump
+    )]
+    pub whitelist: Account<'info, Whitelist>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, owner: Pubkey)]
+pub struct AddToBlacklist<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
         bump
     )]
     pub staking_pool: Account<'info, StakingPool>,
-    
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"blacklist", staking_pool.key().as_ref(), owner.as_ref()],
+        bump,
+        space = 8 + Blacklist::SIZE
+    )]
+    pub blacklist: Account<'info, Blacklist>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, owner: Pubkey)]
+pub struct RemoveFromBlacklist<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
     #[account(
         mut,
-        seeds = [b"user-stake", user.key().as_ref()],
+        seeds = [b"blacklist", staking_pool.key().as_ref(), owner.as_ref()],
         bump,
-        constraint = user_stake.owner == user.key() @ ErrorCode::Unauthorized
+        close = admin
     )]
-    pub user_stake: Account<'info, UserStake>,
-    
+    pub blacklist: Account<'info, Blacklist>,
+
     #[account(mut)]
-    pub user: Signer<'info>,
-    
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct ProposeAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub new_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct GetPendingRewards<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user_stake.owner.as_ref(), position_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64, position_id: u64)]
+pub struct GetUnlockInfo<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+
+    #[account(
+        seeds = [b"user-stake", staking_pool.key().as_ref(), user_stake.owner.as_ref(), position_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct GetTotalLiability<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct GetEffectiveRate<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct GetApr<'info> {
+    #[account(
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct GetPoolStats<'info> {
     #[account(
-        mut,
-        constraint = user_reward_account.mint == staking_pool.reward_mint,
-        constraint = user_reward_account.owner == user.key()
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
+        bump
     )]
-    pub user_reward_account: Account<'info, TokenAccount>,
-    
+    pub staking_pool: Account<'info, StakingPool>,
+
     #[account(
-        mut,
-        constraint = pool_reward_account.mint == staking_pool.reward_mint,
         constraint = pool_reward_account.key() == staking_pool.pool_reward_account
     )]
-    pub pool_reward_account: Account<'info, TokenAccount>,
-    
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    pub pool_reward_account: InterfaceAccount<'info, TokenAccount>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateRewardRate<'info> {
+#[instruction(pool_id: u64)]
+pub struct GetDisplayInfo<'info> {
     #[account(
-        mut,
-        seeds = [b"staking_pool"],
+        seeds = [b"staking_pool", pool_id.to_le_bytes().as_ref()],
         bump
     )]
     pub staking_pool: Account<'info, StakingPool>,
-    pub admin: Signer<'info>,
 }
 
 #[account]
+#[derive(Default, InitSpace)]
 pub struct StakingPool {
+    pub pool_id: u64,
+    pub bump: u8,
+    pub is_initialized: bool,
     pub admin: Pubkey,
+    pub pending_admin: Pubkey,
     pub reward_rate: u64,
+    pub reward_rate_denominator: u64,
+    pub max_reward_rate: u64,
+    pub rate_timelock: i64,
+    pub pending_reward_rate: u64,
+    pub pending_rate_effective_time: i64,
+    pub require_solvency_on_rate_change: bool,
+    pub rewards_start_time: i64,
+    pub rewards_end_time: i64,
+    pub reward_rate_is_per_second: bool,
+    pub shared_emission: bool,
+    pub normalize_decimals: bool,
+    pub reward_equals_stake: bool,
+    pub rounding_mode: u8,
+    pub rate_schedule_enabled: bool,
+    pub initial_rate: u64,
+    pub decay_bps_per_period: u16,
+    pub period_seconds: i64,
+    pub schedule_start: i64,
+    pub lock_duration: i64,
+    pub min_stake_amount: u64,
+    pub early_unstake_penalty_bps: u16,
+    pub referral_bps: u16,
+    pub stake_fee_bps: u16,
+    pub unstake_fee_bps: u16,
+    pub fee_recipient: Pubkey,
+    pub nft_collection: Pubkey,
+    pub nft_boost_bps: u16,
+    pub boost_mint: Pubkey,
+    pub pool_boost_account: Pubkey,
+    pub boost_tokens_per_bps: u64,
+    pub max_boost_bps: u16,
+    pub total_boost_locked: u64,
+    pub keeper_tip_bps: u16,
+    pub lock_tiers: [LockTier; 4],
+    pub reward_rates: [u64; 4],
+    pub cooldown_seconds: i64,
+    pub claim_cooldown_seconds: i64,
+    pub unstake_grace_seconds: i64,
+    pub paused: bool,
+    pub deposits_paused: bool,
+    pub prevent_flash: bool,
+    pub partial_fill_unstake: bool,
+    pub require_whitelist: bool,
+    pub blacklist_blocks_unstake: bool,
+    pub receipt_required: bool,
+    pub admin_can_extend_lock: bool,
     pub total_staked: u64,
+    /// Sum of every position's `stake_amount` times its multiplier in bps.
+    pub total_weighted_stake: u128,
+    pub total_pending_unstake: u64,
+    pub staker_count: u64,
+    pub max_total_staked: u64,
+    pub max_stake_per_user: u64,
+    pub max_positions_per_user: u16,
+    pub max_single_stake: u64,
+    pub total_rewards_funded: u64,
+    pub total_forfeited: u64,
+    pub max_total_rewards: u64,
+    pub total_rewards_distributed: u64,
+    pub total_reward_debt: u128,
+    pub acc_reward_per_share: u128,
+    pub acc_reward_carry: u64,
+    pub tier_acc_reward_per_share: [u128; 4],
+    pub tier_acc_reward_carry: [u64; 4],
     pub last_update_time: i64,
+    pub accrual_frozen_at: i64,
+    pub max_accrual_seconds: i64,
     pub stake_mint: Pubkey,
     pub reward_mint: Pubkey,
     pub pool_stake_account: Pubkey,
     pub pool_reward_account: Pubkey,
+    pub stake_decimals: u8,
+    pub reward_decimals: u8,
+    pub reward_mint_2: Pubkey,
+    pub pool_reward_account_2: Pubkey,
+    pub reward_rate_2: u64,
+    pub acc_reward_per_share_2: u128,
+    pub acc_reward_carry_2: u64,
+    pub total_reward_debt_2: u128,
 }
 
 impl StakingPool {
-    pub const SIZE: usize = 32 + 8 + 8 + 8 + 32 + 32 + 32 + 32;
+    pub const SIZE: usize = Self::INIT_SPACE;
 }
 
 #[account]
+#[derive(Default, InitSpace)]
 pub struct UserStake {
     pub owner: Pubkey,
+    pub position_id: u64,
     pub stake_amount: u64,
-    pub reward_debt: u64,
+    pub max_stake_reached: u64,
+    pub reward_debt: u128,
+    pub reward_checkpoint: u128,
+    pub reward_remainder: u128,
+    pub reward_debt_2: u128,
+    pub reward_checkpoint_2: u128,
+    pub reward_remainder_2: u128,
     pub last_stake_time: i64,
+    pub average_entry_time: i64,
+    pub last_stake_slot: u64,
+    pub unlock_time: i64,
+    pub lock_multiplier_bps: u16,
+    pub reward_tier: u8,
+    pub boost_bps: u16,
+    pub nft_mint: Pubkey,
+    pub governance_boost_bps: u16,
+    pub boost_amount: u64,
+    pub boost_unlock: i64,
+    pub auto_compound: bool,
+    pub delegate: Option<Pubkey>,
+    pub reward_destination: Option<Pubkey>,
+    pub receipt_mint: Pubkey,
+    pub pending_unstake: u64,
+    pub cooldown_end: i64,
+    pub last_claim_time: i64,
+    pub last_claim_time_2: i64,
+    pub referrer: Pubkey,
+    pub total_claimed: u64,
+    pub snapshot_epoch: Option<u64>,
 }
 
 impl UserStake {
-    pub const SIZE: usize = 32 + 8 + 8 + 8;
+    pub const SIZE: usize = Self::INIT_SPACE;
+}
+
+#[account]
+#[derive(Default, InitSpace)]
+pub struct UserAccount {
+    pub owner: Pubkey,
+    pub positions: u16,
+}
+
+impl UserAccount {
+    pub const SIZE: usize = Self::INIT_SPACE;
+}
+
+#[account]
+#[derive(Default, InitSpace)]
+pub struct PoolMember {
+    pub owner: Pubkey,
+    pub positions: u16,
+}
+
+impl PoolMember {
+    pub const SIZE: usize = Self::INIT_SPACE;
+}
+
+#[account]
+#[derive(Default)]
+pub struct StakeSnapshot {
+    pub owner: Pubkey,
+    pub staking_pool: Pubkey,
+    pub epoch: u64,
+    pub stake_amount: u64,
+    pub weighted_stake: u128,
+    pub positions: u16,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+impl StakeSnapshot {
+    pub const SIZE: usize = 32 + 32 + 8 + 8 + 16 + 2 + 8 + 8;
+}
+
+#[account]
+#[derive(Default)]
+pub struct Whitelist {
+    pub user: Pubkey,
+    pub active: bool,
+}
+
+impl Whitelist {
+    pub const SIZE: usize = 32 + 1;
+}
+
+#[account]
+#[derive(Default)]
+pub struct Blacklist {
+    pub user: Pubkey,
+}
+
+impl Blacklist {
+    pub const SIZE: usize = 32;
+}
+
+/// Pool configuration passed to `initialize`. See the README for what each field does.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct InitializeParams {
+    pub reward_rate: u64,
+    pub lock_duration: i64,
+    pub reward_rate_is_per_second: bool,
+    pub min_stake_amount: u64,
+    pub early_unstake_penalty_bps: u16,
+    pub max_total_rewards: u64,
+    pub cooldown_seconds: i64,
+    pub max_total_staked: u64,
+    pub max_reward_rate: u64,
+    pub rate_timelock: i64,
+    pub rewards_start_time: i64,
+    pub rewards_end_time: i64,
+    pub reward_rate_denominator: u64,
+    pub shared_emission: bool,
+    pub normalize_decimals: bool,
+    pub reward_equals_stake: bool,
+}
+
+/// Pool-level numbers returned by `get_pool_stats`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct PoolStats {
+    pub total_staked: u64,
+    pub reward_rate: u64,
+    pub last_update_time: i64,
+    pub total_rewards_distributed: u64,
+    pub reward_pool_balance: u64,
+}
+
+/// Unit information returned by `get_display_info`. Amounts are in base units; divide by
+/// `10^decimals` of the matching mint to display them.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct DisplayInfo {
+    pub stake_decimals: u8,
+    pub reward_decimals: u8,
+    pub total_staked: u64,
+    pub reward_per_token_per_day: u128,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct LockTier {
+    pub lock_days: u64,
+    pub multiplier_bps: u16,
+}
+
+impl LockTier {
+    pub const SIZE: usize = Self::INIT_SPACE;
+}
+
+#[event]
+pub struct PoolInitialized {
+    pub pool: Pubkey,
+    pub pool_id: u64,
+    pub admin: Pubkey,
+    pub stake_mint: Pubkey,
+    pub reward_mint: Pubkey,
+    pub pool_stake_account: Pubkey,
+    pub pool_reward_account: Pubkey,
+    pub reward_rate: u64,
+}
+
+#[event]
+pub struct StakeEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub stake_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UnstakeEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub stake_amount: u64,
+    /// Part of the requested amount a partial-fill unstake couldn't pay out
+    pub shortfall: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ClaimEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub stake_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SlashEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub stake_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LockAdjusted {
+    pub user: Pubkey,
+    pub position_id: u64,
+    pub old_unlock_time: i64,
+    pub new_unlock_time: i64,
+    pub timestamp: i64,
 }
 
 #[error_code]
@@ -436,4 +6262,831 @@ pub enum ErrorCode {
     Unauthorized,
     #[msg("No rewards to claim")]
     NoRewardsToClaim,
+    #[msg("Stake is still locked")]
+    StakeLocked,
+    #[msg("Staking pool is paused")]
+    PoolPaused,
+    #[msg("Insufficient reward funds in pool")]
+    InsufficientRewardFunds,
+    #[msg("Stake is below the pool minimum")]
+    BelowMinimumStake,
+    #[msg("Stake account still holds stake or rewards")]
+    StakeAccountNotEmpty,
+    #[msg("Penalty exceeds 10000 basis points")]
+    InvalidPenaltyBps,
+    #[msg("Clock moved backwards beyond the tolerated drift")]
+    ClockWentBackwards,
+    #[msg("No lock tier matches the requested lock days")]
+    InvalidLockDays,
+    #[msg("Unstake cooldown has not finished")]
+    CooldownActive,
+    #[msg("Compounding requires the stake and reward mints to match")]
+    CompoundMintMismatch,
+    #[msg("Rate schedule decay or period is out of range")]
+    InvalidRateSchedule,
+    #[msg("Stake would exceed the pool's total stake cap")]
+    PoolCapExceeded,
+    #[msg("Stake would exceed the per-user stake cap")]
+    UserStakeCapExceeded,
+    #[msg("Wallet is not whitelisted for this pool")]
+    NotWhitelisted,
+    #[msg("This position is already in a snapshot for this epoch")]
+    SnapshotExists,
+    #[msg("Users can't refer themselves")]
+    SelfReferral,
+    #[msg("Referrer stake account doesn't belong to the referrer in this pool")]
+    InvalidReferrer,
+    #[msg("Referral reward exceeds 10000 basis points")]
+    InvalidReferralBps,
+    #[msg("Amount must be greater than zero")]
+    ZeroAmount,
+    #[msg("Reward rate exceeds the pool's maximum")]
+    RewardRateTooHigh,
+    #[msg("Proposed reward rate isn't effective yet")]
+    TimelockNotElapsed,
+    #[msg("No reward rate change has been proposed")]
+    NoPendingRewardRate,
+    #[msg("Position account is read-only, repeated, or not the signer's position in this pool")]
+    InvalidPositionAccount,
+    #[msg("Staking pool is already initialized")]
+    AlreadyInitialized,
+    #[msg("Fee exceeds the maximum of 1000 basis points")]
+    FeeTooHigh,
+    #[msg("Fee token account is missing or doesn't belong to the fee recipient")]
+    InvalidFeeAccount,
+    #[msg("NFT boost account or configuration is invalid")]
+    InvalidNftBoost,
+    #[msg("Position hasn't opted in to keeper compounding")]
+    AutoCompoundDisabled,
+    #[msg("Keeper tip exceeds 500 basis points")]
+    InvalidKeeperTipBps,
+    #[msg("Settled rewards are still owed to stakers")]
+    OutstandingRewards,
+    #[msg("Destination token account is frozen")]
+    DestinationAccountFrozen,
+    #[msg("Rewards end time must be after the start time")]
+    InvalidRewardsWindow,
+    #[msg("User token account can't be one of the pool's vaults")]
+    InvalidAccountAlias,
+    #[msg("Signer is neither the position's owner nor its delegate")]
+    NotOwnerOrDelegate,
+    #[msg("Accrual cap must be between 1 second and one year")]
+    InvalidMaxAccrualSeconds,
+    #[msg("Pool has no second reward stream")]
+    SecondRewardNotConfigured,
+    #[msg("Pool stake account must start empty")]
+    PoolAccountNotEmpty,
+    #[msg("Reward rate denominator must be non-zero")]
+    InvalidRewardRateDenominator,
+    #[msg("New deposits are paused")]
+    DepositsPaused,
+    #[msg("Reward account does not belong to the position's reward destination")]
+    RewardDestinationMismatch,
+    #[msg("Claim cooldown has not elapsed")]
+    ClaimCooldownActive,
+    #[msg("No dust to sweep")]
+    NoDustToSweep,
+    #[msg("Boost tokens are still locked")]
+    BoostLocked,
+    #[msg("Governance boost is not configured or the configuration is invalid")]
+    InvalidBoostConfig,
+    #[msg("Cannot unstake in the same slot as a stake")]
+    SameSlotStakeUnstake,
+    #[msg("Tier index is out of range")]
+    InvalidTier,
+    #[msg("Nothing is staked in the pool")]
+    NoStakers,
+    #[msg("Wallet already holds the maximum number of positions")]
+    TooManyPositions,
+    #[msg("Stake amount exceeds the pool's single-stake limit")]
+    StakeTooLarge,
+    #[msg("Wallet is blacklisted for this pool")]
+    Blacklisted,
+    #[msg("Seeded amounts do not match the tokens received")]
+    SeedAmountMismatch,
+    #[msg("Rounding mode must be 0 (floor), 1 (nearest), or 2 (ceil)")]
+    InvalidRoundingMode,
+    #[msg("The new owner already has this position")]
+    PositionExists,
+    #[msg("Reward mint matches the stake mint")]
+    MintCollision,
+    #[msg("The position's receipt must be held to unstake, claim or restake")]
+    ReceiptRequired,
+    #[msg("This position already has a receipt")]
+    ReceiptAlreadyMinted,
+    #[msg("Unlock time is in the past")]
+    InvalidUnlockTime,
+    #[msg("Locks can only be shortened")]
+    LockExtensionNotAllowed,
+    #[msg("Snapshots can only be taken for the current epoch")]
+    InvalidSnapshotEpoch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool_with_rate(reward_rate: u64, per_second: bool) -> StakingPool {
+        StakingPool {
+            reward_rate,
+            reward_rate_is_per_second: per_second,
+            reward_rate_denominator: 1,
+            max_accrual_seconds: MAX_ACCRUAL_SECONDS,
+            ..Default::default()
+        }
+    }
+
+    /// Whole tokens `stake_amount` earns at 1x from a zero checkpoint.
+    fn unboosted_reward(stake_amount: u64, acc_reward_per_share: u128) -> u64 {
+        to_token_amount(calculate_pending_reward(stake_amount, acc_reward_per_share, 0, 10_000, 1, 0, ROUNDING_FLOOR).unwrap().0).unwrap()
+    }
+
+    #[test]
+    fn pending_reward_accrues_per_day() {
+        let pool = pool_with_rate(10, false);
+
+        assert_eq!(unboosted_reward(1_000, tier_reward_per_share_at(&pool, 0, 86400).unwrap()), 10_000);
+        assert_eq!(unboosted_reward(1_000, tier_reward_per_share_at(&pool, 0, 43200).unwrap()), 5_000);
+        assert_eq!(unboosted_reward(1_000, tier_reward_per_share_at(&pool, 0, 0).unwrap()), 0);
+    }
+
+    #[test]
+    fn pending_reward_over_ten_seconds_in_both_modes() {
+        // Daily mode pro-rates 10 seconds of a day and rounds down to whole tokens
+        let daily = tier_reward_per_share_at(&pool_with_rate(10, false), 0, 10).unwrap();
+        assert_eq!(unboosted_reward(1_000, daily), 1);
+
+        // Per-second mode pays the full rate for every second
+        let per_second = tier_reward_per_share_at(&pool_with_rate(10, true), 0, 10).unwrap();
+        assert_eq!(unboosted_reward(1_000, per_second), 100_000);
+    }
+
+    #[test]
+    fn sub_day_fractions_carry_into_the_next_settlement() {
+        let mut pool = pool_with_rate(1, false);
+        let mut user_stake = UserStake {
+            stake_amount: 1,
+            lock_multiplier_bps: 10_000,
+            ..Default::default()
+        };
+
+        // 23 hours at 1 token/day is worth less than one whole token
+        update_pool(&mut pool, 23 * 3600).unwrap();
+        settle_rewards(&mut pool, &mut user_stake).unwrap();
+        assert_eq!(user_stake.reward_debt, 0);
+        assert!(user_stake.reward_remainder > 0);
+
+        // The last hour completes the day, and neither the pool nor the user lost a fraction
+        update_pool(&mut pool, 86400).unwrap();
+        settle_rewards(&mut pool, &mut user_stake).unwrap();
+        assert_eq!(pool.acc_reward_per_share, REWARD_PRECISION);
+        assert_eq!(pool.acc_reward_carry, 0);
+        assert_eq!(user_stake.reward_debt, 1);
+        assert_eq!(user_stake.reward_remainder, 0);
+    }
+
+    #[test]
+    fn frequent_settlements_match_a_single_multi_day_settlement() {
+        let mut pool = pool_with_rate(7, false);
+        let mut boosted = UserStake {
+            stake_amount: 3,
+            lock_multiplier_bps: DEFAULT_LOCK_TIERS[1].multiplier_bps,
+            ..Default::default()
+        };
+
+        // Settle every 7 minutes for three days
+        let mut now = 0;
+        while now < 3 * 86400 {
+            now = (now + 420).min(3 * 86400);
+            update_pool(&mut pool, now).unwrap();
+            settle_rewards(&mut pool, &mut boosted).unwrap();
+        }
+
+        // 3 tokens * 7/day * 3 days * 1.25x = 78.75
+        assert_eq!(boosted.reward_debt, 78);
+        assert_eq!(pool.total_reward_debt, 78);
+        assert_eq!(
+            calculate_pending_reward(3, 21 * REWARD_PRECISION, 0, DEFAULT_LOCK_TIERS[1].multiplier_bps.into(), 1, 0, ROUNDING_FLOOR).unwrap(),
+            (78, boosted.reward_remainder)
+        );
+    }
+
+    #[test]
+    fn rate_changes_only_apply_to_future_time() {
+        let mut pool = pool_with_rate(10, false);
+        let mut user_stake = UserStake {
+            stake_amount: 1_000,
+            lock_multiplier_bps: 10_000,
+            ..Default::default()
+        };
+
+        // One day at 10/day, then the admin doubles the rate
+        update_pool(&mut pool, 86400).unwrap();
+        pool.reward_rate = 20;
+
+        // One more day at 20/day
+        update_pool(&mut pool, 2 * 86400).unwrap();
+        settle_rewards(&mut pool, &mut user_stake).unwrap();
+
+        assert_eq!(user_stake.reward_debt, 10_000 + 20_000);
+        assert_eq!(user_stake.reward_checkpoint, pool.acc_reward_per_share);
+    }
+
+    #[test]
+    fn settled_position_starts_accruing_from_its_checkpoint() {
+        let mut pool = pool_with_rate(10, false);
+        update_pool(&mut pool, 86400).unwrap();
+
+        // A stake opened after a day of accrual doesn't earn that day
+        let mut user_stake = UserStake {
+            lock_multiplier_bps: 10_000,
+            ..Default::default()
+        };
+        settle_rewards(&mut pool, &mut user_stake).unwrap();
+        user_stake.stake_amount = 1_000;
+
+        update_pool(&mut pool, 2 * 86400).unwrap();
+        settle_rewards(&mut pool, &mut user_stake).unwrap();
+
+        assert_eq!(user_stake.reward_debt, 10_000);
+    }
+
+    #[test]
+    fn lock_multiplier_scales_settled_rewards() {
+        let mut pool = pool_with_rate(10, false);
+        let mut no_lock = UserStake {
+            stake_amount: 1_000,
+            lock_multiplier_bps: DEFAULT_LOCK_TIERS[0].multiplier_bps,
+            ..Default::default()
+        };
+        let mut ninety_days = UserStake {
+            stake_amount: 1_000,
+            lock_multiplier_bps: DEFAULT_LOCK_TIERS[2].multiplier_bps,
+            ..Default::default()
+        };
+
+        update_pool(&mut pool, 86400).unwrap();
+        settle_rewards(&mut pool, &mut no_lock).unwrap();
+        settle_rewards(&mut pool, &mut ninety_days).unwrap();
+
+        assert_eq!(no_lock.reward_debt, 10_000);
+        assert_eq!(ninety_days.reward_debt, 15_000);
+        assert_eq!(pool.total_reward_debt, 25_000);
+    }
+
+    #[test]
+    fn nft_boost_adds_to_the_lock_multiplier() {
+        let mut pool = pool_with_rate(10, false);
+        let mut boosted = UserStake {
+            stake_amount: 1_000,
+            lock_multiplier_bps: DEFAULT_LOCK_TIERS[2].multiplier_bps,
+            boost_bps: 1_000,
+            ..Default::default()
+        };
+
+        update_pool(&mut pool, 86400).unwrap();
+        settle_rewards(&mut pool, &mut boosted).unwrap();
+
+        // 1.5x for the lock plus 10% for the NFT
+        assert_eq!(boosted.reward_debt, 16_000);
+    }
+
+    #[test]
+    fn apr_covers_every_rate_mode() {
+        // 10 per day per token is 3650% a year
+        let mut pool = pool_with_rate(10, false);
+        assert_eq!(apr_bps(&pool, pool.reward_rate).unwrap(), 36_500_000);
+
+        pool.reward_rate_is_per_second = true;
+        assert_eq!(apr_bps(&pool, 1).unwrap(), 315_360_000_000);
+
+        // A denominator of 100,000 turns the same rate into 3.65%
+        let mut pool = pool_with_rate(10, false);
+        pool.reward_rate_denominator = 100_000;
+        assert_eq!(apr_bps(&pool, pool.reward_rate).unwrap(), 365);
+
+        // Shared emission splits the rate across everything staked, by weight
+        let mut pool = pool_with_rate(10_000, false);
+        pool.shared_emission = true;
+        assert_eq!(apr_bps(&pool, pool.reward_rate).unwrap(), 0);
+        pool.total_staked = 1_000_000;
+        pool.total_weighted_stake = 1_000_000 * 10_000;
+        assert_eq!(apr_bps(&pool, pool.reward_rate).unwrap(), 36_500);
+        pool.total_weighted_stake = 2_000_000 * 10_000;
+        assert_eq!(apr_bps(&pool, pool.reward_rate).unwrap(), 18_250);
+
+        // One 9-decimal reward token per whole 6-decimal staked token per day
+        let mut pool = pool_with_rate(1_000_000_000, false);
+        pool.normalize_decimals = true;
+        pool.stake_decimals = 6;
+        pool.reward_decimals = 9;
+        assert_eq!(apr_bps(&pool, pool.reward_rate).unwrap(), 3_650_000);
+    }
+
+    #[test]
+    fn daily_liability_covers_a_day_of_the_current_stake() {
+        let mut pool = pool_with_rate(10, false);
+        assert_eq!(daily_liability(&pool, 10).unwrap(), 0);
+
+        pool.total_staked = 1_000;
+        assert_eq!(daily_liability(&pool, 10).unwrap(), 10_000);
+        assert_eq!(daily_liability(&pool, 1_000).unwrap(), 1_000_000);
+
+        pool.reward_rate_is_per_second = true;
+        assert_eq!(daily_liability(&pool, 1).unwrap(), 86_400_000);
+
+        // Shared emission pays the rate once, and the denominator divides it
+        let mut pool = pool_with_rate(10, false);
+        pool.shared_emission = true;
+        pool.total_staked = 1_000;
+        pool.reward_rate_denominator = 4;
+        assert_eq!(daily_liability(&pool, 10).unwrap(), 2);
+    }
+
+    #[test]
+    fn daily_reward_per_whole_token_undoes_the_rate_units() {
+        // 10 base units per staked base unit per day, on a 6-decimal stake mint
+        let mut pool = pool_with_rate(10, false);
+        pool.stake_decimals = 6;
+        assert_eq!(daily_reward_per_whole_token(&pool, 10).unwrap(), 10_000_000);
+
+        pool.reward_rate_is_per_second = true;
+        assert_eq!(daily_reward_per_whole_token(&pool, 1).unwrap(), 86_400_000_000);
+
+        // Normalized rates are already per whole token
+        let mut pool = pool_with_rate(1_000_000_000, false);
+        pool.normalize_decimals = true;
+        pool.stake_decimals = 6;
+        assert_eq!(daily_reward_per_whole_token(&pool, pool.reward_rate).unwrap(), 1_000_000_000);
+
+        // Shared emission splits the rate across everything staked
+        let mut pool = pool_with_rate(1_000, false);
+        pool.shared_emission = true;
+        pool.stake_decimals = 2;
+        assert_eq!(daily_reward_per_whole_token(&pool, pool.reward_rate).unwrap(), 0);
+        pool.total_staked = 1_000;
+        pool.total_weighted_stake = 1_000 * 10_000;
+        assert_eq!(daily_reward_per_whole_token(&pool, pool.reward_rate).unwrap(), 100);
+    }
+
+    #[test]
+    fn stake_accounts_recorded_for_another_owner_are_rejected() {
+        let owner = Pubkey::new_unique();
+        let attacker = Pubkey::new_unique();
+
+        assert!(stake_account_belongs_to(&UserStake::default(), &owner));
+        let existing = UserStake { owner, ..Default::default() };
+        assert!(stake_account_belongs_to(&existing, &owner));
+
+        // A position pre-filled with another owner must not be adopted
+        let planted = UserStake { owner: attacker, ..Default::default() };
+        assert!(!stake_account_belongs_to(&planted, &owner));
+    }
+
+    #[test]
+    fn tier_rates_override_the_base_rate() {
+        let mut pool = pool_with_rate(10, false);
+        pool.reward_rates[1] = 30;
+        let position = |reward_tier| UserStake {
+            stake_amount: 1_000,
+            lock_multiplier_bps: 10_000,
+            reward_tier,
+            ..Default::default()
+        };
+        let (mut base, mut fast) = (position(0), position(1));
+
+        update_pool(&mut pool, 86400).unwrap();
+        settle_rewards(&mut pool, &mut base).unwrap();
+        settle_rewards(&mut pool, &mut fast).unwrap();
+
+        // Tiers without a rate track the base accumulator
+        assert_eq!(pool.tier_acc_reward_per_share[0], pool.acc_reward_per_share);
+        assert_eq!(base.reward_debt, 10_000);
+        assert_eq!(fast.reward_debt, 30_000);
+        assert_eq!(pool.total_reward_debt, 40_000);
+    }
+
+    #[test]
+    fn governance_boost_scales_with_locked_tokens_up_to_the_cap() {
+        let mut pool = pool_with_rate(10, false);
+        pool.boost_tokens_per_bps = 100;
+        pool.max_boost_bps = 2_000;
+
+        assert_eq!(governance_boost_bps(&pool, 99), 0);
+        assert_eq!(governance_boost_bps(&pool, 50_000), 500);
+        assert_eq!(governance_boost_bps(&pool, 10_000_000), 2_000);
+
+        let mut boosted = UserStake {
+            stake_amount: 1_000,
+            lock_multiplier_bps: DEFAULT_LOCK_TIERS[2].multiplier_bps,
+            boost_bps: 1_000,
+            governance_boost_bps: governance_boost_bps(&pool, 50_000),
+            ..Default::default()
+        };
+        update_pool(&mut pool, 86400).unwrap();
+        settle_rewards(&mut pool, &mut boosted).unwrap();
+
+        // 1.5x for the lock, 10% for the NFT and 5% for the governance lock
+        assert_eq!(boosted.reward_debt, 16_500);
+    }
+
+    #[test]
+    fn pool_accrual_clamps_long_idle_windows() {
+        let pool = pool_with_rate(10, false);
+        let ten_years = 10 * MAX_ACCRUAL_SECONDS;
+
+        assert_eq!(
+            tier_reward_per_share_at(&pool, 0, ten_years).unwrap(),
+            tier_reward_per_share_at(&pool, 0, MAX_ACCRUAL_SECONDS).unwrap()
+        );
+    }
+
+    #[test]
+    fn whale_stakes_settle_a_week_without_overflowing() {
+        let week = 7 * 86400;
+        let acc_reward_per_share = tier_reward_per_share_at(&pool_with_rate(1, false), 0, week).unwrap();
+
+        // stake * acc * multiplier is far beyond u64 but fits comfortably in u128
+        let whale = u64::MAX / 2;
+        let fits = whale / 14;
+        assert_eq!(unboosted_reward(fits, acc_reward_per_share), fits * 7);
+        assert_eq!(
+            calculate_pending_reward(fits, acc_reward_per_share, 0, 15_000, 1, 0, ROUNDING_FLOOR).unwrap().0,
+            (fits / 2 * 21) as u128
+        );
+
+        // A week at 1 token per token per day owes 3.5x u64::MAX; it settles, but can't be
+        // paid in one transfer
+        let (owed, _) = calculate_pending_reward(whale, acc_reward_per_share, 0, 10_000, 1, 0, ROUNDING_FLOOR).unwrap();
+        assert_eq!(owed, whale as u128 * 7);
+        assert!(to_token_amount(owed).is_err());
+    }
+
+    #[test]
+    fn reward_debt_grows_past_u64_without_overflowing() {
+        let mut pool = pool_with_rate(u64::MAX / 4, true);
+        let mut user_stake = UserStake {
+            stake_amount: 8,
+            lock_multiplier_bps: 10_000,
+            ..Default::default()
+        };
+
+        // Each settlement adds 2x u64::MAX; the second would overflow a u64 debt
+        update_pool(&mut pool, 1).unwrap();
+        settle_rewards(&mut pool, &mut user_stake).unwrap();
+        update_pool(&mut pool, 2).unwrap();
+        settle_rewards(&mut pool, &mut user_stake).unwrap();
+
+        let owed = (u64::MAX / 4) as u128 * 8 * 2;
+        assert_eq!(user_stake.reward_debt, owed);
+        assert_eq!(pool.total_reward_debt, owed);
+        assert!(to_token_amount(user_stake.reward_debt).is_err());
+        assert_eq!(to_token_amount((u64::MAX as u128).min(user_stake.reward_debt)).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn nothing_accrues_before_the_rewards_start_time() {
+        let pool = StakingPool {
+            rewards_start_time: 1_000,
+            ..pool_with_rate(10, false)
+        };
+
+        assert_eq!(tier_reward_per_share_at(&pool, 0, 500).unwrap(), 0);
+        assert_eq!(tier_reward_per_share_at(&pool, 0, 1_000).unwrap(), 0);
+        assert_eq!(
+            tier_reward_per_share_at(&pool, 0, 1_000 + 86400).unwrap(),
+            tier_reward_per_share_at(&pool_with_rate(10, false), 0, 86400).unwrap()
+        );
+    }
+
+    #[test]
+    fn nothing_accrues_after_the_rewards_end_time() {
+        let pool = StakingPool {
+            rewards_end_time: 86400,
+            ..pool_with_rate(10, false)
+        };
+        let at_end = tier_reward_per_share_at(&pool, 0, 86400).unwrap();
+
+        assert_eq!(at_end, tier_reward_per_share_at(&pool_with_rate(10, false), 0, 86400).unwrap());
+        assert_eq!(tier_reward_per_share_at(&pool, 0, 10 * 86400).unwrap(), at_end);
+
+        // A pool last updated after the end stays put
+        let wound_down = StakingPool {
+            last_update_time: 2 * 86400,
+            tier_acc_reward_per_share: [at_end; LOCK_TIER_COUNT],
+            ..pool
+        };
+        assert_eq!(tier_reward_per_share_at(&wound_down, 0, 3 * 86400).unwrap(), at_end);
+    }
+
+    #[test]
+    fn ancient_positions_settle_within_the_accrual_cap() {
+        let five_years = 5 * 365 * 86400;
+        let mut pool = StakingPool {
+            max_accrual_seconds: 30 * 86400,
+            ..pool_with_rate(1_000_000, false)
+        };
+        let mut user_stake = UserStake {
+            stake_amount: 1_000,
+            lock_multiplier_bps: 10_000,
+            ..Default::default()
+        };
+
+        // Untouched for five years, the position still settles, earning only the capped window
+        update_pool(&mut pool, five_years).unwrap();
+        settle_rewards(&mut pool, &mut user_stake).unwrap();
+        assert_eq!(user_stake.reward_debt, 1_000 * 1_000_000 * 30);
+        assert_eq!(pool.last_update_time, five_years);
+    }
+
+    #[test]
+    fn second_reward_stream_settles_at_its_own_rate() {
+        let mut pool = StakingPool {
+            reward_rate_2: 3,
+            ..pool_with_rate(10, false)
+        };
+        let mut user_stake = UserStake {
+            stake_amount: 1_000,
+            lock_multiplier_bps: 10_000,
+            ..Default::default()
+        };
+
+        update_pool(&mut pool, 86400).unwrap();
+        settle_rewards(&mut pool, &mut user_stake).unwrap();
+        assert_eq!(user_stake.reward_debt, 10_000);
+        assert_eq!(user_stake.reward_debt_2, 3_000);
+        assert_eq!(pool.total_reward_debt_2, 3_000);
+
+        // Without a second rate nothing accrues to the second stream
+        let mut single = pool_with_rate(10, false);
+        let mut other = UserStake {
+            stake_amount: 1_000,
+            lock_multiplier_bps: 10_000,
+            ..Default::default()
+        };
+        update_pool(&mut single, 86400).unwrap();
+        settle_rewards(&mut single, &mut other).unwrap();
+        assert_eq!(other.reward_debt_2, 0);
+    }
+
+    #[test]
+    fn reward_rate_denominator_allows_fractional_rates() {
+        let acc_reward_per_share = tier_reward_per_share_at(&pool_with_rate(1, false), 0, 86400).unwrap();
+
+        // 1 token per token per day, versus 0.001
+        let whole = calculate_pending_reward(1_000_000, acc_reward_per_share, 0, 10_000, 1, 0, ROUNDING_FLOOR).unwrap();
+        let fractional = calculate_pending_reward(1_000_000, acc_reward_per_share, 0, 10_000, 1_000, 0, ROUNDING_FLOOR).unwrap();
+        assert_eq!(whole, (1_000_000, 0));
+        assert_eq!(fractional, (1_000, 0));
+
+        // Sub-token amounts carry over instead of being lost
+        let (reward, remainder) = calculate_pending_reward(500, acc_reward_per_share, 0, 10_000, 1_000, 0, ROUNDING_FLOOR).unwrap();
+        assert_eq!(reward, 0);
+        let (reward, _) = calculate_pending_reward(500, acc_reward_per_share, 0, 10_000, 1_000, remainder, ROUNDING_FLOOR).unwrap();
+        assert_eq!(reward, 1);
+    }
+
+    #[test]
+    fn rounding_mode_settles_the_fraction_of_a_token() {
+        // 3 tokens at half a reward each earn 1.5 reward tokens
+        let half = REWARD_PRECISION / 2;
+        let settle = |rounding_mode| calculate_pending_reward(3, half, 0, 10_000, 1, 0, rounding_mode).unwrap();
+        assert_eq!(settle(ROUNDING_FLOOR), (1, REWARD_PRECISION * 10_000 / 2));
+        assert_eq!(settle(ROUNDING_NEAREST), (2, 0));
+        assert_eq!(settle(ROUNDING_CEIL), (2, 0));
+
+        // 1.4 only rounds up under ceil, and a whole amount is never rounded
+        let settle = |stake_amount, rounding_mode| {
+            calculate_pending_reward(stake_amount, REWARD_PRECISION / 5, 0, 10_000, 1, 0, rounding_mode).unwrap().0
+        };
+        assert_eq!(settle(7, ROUNDING_NEAREST), 1);
+        assert_eq!(settle(7, ROUNDING_CEIL), 2);
+        assert_eq!(settle(10, ROUNDING_CEIL), 2);
+    }
+
+    #[test]
+    fn shared_emission_splits_the_rate_between_stakers() {
+        let mut pool = StakingPool {
+            shared_emission: true,
+            ..pool_with_rate(100, false)
+        };
+
+        // Alone, a staker collects the whole day's emission
+        pool.total_staked = 1_000;
+        pool.total_weighted_stake = 1_000 * 10_000;
+        let alone = unboosted_reward(1_000, tier_reward_per_share_at(&pool, 0, 86400).unwrap());
+        assert_eq!(alone, 100);
+
+        // Two equal stakers each get half, so the pool still emits 100
+        pool.total_staked = 2_000;
+        pool.total_weighted_stake = 2_000 * 10_000;
+        let shared = unboosted_reward(1_000, tier_reward_per_share_at(&pool, 0, 86400).unwrap());
+        assert_eq!(shared, 50);
+
+        // A 2x position counts twice its stake, so the payouts still add up to the emission
+        pool.total_staked = 1_500;
+        pool.total_weighted_stake = 1_000 * 10_000 + 500 * 20_000;
+        let acc_reward_per_share = tier_reward_per_share_at(&pool, 0, 86400).unwrap();
+        let plain = unboosted_reward(1_000, acc_reward_per_share);
+        let (boosted, _) = calculate_pending_reward(500, acc_reward_per_share, 0, 20_000, 1, 0, ROUNDING_FLOOR).unwrap();
+        assert_eq!((plain, to_token_amount(boosted).unwrap()), (50, 50));
+
+        // Nothing accrues to an empty pool
+        pool.total_staked = 0;
+        pool.total_weighted_stake = 0;
+        assert_eq!(tier_reward_per_share_at(&pool, 0, 86400).unwrap(), 0);
+
+        // Fixed-rate pools ignore total_staked
+        let fixed = pool_with_rate(100, false);
+        assert_eq!(unboosted_reward(1_000, tier_reward_per_share_at(&fixed, 0, 86400).unwrap()), 100_000);
+    }
+
+    #[test]
+    fn normalized_rates_are_per_whole_staked_token() {
+        // 6-decimal stake mint paying 1,000 reward base units per whole token per day
+        let pool = StakingPool {
+            normalize_decimals: true,
+            stake_decimals: 6,
+            ..pool_with_rate(1_000, false)
+        };
+        let acc_reward_per_share = tier_reward_per_share_at(&pool, 0, 86400).unwrap();
+        let denominator = rate_denominator(&pool).unwrap();
+        assert_eq!(denominator, 1_000_000);
+
+        // Two whole tokens earn 2,000, and half a token 500
+        let (reward, _) = calculate_pending_reward(2_000_000, acc_reward_per_share, 0, 10_000, denominator, 0, ROUNDING_FLOOR).unwrap();
+        assert_eq!(reward, 2_000);
+        let (reward, _) = calculate_pending_reward(500_000, acc_reward_per_share, 0, 10_000, denominator, 0, ROUNDING_FLOOR).unwrap();
+        assert_eq!(reward, 500);
+
+        // Without normalization the rate applies per base unit
+        assert_eq!(rate_denominator(&pool_with_rate(1_000, false)).unwrap(), 1);
+    }
+
+    #[test]
+    fn future_checkpoint_counts_as_no_elapsed_time() {
+        let now = 1_700_000_000;
+        assert_eq!(elapsed_since(now, now - 30).unwrap(), 30);
+        assert_eq!(elapsed_since(now, now + 10).unwrap(), 0);
+        assert_eq!(elapsed_since(now, now + MAX_CLOCK_DRIFT_SECONDS).unwrap(), 0);
+        assert!(elapsed_since(now, now + MAX_CLOCK_DRIFT_SECONDS + 1).is_err());
+    }
+
+    #[test]
+    fn entry_time_blends_by_stake() {
+        let mut user_stake = UserStake::default();
+
+        // The first deposit enters at the deposit time
+        assert_eq!(blended_entry_time(&user_stake, 1_000, 1_000).unwrap(), 1_000);
+
+        user_stake.stake_amount = 1_000;
+        user_stake.average_entry_time = 1_000;
+
+        // An equal top-up lands halfway; a small one barely moves it
+        assert_eq!(blended_entry_time(&user_stake, 1_000, 2_000).unwrap(), 1_500);
+        assert_eq!(blended_entry_time(&user_stake, 10, 2_000).unwrap(), 1_009);
+
+        // Adding nothing keeps the existing entry time
+        assert_eq!(blended_entry_time(&user_stake, 0, 2_000).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn pool_account_round_trips_in_its_declared_space() {
+        let pool = StakingPool {
+            pool_id: 7,
+            admin: Pubkey::new_unique(),
+            reward_mint_2: Pubkey::new_unique(),
+            acc_reward_per_share: u128::MAX,
+            lock_tiers: DEFAULT_LOCK_TIERS,
+            ..pool_with_rate(10, true)
+        };
+
+        let mut data = Vec::new();
+        pool.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), 8 + StakingPool::SIZE);
+
+        let decoded = StakingPool::try_deserialize(&mut data.as_slice()).unwrap();
+        assert_eq!(decoded.pool_id, 7);
+        assert_eq!(decoded.admin, pool.admin);
+        assert_eq!(decoded.reward_mint_2, pool.reward_mint_2);
+        assert_eq!(decoded.acc_reward_per_share, u128::MAX);
+        assert_eq!(decoded.lock_tiers[3].lock_days, DEFAULT_LOCK_TIERS[3].lock_days);
+        assert_eq!(decoded.reward_rate, 10);
+    }
+
+    #[test]
+    fn user_stake_account_round_trips_in_its_declared_space() {
+        // Every Option is Some, the largest encoding
+        let user_stake = UserStake {
+            owner: Pubkey::new_unique(),
+            position_id: 3,
+            stake_amount: u64::MAX,
+            reward_remainder_2: u128::MAX,
+            delegate: Some(Pubkey::new_unique()),
+            reward_destination: Some(Pubkey::new_unique()),
+            last_claim_time_2: i64::MAX,
+            total_claimed: 42,
+            snapshot_epoch: Some(9),
+            ..Default::default()
+        };
+
+        let mut data = Vec::new();
+        user_stake.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), 8 + UserStake::SIZE);
+
+        let decoded = UserStake::try_deserialize(&mut data.as_slice()).unwrap();
+        assert_eq!(decoded.owner, user_stake.owner);
+        assert_eq!(decoded.position_id, 3);
+        assert_eq!(decoded.stake_amount, u64::MAX);
+        assert_eq!(decoded.reward_remainder_2, u128::MAX);
+        assert_eq!(decoded.delegate, user_stake.delegate);
+        assert_eq!(decoded.reward_destination, user_stake.reward_destination);
+        assert_eq!(decoded.last_claim_time_2, i64::MAX);
+        assert_eq!(decoded.total_claimed, 42);
+        assert_eq!(decoded.snapshot_epoch, Some(9));
+    }
+
+    #[test]
+    fn penalty_is_taken_in_basis_points() {
+        assert_eq!(calculate_bps_share(1_000, 0).unwrap(), 0);
+        assert_eq!(calculate_bps_share(1_000, 500).unwrap(), 50);
+        assert_eq!(calculate_bps_share(1_000, 10_000).unwrap(), 1_000);
+        assert_eq!(calculate_bps_share(u64::MAX, 10_000).unwrap(), u64::MAX);
+    }
+
+    fn pool_with_schedule(initial_rate: u64, decay_bps_per_period: u16) -> StakingPool {
+        StakingPool {
+            rate_schedule_enabled: true,
+            initial_rate,
+            decay_bps_per_period,
+            period_seconds: 86400,
+            reward_rate_denominator: 1,
+            max_accrual_seconds: MAX_ACCRUAL_SECONDS,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scheduled_rate_decays_once_per_period() {
+        let pool = pool_with_schedule(1_000_000, 1_000);
+
+        assert_eq!(scheduled_rate_at(&pool, 0).unwrap(), 1_000_000);
+        assert_eq!(scheduled_rate_at(&pool, 86400 - 1).unwrap(), 1_000_000);
+        assert_eq!(scheduled_rate_at(&pool, 86400).unwrap(), 900_000);
+        assert_eq!(scheduled_rate_at(&pool, 5 * 86400).unwrap(), 590_490);
+        assert_eq!(scheduled_rate_at(&pool_with_schedule(1_000_000, 10_000), 86400).unwrap(), 0);
+    }
+
+    #[test]
+    fn effective_rate_follows_the_schedule_inside_the_window() {
+        let mut pool = pool_with_schedule(1_000_000, 1_000);
+        pool.rewards_start_time = 100;
+        pool.rewards_end_time = 10 * 86400;
+
+        assert_eq!(effective_rate_at(&pool, 99).unwrap(), 0);
+        assert_eq!(effective_rate_at(&pool, 100).unwrap(), 1_000_000);
+        assert_eq!(effective_rate_at(&pool, 86400).unwrap(), 900_000);
+        assert_eq!(effective_rate_at(&pool, 2 * 86400 + 1).unwrap(), 810_000);
+        assert_eq!(effective_rate_at(&pool, 5 * 86400).unwrap(), 590_490);
+        assert_eq!(effective_rate_at(&pool, 10 * 86400).unwrap(), 0);
+
+        // Flat pools report reward_rate, and a zero end time never closes the window
+        pool.rate_schedule_enabled = false;
+        pool.reward_rate = 42;
+        pool.rewards_end_time = 0;
+        assert_eq!(effective_rate_at(&pool, 100 * 86400).unwrap(), 42);
+    }
+
+    #[test]
+    fn frozen_accrual_stops_at_the_freeze() {
+        let mut pool = pool_with_rate(86400, true);
+        pool.total_staked = 1;
+        pool.last_update_time = 100;
+        pool.accrual_frozen_at = 160;
+
+        // Only the 60 seconds before the freeze count, however long it lasts
+        let (_, time_passed) = accrual_window(&pool, 1_000).unwrap();
+        assert_eq!(time_passed, 60);
+
+        pool.last_update_time = 1_000;
+        let (_, time_passed) = accrual_window(&pool, 2_000).unwrap();
+        assert_eq!(time_passed, 0);
+        assert_eq!(effective_rate_at(&pool, 2_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn scheduled_accrual_uses_each_periods_rate() {
+        let mut pool = pool_with_schedule(1_000, 1_000);
+        pool.last_update_time = 43200;
+
+        // Half a day at 1000/day, then half a day at 900/day
+        let expected = (500 + 450) * REWARD_PRECISION;
+        assert_eq!(tier_reward_per_share_at(&pool, 0, 43200 + 86400).unwrap(), expected);
+    }
+
+    #[test]
+    fn per_second_reward_overflow_is_an_error() {
+        let pool = pool_with_rate(u64::MAX, true);
+        assert!(tier_reward_per_share_at(&pool, 0, MAX_ACCRUAL_SECONDS).is_err());
+    }
 }
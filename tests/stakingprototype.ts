@@ -5,11 +5,28 @@ import { expect } from "chai";
 import { PublicKey, Keypair, SystemProgram, SYSVAR_RENT_PUBKEY } from "@solana/web3.js";
 import { 
   TOKEN_PROGRAM_ID, 
+  TOKEN_2022_PROGRAM_ID,
   ASSOCIATED_TOKEN_PROGRAM_ID, 
   createMint, 
   createAccount,
   mintTo,
-  getAccount
+  getAccount,
+  ExtensionType,
+  getMintLen,
+  createInitializeTransferFeeConfigInstruction,
+  createInitializePermanentDelegateInstruction,
+  createInitializeMintInstruction,
+  createInitializeGroupPointerInstruction,
+  createInitializeGroupMemberPointerInstruction,
+  tokenGroupInitializeGroupWithRentTransfer,
+  tokenGroupMemberInitializeWithRentTransfer,
+  freezeAccount,
+  thawAccount,
+  burn,
+  closeAccount,
+  getAssociatedTokenAddressSync,
+  getMint,
+  transfer
 } from "@solana/spl-token";
 
 describe("stakingprototype", () => {
@@ -33,9 +50,387 @@ describe("stakingprototype", () => {
   let userStakeAccount: PublicKey;
   let userRewardAccount: PublicKey;
   
+  const poolId = new anchor.BN(0);
   const rewardRate = new anchor.BN(10);
+  const lockDuration = new anchor.BN(0);
+  const rewardRateIsPerSecond = false;
+  const minStakeAmount = new anchor.BN(0);
+  const earlyUnstakePenaltyBps = 500;
+  const maxTotalRewards = new anchor.BN(0);
+  const cooldownSeconds = new anchor.BN(0);
+  const maxTotalStaked = new anchor.BN(0);
+  const maxRewardRate = new anchor.BN(0);
+  const rateTimelock = new anchor.BN(0);
+  const rewardsStartTime = new anchor.BN(0);
+  const rewardsEndTime = new anchor.BN(0);
+  const rewardRateDenominator = new anchor.BN(1);
+  const sharedEmission = false;
+  const normalizeDecimals = false;
+  const rewardEqualsStake = false;
   const stakeAmount = new anchor.BN(1000);
+  const noLock = new anchor.BN(0);
+  const positionId = new anchor.BN(0);
   
+  // Creates a wallet with SOL, stake and reward token accounts, and `amount` stake tokens
+  const createFundedUser = async (amount: number) => {
+    const connection = provider.connection;
+    const wallet = Keypair.generate();
+    const signature = await connection.requestAirdrop(
+      wallet.publicKey,
+      2 * anchor.web3.LAMPORTS_PER_SOL
+    );
+    await connection.confirmTransaction(signature);
+
+    const stakeAccount = await createAccount(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      stakeMint,
+      wallet.publicKey
+    );
+
+    const rewardAccount = await createAccount(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      wallet.publicKey
+    );
+
+    await mintTo(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      stakeMint,
+      stakeAccount,
+      adminWallet.publicKey,
+      amount
+    );
+
+    const [stakePda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("user-stake"),
+        stakingPoolPda.toBuffer(),
+        wallet.publicKey.toBuffer(),
+        positionId.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    );
+
+    return { wallet, stakeAccount, rewardAccount, stakePda };
+  };
+
+  const defaultPoolConfig = () => ({
+    rewardRate,
+    lockDuration,
+    rewardRateIsPerSecond,
+    minStakeAmount,
+    earlyUnstakePenaltyBps,
+    maxTotalRewards,
+    cooldownSeconds,
+    maxTotalStaked,
+    maxRewardRate,
+    rateTimelock,
+    rewardsStartTime,
+    rewardsEndTime,
+    rewardRateDenominator,
+    sharedEmission,
+    normalizeDecimals,
+    rewardEqualsStake,
+  });
+
+  type PoolConfig = ReturnType<typeof defaultPoolConfig>;
+
+  type PoolMints = {
+    stakeMint?: PublicKey;
+    rewardMint?: PublicKey;
+    stakeTokenProgram?: PublicKey;
+    rewardTokenProgram?: PublicKey;
+  };
+
+  // Stake and reward vaults `initialize` creates for the pool at `pda`
+  const poolVaultsFor = (pda: PublicKey) =>
+    ["pool_stake", "pool_reward"].map(
+      (seed) => anchor.web3.PublicKey.findProgramAddressSync([Buffer.from(seed), pda.toBuffer()], program.programId)[0]
+    );
+
+  // Initializes pool `id` with `overrides`; the program creates the pool's vaults.
+  // `mints` swaps in other mints (and their token programs) for the shared test mints.
+  const createPool = async (
+    id: number,
+    overrides: Partial<PoolConfig> = {},
+    mints: PoolMints = {}
+  ) => {
+    const poolStakeMint = mints.stakeMint ?? stakeMint;
+    const poolRewardMint = mints.rewardMint ?? rewardMint;
+    const stakeTokenProgram = mints.stakeTokenProgram ?? TOKEN_PROGRAM_ID;
+    const rewardTokenProgram = mints.rewardTokenProgram ?? TOKEN_PROGRAM_ID;
+    const connection = provider.connection;
+    const poolIdBn = new anchor.BN(id);
+    const [pda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("staking_pool"), poolIdBn.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+
+    const [stakeAccount, rewardAccount] = poolVaultsFor(pda);
+
+    const config = { ...defaultPoolConfig(), ...overrides };
+    const signature = await program.methods
+      .initialize(poolIdBn, config)
+      .accounts({
+        stakingPool: pda,
+        admin: adminWallet.publicKey,
+        stakeMint: poolStakeMint,
+        rewardMint: poolRewardMint,
+        poolStakeAccount: stakeAccount,
+        poolRewardAccount: rewardAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: stakeTokenProgram,
+        rewardTokenProgram,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+
+    return {
+      id: poolIdBn,
+      pda,
+      stakeAccount,
+      rewardAccount,
+      stakeMint: poolStakeMint,
+      rewardMint: poolRewardMint,
+      stakeTokenProgram,
+      rewardTokenProgram,
+      signature,
+    };
+  };
+
+  type Pool = Awaited<ReturnType<typeof createPool>>;
+  type User = Awaited<ReturnType<typeof createFundedUser>>;
+
+  const userStakePdaFor = (pool: Pool, wallet: PublicKey, position = 0) =>
+    anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("user-stake"),
+        pool.pda.toBuffer(),
+        wallet.toBuffer(),
+        new anchor.BN(position).toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )[0];
+
+  const userAccountPdaFor = (wallet: PublicKey) =>
+    anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("user-account"), wallet.toBuffer()],
+      program.programId
+    )[0];
+
+  const poolMemberPdaFor = (poolPda: PublicKey, wallet: PublicKey) =>
+    anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("pool-member"), poolPda.toBuffer(), wallet.toBuffer()],
+      program.programId
+    )[0];
+
+  const blacklistPdaFor = (poolPda: PublicKey, wallet: PublicKey) =>
+    anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("blacklist"), poolPda.toBuffer(), wallet.toBuffer()],
+      program.programId
+    )[0];
+
+  const fundPoolRewards = (pool: Pool, amount: number) =>
+    mintTo(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      pool.rewardMint,
+      pool.rewardAccount,
+      adminWallet.publicKey,
+      amount,
+      [],
+      undefined,
+      pool.rewardTokenProgram
+    );
+
+  type StakeOptions = {
+    lockDays?: number;
+    position?: number;
+    whitelist?: PublicKey;
+    referrer?: PublicKey;
+    referrerStake?: PublicKey;
+    feeAccount?: PublicKey;
+    nft?: { mint: PublicKey; account: PublicKey; escrow: PublicKey };
+    positionOwner?: PublicKey;
+    payer?: Keypair;
+  };
+
+  const stakeBuilder = (pool: Pool, user: User, amount: number | anchor.BN, options: StakeOptions = {}) => {
+    const { lockDays = 0, position = 0 } = options;
+    return program.methods
+      .stake(
+        pool.id,
+        new anchor.BN(position),
+        new anchor.BN(amount),
+        new anchor.BN(lockDays),
+        options.referrer ?? null
+      )
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, options.positionOwner ?? user.wallet.publicKey, position),
+        userAccount: userAccountPdaFor(options.positionOwner ?? user.wallet.publicKey),
+        poolMember: poolMemberPdaFor(pool.pda, options.positionOwner ?? user.wallet.publicKey),
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, options.positionOwner ?? user.wallet.publicKey),
+        payer: options.payer?.publicKey ?? user.wallet.publicKey,
+        positionOwner: options.positionOwner ?? null,
+        userTokenAccount: user.stakeAccount,
+        poolStakeAccount: pool.stakeAccount,
+        systemProgram: SystemProgram.programId,
+        stakeMint: pool.stakeMint,
+        nftMint: options.nft?.mint ?? null,
+        nftTokenAccount: options.nft?.account ?? null,
+        nftEscrowAccount: options.nft?.escrow ?? null,
+        nftTokenProgram: options.nft ? TOKEN_2022_PROGRAM_ID : null,
+        feeTokenAccount: options.feeAccount ?? null,
+        referrerStake: options.referrerStake ?? null,
+        whitelist: options.whitelist ?? null,
+        tokenProgram: pool.stakeTokenProgram,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+      })
+      .signers(options.payer ? [user.wallet, options.payer] : [user.wallet]);
+  };
+
+  const stakeInto = (pool: Pool, user: User, amount: number | anchor.BN, options: StakeOptions = {}) =>
+    stakeBuilder(pool, user, amount, options).rpc();
+
+  const unstakeFrom = (
+    pool: Pool,
+    user: User,
+    amount: number | anchor.BN,
+    position = 0,
+    feeAccount: PublicKey | null = null
+  ) =>
+    program.methods
+      .unstake(pool.id, new anchor.BN(position), new anchor.BN(amount))
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey, position),
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+        receiptTokenAccount: null,
+        userTokenAccount: user.stakeAccount,
+        poolStakeAccount: pool.stakeAccount,
+        systemProgram: SystemProgram.programId,
+        stakeMint: pool.stakeMint,
+        feeTokenAccount: feeAccount,
+        tokenProgram: pool.stakeTokenProgram,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+  // Claims everything, or at most `amount` when given
+  const claimBuilder = (
+    pool: Pool,
+    user: User,
+    amount?: number | anchor.BN,
+    position = 0,
+    receipt: PublicKey | null = null
+  ) =>
+    (amount === undefined
+      ? program.methods.claimRewards(pool.id, new anchor.BN(position))
+      : program.methods.claimRewardsPartial(pool.id, new anchor.BN(position), new anchor.BN(amount))
+    )
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey, position),
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+        receiptTokenAccount: receipt,
+        rewardOwner: user.wallet.publicKey,
+        userRewardAccount: user.rewardAccount,
+        poolRewardAccount: pool.rewardAccount,
+        systemProgram: SystemProgram.programId,
+        rewardMint: pool.rewardMint,
+        tokenProgram: pool.rewardTokenProgram,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user.wallet]);
+
+  const claimFrom = (pool: Pool, user: User, amount?: number | anchor.BN, position = 0) =>
+    claimBuilder(pool, user, amount, position).rpc();
+
+  const compoundFor = (pool: Pool, user: User) =>
+    program.methods
+      .compound(pool.id, positionId)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey),
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+        receiptTokenAccount: null,
+        poolStakeAccount: pool.stakeAccount,
+        poolRewardAccount: pool.rewardAccount,
+        rewardMint: pool.rewardMint,
+        tokenProgram: pool.rewardTokenProgram,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+  const restakeFor = (pool: Pool, user: User, amount: number) =>
+    program.methods
+      .restakeRewards(pool.id, positionId, new anchor.BN(amount))
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey),
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+        receiptTokenAccount: null,
+        poolStakeAccount: pool.stakeAccount,
+        poolRewardAccount: pool.rewardAccount,
+        rewardMint: pool.rewardMint,
+        tokenProgram: pool.rewardTokenProgram,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+  // Sends several instructions in one transaction so they share a timestamp
+  const sendTogether = async (
+    builders: { instruction: () => Promise<anchor.web3.TransactionInstruction> }[],
+    signers: Keypair[]
+  ) => {
+    const tx = new anchor.web3.Transaction();
+    for (const builder of builders) {
+      tx.add(await builder.instruction());
+    }
+    return (provider as anchor.AnchorProvider).sendAndConfirm(tx, signers);
+  };
+
+  // Runs `fn` and asserts it fails with the custom program error `code`
+  const proposeRewardRate = (id: anchor.BN, pda: PublicKey, rate: number) =>
+    program.methods
+      .proposeRewardRate(id, new anchor.BN(rate))
+      .accounts({
+        stakingPool: pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+  const applyRewardRate = (id: anchor.BN, pda: PublicKey) =>
+    program.methods
+      .applyRewardRate(id)
+      .accounts({
+        stakingPool: pda,
+        admin: adminWallet.publicKey,
+        poolRewardAccount: poolVaultsFor(pda)[1],
+      })
+      .rpc();
+
+  const expectError = async (fn: () => Promise<unknown>, code: string) => {
+    try {
+      await fn();
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal(code);
+      return;
+    }
+    expect.fail(`expected ${code}`);
+  };
+
   before(async () => {
     // Airdrop SOL to the user wallet
     const connection = provider.connection;
@@ -47,12 +442,17 @@ describe("stakingprototype", () => {
     
     // Derive PDAs
     [stakingPoolPda, stakingPoolBump] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("staking_pool")],
+      [Buffer.from("staking_pool"), poolId.toArrayLike(Buffer, "le", 8)],
       program.programId
     );
     
     [userStakePda] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("user-stake"), userWallet.publicKey.toBuffer()],
+      [
+        Buffer.from("user-stake"),
+        stakingPoolPda.toBuffer(),
+        userWallet.publicKey.toBuffer(),
+        positionId.toArrayLike(Buffer, "le", 8),
+      ],
       program.programId
     );
     
@@ -73,28 +473,8 @@ describe("stakingprototype", () => {
       9
     );
     
-    // Create pool token accounts using normal accounts (not ATAs) for PDAs
-    const poolStakeAccountKeypair = Keypair.generate();
-    poolStakeAccount = poolStakeAccountKeypair.publicKey;
-    
-    await createAccount(
-      connection,
-      (adminWallet as anchor.Wallet).payer,
-      stakeMint,
-      stakingPoolPda,
-      poolStakeAccountKeypair
-    );
-    
-    const poolRewardAccountKeypair = Keypair.generate();
-    poolRewardAccount = poolRewardAccountKeypair.publicKey;
-    
-    await createAccount(
-      connection,
-      (adminWallet as anchor.Wallet).payer,
-      rewardMint,
-      stakingPoolPda,
-      poolRewardAccountKeypair
-    );
+    // The pool's vaults are PDAs that initialize creates
+    [poolStakeAccount, poolRewardAccount] = poolVaultsFor(stakingPoolPda);
     
     // Create user token accounts
     userStakeAccount = await createAccount(
@@ -121,19 +501,11 @@ describe("stakingprototype", () => {
       10000
     );
     
-    await mintTo(
-      connection,
-      (adminWallet as anchor.Wallet).payer,
-      rewardMint,
-      poolRewardAccount,
-      adminWallet.publicKey,
-      10000
-    );
   });
 
   it("Initialize the staking pool", async () => {
     const tx = await program.methods
-      .initialize(rewardRate)
+      .initialize(poolId, defaultPoolConfig())
       .accounts({
         stakingPool: stakingPoolPda,
         admin: adminWallet.publicKey,
@@ -143,17 +515,44 @@ describe("stakingprototype", () => {
         poolRewardAccount,
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
+        rewardTokenProgram: TOKEN_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         rent: SYSVAR_RENT_PUBKEY,
       })
       .rpc();
     
     console.log("Your transaction signature", tx);
+
+    // Seed the new reward vault for the tests that follow
+    await mintTo(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      poolRewardAccount,
+      adminWallet.publicKey,
+      10000
+    );
     
     // Verify the staking pool was initialized correctly
     const stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
+    expect(stakingPool.poolId.toNumber()).to.equal(poolId.toNumber());
     expect(stakingPool.admin.toString()).to.equal(adminWallet.publicKey.toString());
     expect(stakingPool.rewardRate.toNumber()).to.equal(rewardRate.toNumber());
+    expect(stakingPool.rewardRateIsPerSecond).to.equal(rewardRateIsPerSecond);
+    expect(stakingPool.lockDuration.toNumber()).to.equal(lockDuration.toNumber());
+    expect(stakingPool.minStakeAmount.toNumber()).to.equal(minStakeAmount.toNumber());
+    expect(stakingPool.earlyUnstakePenaltyBps).to.equal(earlyUnstakePenaltyBps);
+    expect(stakingPool.maxTotalRewards.toNumber()).to.equal(maxTotalRewards.toNumber());
+    expect(stakingPool.cooldownSeconds.toNumber()).to.equal(cooldownSeconds.toNumber());
+    expect(stakingPool.maxTotalStaked.toNumber()).to.equal(maxTotalStaked.toNumber());
+    expect(stakingPool.maxRewardRate.toNumber()).to.equal(maxRewardRate.toNumber());
+    expect(stakingPool.rateTimelock.toNumber()).to.equal(rateTimelock.toNumber());
+    expect(stakingPool.rewardsStartTime.toNumber()).to.equal(rewardsStartTime.toNumber());
+    expect(stakingPool.rewardsEndTime.toNumber()).to.equal(rewardsEndTime.toNumber());
+    expect(stakingPool.rewardRateDenominator.toNumber()).to.equal(rewardRateDenominator.toNumber());
+    expect(stakingPool.sharedEmission).to.equal(sharedEmission);
+    expect(stakingPool.normalizeDecimals).to.equal(normalizeDecimals);
+    expect(stakingPool.rewardEqualsStake).to.equal(rewardEqualsStake);
     expect(stakingPool.totalStaked.toNumber()).to.equal(0);
     expect(stakingPool.stakeMint.toString()).to.equal(stakeMint.toString());
     expect(stakingPool.rewardMint.toString()).to.equal(rewardMint.toString());
@@ -167,14 +566,27 @@ describe("stakingprototype", () => {
     const poolBalanceBefore = (await getAccount(provider.connection, poolStakeAccount)).amount;
     
     const tx = await program.methods
-      .stake(stakeAmount)
+      .stake(poolId, positionId, stakeAmount, noLock, null)
       .accounts({
         stakingPool: stakingPoolPda,
         userStake: userStakePda,
+        userAccount: userAccountPdaFor(userWallet.publicKey),
+        poolMember: poolMemberPdaFor(stakingPoolPda, userWallet.publicKey),
         user: userWallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, userWallet.publicKey),
+        payer: userWallet.publicKey,
         userTokenAccount: userStakeAccount,
         poolStakeAccount,
         systemProgram: SystemProgram.programId,
+        stakeMint,
+        nftMint: null,
+        nftTokenAccount: null,
+        nftEscrowAccount: null,
+        nftTokenProgram: null,
+        positionOwner: null,
+        feeTokenAccount: null,
+        referrerStake: null,
+        whitelist: null,
         tokenProgram: TOKEN_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         rent: SYSVAR_RENT_PUBKEY,
@@ -188,6 +600,9 @@ describe("stakingprototype", () => {
     const userStake = await program.account.userStake.fetch(userStakePda);
     expect(userStake.owner.toString()).to.equal(userWallet.publicKey.toString());
     expect(userStake.stakeAmount.toNumber()).to.equal(stakeAmount.toNumber());
+    expect(userStake.unlockTime.toNumber()).to.equal(
+      userStake.lastStakeTime.toNumber() + lockDuration.toNumber()
+    );
     
     // Verify the staking pool total was updated
     const stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
@@ -209,14 +624,18 @@ describe("stakingprototype", () => {
     const poolBalanceBefore = (await getAccount(provider.connection, poolStakeAccount)).amount;
     
     const tx = await program.methods
-      .unstake(unstakeAmount)
+      .unstake(poolId, positionId, unstakeAmount)
       .accounts({
         stakingPool: stakingPoolPda,
         userStake: userStakePda,
         user: userWallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, userWallet.publicKey),
+        receiptTokenAccount: null,
         userTokenAccount: userStakeAccount,
         poolStakeAccount,
         systemProgram: SystemProgram.programId,
+        stakeMint,
+        feeTokenAccount: null,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .signers([userWallet])
@@ -228,9 +647,10 @@ describe("stakingprototype", () => {
     const userStake = await program.account.userStake.fetch(userStakePda);
     expect(userStake.stakeAmount.toNumber()).to.equal(stakeAmount.sub(unstakeAmount).toNumber());
     
-    // Verify the staking pool total was updated
+    // Verify the staking pool total was updated; an unlocked position pays no penalty
     const stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
     expect(stakingPool.totalStaked.toNumber()).to.equal(stakeAmount.sub(unstakeAmount).toNumber());
+    expect(stakingPool.totalForfeited.toNumber()).to.equal(0);
     
     // Verify token balances
     const userBalanceAfter = (await getAccount(provider.connection, userStakeAccount)).amount;
@@ -250,14 +670,27 @@ describe("stakingprototype", () => {
     const additionalStakeAmount = new anchor.BN(9000);
     
     await program.methods
-      .stake(additionalStakeAmount)
+      .stake(poolId, positionId, additionalStakeAmount, noLock, null)
       .accounts({
         stakingPool: stakingPoolPda,
         userStake: userStakePda,
+        userAccount: userAccountPdaFor(userWallet.publicKey),
+        poolMember: poolMemberPdaFor(stakingPoolPda, userWallet.publicKey),
         user: userWallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, userWallet.publicKey),
+        payer: userWallet.publicKey,
         userTokenAccount: userStakeAccount,
         poolStakeAccount,
         systemProgram: SystemProgram.programId,
+        stakeMint,
+        nftMint: null,
+        nftTokenAccount: null,
+        nftEscrowAccount: null,
+        nftTokenProgram: null,
+        positionOwner: null,
+        feeTokenAccount: null,
+        referrerStake: null,
+        whitelist: null,
         tokenProgram: TOKEN_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         rent: SYSVAR_RENT_PUBKEY,
@@ -273,15 +706,20 @@ describe("stakingprototype", () => {
     const poolRewardBefore = (await getAccount(provider.connection, poolRewardAccount)).amount;
     
     const tx = await program.methods
-      .claimRewards()
+      .claimRewards(poolId, positionId)
       .accounts({
         stakingPool: stakingPoolPda,
         userStake: userStakePda,
         user: userWallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, userWallet.publicKey),
+        receiptTokenAccount: null,
+        rewardOwner: userWallet.publicKey,
         userRewardAccount,
         poolRewardAccount,
         systemProgram: SystemProgram.programId,
+        rewardMint,
         tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       })
       .signers([userWallet])
       .rpc();
@@ -302,19 +740,4387 @@ describe("stakingprototype", () => {
 
   it("Lets an admin update the reward rate", async () => {
     const newRewardRate = new anchor.BN(20);
+
+    // The default pool has no timelock, so the proposal applies right away
+    await proposeRewardRate(poolId, stakingPoolPda, newRewardRate.toNumber());
+    const tx = await applyRewardRate(poolId, stakingPoolPda);
     
-    const tx = await program.methods
-      .updateRewardRate(newRewardRate)
+    console.log("Apply reward rate transaction signature", tx);
+    
+    // Verify the reward rate was updated
+    const stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
+    expect(stakingPool.rewardRate.toNumber()).to.equal(newRewardRate.toNumber());
+  });
+
+  it("Rejects stakes while the pool is paused", async () => {
+    await program.methods
+      .setPaused(poolId, true)
       .accounts({
         stakingPool: stakingPoolPda,
         admin: adminWallet.publicKey,
       })
       .rpc();
-    
-    console.log("Update reward rate transaction signature", tx);
-    
-    // Verify the reward rate was updated
+
     const stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
-    expect(stakingPool.rewardRate.toNumber()).to.equal(newRewardRate.toNumber());
+    expect(stakingPool.paused).to.equal(true);
+
+    try {
+      await program.methods
+        .stake(poolId, positionId, new anchor.BN(100), noLock, null)
+        .accounts({
+          stakingPool: stakingPoolPda,
+          userStake: userStakePda,
+          userAccount: userAccountPdaFor(userWallet.publicKey),
+          poolMember: poolMemberPdaFor(stakingPoolPda, userWallet.publicKey),
+          user: userWallet.publicKey,
+          blacklist: blacklistPdaFor(stakingPoolPda, userWallet.publicKey),
+          payer: userWallet.publicKey,
+          userTokenAccount: userStakeAccount,
+          poolStakeAccount,
+          systemProgram: SystemProgram.programId,
+          stakeMint,
+          nftMint: null,
+          nftTokenAccount: null,
+          nftEscrowAccount: null,
+          nftTokenProgram: null,
+        nftEscrowAccount: null,
+        nftTokenProgram: null,
+          positionOwner: null,
+          feeTokenAccount: null,
+          referrerStake: null,
+          whitelist: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .signers([userWallet])
+        .rpc();
+      expect.fail("stake should fail while paused");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("PoolPaused");
+    }
+  });
+
+  it("Still lets a user unstake while the pool is paused", async () => {
+    const unstakeAmount = new anchor.BN(100);
+    const userStakeBefore = await program.account.userStake.fetch(userStakePda);
+
+    await program.methods
+      .unstake(poolId, positionId, unstakeAmount)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        userStake: userStakePda,
+        user: userWallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, userWallet.publicKey),
+        receiptTokenAccount: null,
+        userTokenAccount: userStakeAccount,
+        poolStakeAccount,
+        systemProgram: SystemProgram.programId,
+        stakeMint,
+        feeTokenAccount: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([userWallet])
+      .rpc();
+
+    const userStakeAfter = await program.account.userStake.fetch(userStakePda);
+    expect(userStakeAfter.stakeAmount.toNumber()).to.equal(
+      userStakeBefore.stakeAmount.sub(unstakeAmount).toNumber()
+    );
+
+    await program.methods
+      .setPaused(poolId, false)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    const stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
+    expect(stakingPool.paused).to.equal(false);
+  });
+
+  it("Transfers admin through propose and accept", async () => {
+    const newAdmin = Keypair.generate();
+
+    await program.methods
+      .proposeAdmin(poolId, newAdmin.publicKey)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    let stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
+    expect(stakingPool.pendingAdmin.toString()).to.equal(newAdmin.publicKey.toString());
+    expect(stakingPool.admin.toString()).to.equal(adminWallet.publicKey.toString());
+
+    // Only the proposed admin may accept
+    try {
+      await program.methods
+        .acceptAdmin(poolId)
+        .accounts({
+          stakingPool: stakingPoolPda,
+          newAdmin: userWallet.publicKey,
+        })
+        .signers([userWallet])
+        .rpc();
+      expect.fail("accept_admin should fail for the wrong signer");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+
+    await program.methods
+      .acceptAdmin(poolId)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        newAdmin: newAdmin.publicKey,
+      })
+      .signers([newAdmin])
+      .rpc();
+
+    stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
+    expect(stakingPool.admin.toString()).to.equal(newAdmin.publicKey.toString());
+    expect(stakingPool.pendingAdmin.toString()).to.equal(PublicKey.default.toString());
+
+    // Hand control back so the remaining tests can keep using the provider wallet
+    await program.methods
+      .proposeAdmin(poolId, adminWallet.publicKey)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        admin: newAdmin.publicKey,
+      })
+      .signers([newAdmin])
+      .rpc();
+
+    await program.methods
+      .acceptAdmin(poolId)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        newAdmin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
+    expect(stakingPool.admin.toString()).to.equal(adminWallet.publicKey.toString());
+  });
+
+  it("Reports pending rewards without claiming", async () => {
+    // Let some rewards accrue on the existing position
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    const userStakeBefore = await program.account.userStake.fetch(userStakePda);
+    const pending = await program.methods
+      .getPendingRewards(poolId, positionId)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        userStake: userStakePda,
+      })
+      .view();
+
+    expect(pending.toNumber()).to.be.greaterThan(0);
+
+    // The view must not touch any state
+    const userStakeAfter = await program.account.userStake.fetch(userStakePda);
+    expect(userStakeAfter.rewardDebt.toNumber()).to.equal(userStakeBefore.rewardDebt.toNumber());
+    expect(userStakeAfter.lastStakeTime.toNumber()).to.equal(userStakeBefore.lastStakeTime.toNumber());
+
+    const userRewardBefore = (await getAccount(provider.connection, userRewardAccount)).amount;
+
+    await program.methods
+      .claimRewards(poolId, positionId)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        userStake: userStakePda,
+        user: userWallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, userWallet.publicKey),
+        receiptTokenAccount: null,
+        rewardOwner: userWallet.publicKey,
+        userRewardAccount,
+        poolRewardAccount,
+        systemProgram: SystemProgram.programId,
+        rewardMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userWallet])
+      .rpc();
+
+    const userRewardAfter = (await getAccount(provider.connection, userRewardAccount)).amount;
+    const claimed = Number(userRewardAfter) - Number(userRewardBefore);
+
+    // The claim may land a second later than the view, so allow one second of accrual
+    const stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
+    const perSecond = Math.ceil(
+      (userStakeBefore.stakeAmount.toNumber() * stakingPool.rewardRate.toNumber()) / 86400
+    );
+    expect(claimed).to.be.at.least(pending.toNumber());
+    expect(claimed - pending.toNumber()).to.be.at.most(perSecond);
+  });
+
+  it("Funds the reward pool and pays claims from it", async () => {
+    const fundAmount = new anchor.BN(5000);
+
+    const adminRewardAccount = await createAccount(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      adminWallet.publicKey,
+      Keypair.generate()
+    );
+
+    await mintTo(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      adminRewardAccount,
+      adminWallet.publicKey,
+      fundAmount.toNumber()
+    );
+
+    const poolRewardBefore = (await getAccount(provider.connection, poolRewardAccount)).amount;
+    const stakingPoolBefore = await program.account.stakingPool.fetch(stakingPoolPda);
+
+    await program.methods
+      .fundRewards(poolId, fundAmount)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        funder: adminWallet.publicKey,
+        funderTokenAccount: adminRewardAccount,
+        poolRewardAccount,
+        rewardMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const poolRewardAfter = (await getAccount(provider.connection, poolRewardAccount)).amount;
+    expect(Number(poolRewardAfter)).to.equal(Number(poolRewardBefore) + fundAmount.toNumber());
+
+    const stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
+    expect(stakingPool.totalRewardsFunded.toNumber()).to.equal(
+      stakingPoolBefore.totalRewardsFunded.add(fundAmount).toNumber()
+    );
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    const userRewardBefore = (await getAccount(provider.connection, userRewardAccount)).amount;
+
+    await program.methods
+      .claimRewards(poolId, positionId)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        userStake: userStakePda,
+        user: userWallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, userWallet.publicKey),
+        receiptTokenAccount: null,
+        rewardOwner: userWallet.publicKey,
+        userRewardAccount,
+        poolRewardAccount,
+        systemProgram: SystemProgram.programId,
+        rewardMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userWallet])
+      .rpc();
+
+    const userRewardAfter = (await getAccount(provider.connection, userRewardAccount)).amount;
+    expect(Number(userRewardAfter)).to.be.greaterThan(Number(userRewardBefore));
+  });
+
+  it("Rejects claims the reward pool can't cover", async () => {
+    // A pool whose reward account was never funded
+    const emptyPool = await createPool(3);
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(emptyPool, user, 1_000_000);
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    await expectError(() => claimFrom(emptyPool, user), "InsufficientRewardFunds");
+  });
+
+  it("Enforces the minimum stake amount", async () => {
+    const userStake = await program.account.userStake.fetch(userStakePda);
+    const minStake = userStake.stakeAmount.add(new anchor.BN(1000));
+
+    await program.methods
+      .setMinStake(poolId, minStake)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    try {
+      await program.methods
+        .stake(poolId, positionId, new anchor.BN(100), noLock, null)
+        .accounts({
+          stakingPool: stakingPoolPda,
+          userStake: userStakePda,
+          userAccount: userAccountPdaFor(userWallet.publicKey),
+          poolMember: poolMemberPdaFor(stakingPoolPda, userWallet.publicKey),
+          user: userWallet.publicKey,
+          blacklist: blacklistPdaFor(stakingPoolPda, userWallet.publicKey),
+          payer: userWallet.publicKey,
+          userTokenAccount: userStakeAccount,
+          poolStakeAccount,
+          systemProgram: SystemProgram.programId,
+          stakeMint,
+          nftMint: null,
+          nftTokenAccount: null,
+          nftEscrowAccount: null,
+          nftTokenProgram: null,
+        nftEscrowAccount: null,
+        nftTokenProgram: null,
+          positionOwner: null,
+          feeTokenAccount: null,
+          referrerStake: null,
+          whitelist: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .signers([userWallet])
+        .rpc();
+      expect.fail("stake below the minimum should fail");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("BelowMinimumStake");
+    }
+
+    // Zero disables the check again
+    await program.methods
+      .setMinStake(poolId, new anchor.BN(0))
+      .accounts({
+        stakingPool: stakingPoolPda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    const stakingPool = await program.account.stakingPool.fetch(stakingPoolPda);
+    expect(stakingPool.minStakeAmount.toNumber()).to.equal(0);
+  });
+
+  it("Closes an emptied stake account and refunds rent", async () => {
+    const user = await createFundedUser(100_000);
+    const amount = new anchor.BN(100_000);
+
+    await program.methods
+      .stake(poolId, positionId, amount, noLock, null)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        userStake: user.stakePda,
+        userAccount: userAccountPdaFor(user.wallet.publicKey),
+        poolMember: poolMemberPdaFor(stakingPoolPda, user.wallet.publicKey),
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, user.wallet.publicKey),
+        payer: user.wallet.publicKey,
+        userTokenAccount: user.stakeAccount,
+        poolStakeAccount,
+        systemProgram: SystemProgram.programId,
+        stakeMint,
+        nftMint: null,
+        nftTokenAccount: null,
+        nftEscrowAccount: null,
+        nftTokenProgram: null,
+        positionOwner: null,
+        feeTokenAccount: null,
+        referrerStake: null,
+        whitelist: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    await program.methods
+      .unstake(poolId, positionId, amount)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        userStake: user.stakePda,
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, user.wallet.publicKey),
+        receiptTokenAccount: null,
+        userTokenAccount: user.stakeAccount,
+        poolStakeAccount,
+        systemProgram: SystemProgram.programId,
+        stakeMint,
+        feeTokenAccount: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+    // Unclaimed rewards keep the account open
+    try {
+      await program.methods
+        .closeUserStake(poolId, positionId)
+        .accounts({
+          stakingPool: stakingPoolPda,
+          userStake: user.stakePda,
+          userAccount: userAccountPdaFor(user.wallet.publicKey),
+          poolMember: poolMemberPdaFor(stakingPoolPda, user.wallet.publicKey),
+          user: user.wallet.publicKey,
+        })
+        .signers([user.wallet])
+        .rpc();
+      expect.fail("close should fail while rewards are unclaimed");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("StakeAccountNotEmpty");
+    }
+
+    await program.methods
+      .claimRewards(poolId, positionId)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        userStake: user.stakePda,
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(stakingPoolPda, user.wallet.publicKey),
+        receiptTokenAccount: null,
+        rewardOwner: user.wallet.publicKey,
+        userRewardAccount: user.rewardAccount,
+        poolRewardAccount,
+        systemProgram: SystemProgram.programId,
+        rewardMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+    await program.methods
+      .closeUserStake(poolId, positionId)
+      .accounts({
+        stakingPool: stakingPoolPda,
+        userStake: user.stakePda,
+        userAccount: userAccountPdaFor(user.wallet.publicKey),
+        poolMember: poolMemberPdaFor(stakingPoolPda, user.wallet.publicKey),
+        user: user.wallet.publicKey,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+    const closed = await provider.connection.getAccountInfo(user.stakePda);
+    expect(closed).to.be.null;
+  });
+
+  it("Runs an independent locked pool under a second pool id", async () => {
+    const lockedPool = await createPool(1, { lockDuration: new anchor.BN(3600) });
+    const user = await createFundedUser(10_000);
+    const defaultPoolBefore = await program.account.stakingPool.fetch(stakingPoolPda);
+
+    await stakeInto(lockedPool, user, 10_000);
+
+    const lockedPoolState = await program.account.stakingPool.fetch(lockedPool.pda);
+    const defaultPoolAfter = await program.account.stakingPool.fetch(stakingPoolPda);
+    expect(lockedPoolState.totalStaked.toNumber()).to.equal(10_000);
+    expect(defaultPoolAfter.totalStaked.toNumber()).to.equal(defaultPoolBefore.totalStaked.toNumber());
+
+    // Unstaking inside the lock window forfeits the penalty to the pool
+    const unstakeAmount = 1_000;
+    const penalty = (unstakeAmount * earlyUnstakePenaltyBps) / 10_000;
+    const userBalanceBefore = (await getAccount(provider.connection, user.stakeAccount)).amount;
+
+    await unstakeFrom(lockedPool, user, unstakeAmount);
+
+    const userBalanceAfter = (await getAccount(provider.connection, user.stakeAccount)).amount;
+    expect(Number(userBalanceAfter) - Number(userBalanceBefore)).to.equal(unstakeAmount - penalty);
+
+    const lockedPoolAfter = await program.account.stakingPool.fetch(lockedPool.pda);
+    expect(lockedPoolAfter.totalStaked.toNumber()).to.equal(10_000 - unstakeAmount);
+    expect(lockedPoolAfter.totalForfeited.toNumber()).to.equal(penalty);
+  });
+
+  it("Caps total claims at the emission budget", async () => {
+    const budget = 50;
+    const pool = await createPool(2, { maxTotalRewards: new anchor.BN(budget) });
+    await fundPoolRewards(pool, 10_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+
+    // 1,000,000 tokens at 10/day earn ~115 per second, well past the budget
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await claimFrom(pool, user);
+
+    const claimed = (await getAccount(provider.connection, user.rewardAccount)).amount;
+    expect(Number(claimed)).to.equal(budget);
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalRewardsDistributed.toNumber()).to.equal(budget);
+
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await expectError(() => claimFrom(pool, user), "NoRewardsToClaim");
+  });
+
+  it("Pays a 90-day lock 1.5x the rewards of an unlocked stake", async () => {
+    const pool = await createPool(4);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const unlocked = await createFundedUser(1_000_000);
+    const locked = await createFundedUser(1_000_000);
+
+    // Stake both positions in one transaction so they accrue over the same interval
+    await sendTogether(
+      [stakeBuilder(pool, unlocked, 1_000_000), stakeBuilder(pool, locked, 1_000_000, { lockDays: 90 })],
+      [unlocked.wallet, locked.wallet]
+    );
+
+    const lockedStake = await program.account.userStake.fetch(
+      userStakePdaFor(pool, locked.wallet.publicKey)
+    );
+    expect(lockedStake.lockMultiplierBps).to.equal(15_000);
+    expect(lockedStake.unlockTime.toNumber()).to.equal(
+      lockedStake.lastStakeTime.toNumber() + 90 * 86400
+    );
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    await sendTogether(
+      [claimBuilder(pool, unlocked), claimBuilder(pool, locked)],
+      [unlocked.wallet, locked.wallet]
+    );
+
+    const unlockedRewards = Number((await getAccount(provider.connection, unlocked.rewardAccount)).amount);
+    const lockedRewards = Number((await getAccount(provider.connection, locked.rewardAccount)).amount);
+    expect(unlockedRewards).to.be.greaterThan(0);
+    // The multiplier applies before rounding down to whole tokens, so the locked
+    // position also keeps the boosted share of the unlocked one's dropped fraction
+    expect(lockedRewards).to.be.within(
+      Math.floor((unlockedRewards * 15_000) / 10_000),
+      Math.floor(((unlockedRewards + 1) * 15_000) / 10_000)
+    );
+
+    // Boosted positions can't exit early, even though the pool charges a penalty
+    await expectError(() => unstakeFrom(pool, locked, 1_000), "StakeLocked");
+    await expectError(() => stakeInto(pool, unlocked, 1_000, { lockDays: 7 }), "InvalidLockDays");
+  });
+
+  it("Claims half of the settled rewards, then the rest", async () => {
+    const pool = await createPool(5);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    // Exiting settles everything into reward_debt and stops further accrual
+    await unstakeFrom(pool, user, 1_000_000);
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    const settled = (await program.account.userStake.fetch(userStakePda)).rewardDebt.toNumber();
+    expect(settled).to.be.greaterThan(1);
+
+    const half = Math.floor(settled / 2);
+    await claimFrom(pool, user, half);
+
+    expect(Number((await getAccount(provider.connection, user.rewardAccount)).amount)).to.equal(half);
+    expect((await program.account.userStake.fetch(userStakePda)).rewardDebt.toNumber()).to.equal(settled - half);
+
+    // Asking for more than is owed pays out just the remainder
+    await claimFrom(pool, user, settled);
+
+    expect(Number((await getAccount(provider.connection, user.rewardAccount)).amount)).to.equal(settled);
+    expect((await program.account.userStake.fetch(userStakePda)).rewardDebt.toNumber()).to.equal(0);
+    await expectError(() => claimFrom(pool, user, 1), "NoRewardsToClaim");
+  });
+
+  it("Releases unstaked tokens only after the cooldown", async () => {
+    const pool = await createPool(6, { cooldownSeconds: new anchor.BN(3) });
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+
+    // The one-step unstake is disabled once the pool has a cooldown
+    await expectError(() => unstakeFrom(pool, user, 1_000), "CooldownActive");
+
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    const withdraw = () =>
+      program.methods
+        .withdrawUnstaked(pool.id, positionId)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePda,
+          user: user.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+          receiptTokenAccount: null,
+          userTokenAccount: user.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          stakeMint: pool.stakeMint,
+          feeTokenAccount: null,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .signers([user.wallet])
+        .rpc();
+
+    await program.methods
+      .requestUnstake(pool.id, positionId, new anchor.BN(400_000))
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePda,
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+        receiptTokenAccount: null,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+    const requested = await program.account.userStake.fetch(userStakePda);
+    expect(requested.stakeAmount.toNumber()).to.equal(600_000);
+    expect(requested.pendingUnstake.toNumber()).to.equal(400_000);
+    expect(requested.cooldownEnd.toNumber()).to.equal(requested.lastStakeTime.toNumber() + 3);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalStaked.toNumber()).to.equal(600_000);
+
+    await expectError(withdraw, "CooldownActive");
+
+    await new Promise(resolve => setTimeout(resolve, 4000));
+    await withdraw();
+
+    const withdrawn = await program.account.userStake.fetch(userStakePda);
+    expect(withdrawn.pendingUnstake.toNumber()).to.equal(0);
+    expect(withdrawn.stakeAmount.toNumber()).to.equal(600_000);
+    expect(Number((await getAccount(provider.connection, user.stakeAccount)).amount)).to.equal(400_000);
+
+    // Nothing left to withdraw
+    await expectError(withdraw, "InsufficientStakeAmount");
+  });
+
+  it("Compounds rewards into the stake when both mints match", async () => {
+    // Rewards are paid in the stake mint
+    const pool = await createPool(7, { rewardEqualsStake: true }, { rewardMint: stakeMint });
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000_000_000);
+    await stakeInto(pool, user, 1_000_000_000);
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    const staked = await program.account.userStake.fetch(userStakePda);
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await compoundFor(pool, user);
+
+    const compounded = await program.account.userStake.fetch(userStakePda);
+    const elapsed = BigInt(compounded.lastStakeTime.toNumber() - staked.lastStakeTime.toNumber());
+    const precision = BigInt(1_000_000_000_000);
+    const accPerShare = (BigInt(rewardRate.toNumber()) * elapsed * precision) / BigInt(86400);
+    const expectedReward = Number((BigInt(1_000_000_000) * accPerShare) / precision);
+
+    expect(expectedReward).to.be.greaterThan(0);
+    expect(compounded.stakeAmount.toNumber()).to.equal(1_000_000_000 + expectedReward);
+    expect(compounded.rewardDebt.toNumber()).to.equal(0);
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalStaked.toNumber()).to.equal(compounded.stakeAmount.toNumber());
+    expect(poolState.totalRewardsDistributed.toNumber()).to.equal(expectedReward);
+
+    // The restaked rewards moved into the stake vault, so the stake stays fully backed
+    const vault = await getAccount(provider.connection, pool.stakeAccount);
+    expect(Number(vault.amount)).to.equal(compounded.stakeAmount.toNumber());
+
+    // Compounding can't push the position past the per-user cap
+    await program.methods
+      .setMaxStakePerUser(pool.id, compounded.stakeAmount)
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await expectError(() => compoundFor(pool, user), "UserStakeCapExceeded");
+  });
+
+  it("Rejects compounding when the stake and reward mints differ", async () => {
+    const pool = await createPool(8);
+    await fundPoolRewards(pool, 1_000_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+    await expectError(() => compoundFor(pool, user), "CompoundMintMismatch");
+  });
+
+  it("Lets the admin withdraw only surplus reward tokens", async () => {
+    const pool = await createPool(9);
+    await fundPoolRewards(pool, 1_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    // Exiting settles the user's rewards into the pool's tracked liability
+    await unstakeFrom(pool, user, 1_000_000);
+    const owed = (await program.account.stakingPool.fetch(pool.pda)).totalRewardDebt.toNumber();
+    expect(owed).to.be.greaterThan(0);
+    expect(owed).to.be.lessThan(1_000);
+
+    const adminRewardAccount = await createAccount(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      adminWallet.publicKey,
+      Keypair.generate()
+    );
+    const withdrawSurplus = (amount: number) =>
+      program.methods
+        .withdrawSurplusRewards(pool.id, new anchor.BN(amount))
+        .accounts({
+          stakingPool: pool.pda,
+          admin: adminWallet.publicKey,
+          adminRewardAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .rpc();
+
+    // Taking even one token past the surplus would eat into what the user is owed
+    await expectError(() => withdrawSurplus(1_000 - owed + 1), "InsufficientRewardFunds");
+
+    await withdrawSurplus(1_000 - owed);
+    expect(Number((await getAccount(provider.connection, adminRewardAccount)).amount)).to.equal(1_000 - owed);
+    expect(Number((await getAccount(provider.connection, pool.rewardAccount)).amount)).to.equal(owed);
+
+    // The user can still claim everything they're owed
+    await claimFrom(pool, user);
+    expect(Number((await getAccount(provider.connection, user.rewardAccount)).amount)).to.equal(owed);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalRewardDebt.toNumber()).to.equal(0);
+  });
+
+  it("Stakes and unstakes a Token-2022 mint", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+    const stakeMint2022 = await createMint(
+      connection,
+      payer,
+      adminWallet.publicKey,
+      null,
+      9,
+      Keypair.generate(),
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    // Token-2022 stake mint, legacy reward mint
+    const pool = await createPool(10, {}, {
+      stakeMint: stakeMint2022,
+      stakeTokenProgram: TOKEN_2022_PROGRAM_ID,
+    });
+
+    const user = await createFundedUser(0);
+    user.stakeAccount = await createAccount(
+      connection,
+      payer,
+      stakeMint2022,
+      user.wallet.publicKey,
+      Keypair.generate(),
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await mintTo(
+      connection,
+      payer,
+      stakeMint2022,
+      user.stakeAccount,
+      adminWallet.publicKey,
+      1_000_000,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    await stakeInto(pool, user, 1_000_000);
+
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake.stakeAmount.toNumber()).to.equal(1_000_000);
+    const vault = await getAccount(connection, pool.stakeAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    expect(Number(vault.amount)).to.equal(1_000_000);
+
+    await unstakeFrom(pool, user, 400_000);
+    const userTokens = await getAccount(connection, user.stakeAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    expect(Number(userTokens.amount)).to.equal(400_000);
+  });
+
+  it("Credits only the net amount received for transfer-fee mints", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+
+    // Token-2022 mint charging 1% on every transfer
+    const feeBps = 100;
+    const feeMint = Keypair.generate();
+    const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+    const createMintTx = new anchor.web3.Transaction().add(
+      SystemProgram.createAccount({
+        fromPubkey: payer.publicKey,
+        newAccountPubkey: feeMint.publicKey,
+        space: mintLen,
+        lamports: await connection.getMinimumBalanceForRentExemption(mintLen),
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      createInitializeTransferFeeConfigInstruction(
+        feeMint.publicKey,
+        adminWallet.publicKey,
+        adminWallet.publicKey,
+        feeBps,
+        BigInt(1_000_000_000),
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createInitializeMintInstruction(feeMint.publicKey, 9, adminWallet.publicKey, null, TOKEN_2022_PROGRAM_ID)
+    );
+    await (provider as anchor.AnchorProvider).sendAndConfirm(createMintTx, [feeMint]);
+
+    const pool = await createPool(11, {}, {
+      stakeMint: feeMint.publicKey,
+      stakeTokenProgram: TOKEN_2022_PROGRAM_ID,
+    });
+
+    const user = await createFundedUser(0);
+    user.stakeAccount = await createAccount(
+      connection,
+      payer,
+      feeMint.publicKey,
+      user.wallet.publicKey,
+      Keypair.generate(),
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await mintTo(
+      connection,
+      payer,
+      feeMint.publicKey,
+      user.stakeAccount,
+      adminWallet.publicKey,
+      1_000_000,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    await stakeInto(pool, user, 1_000_000);
+
+    const netReceived = 1_000_000 - (1_000_000 * feeBps) / 10_000;
+    const vault = await getAccount(connection, pool.stakeAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    expect(Number(vault.amount)).to.equal(netReceived);
+
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake.stakeAmount.toNumber()).to.equal(netReceived);
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalStaked.toNumber()).to.equal(netReceived);
+
+    // The whole credited stake can be withdrawn without draining other users' tokens
+    await unstakeFrom(pool, user, netReceived);
+    const drained = await getAccount(connection, pool.stakeAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    expect(Number(drained.amount)).to.equal(0);
+  });
+
+  it("Keeps several positions for one wallet independent", async () => {
+    const pool = await createPool(12);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(4_000_000);
+
+    // Open both positions in one transaction so they accrue over the same interval
+    await sendTogether(
+      [stakeBuilder(pool, user, 1_000_000, { position: 0 }), stakeBuilder(pool, user, 3_000_000, { position: 1 })],
+      [user.wallet]
+    );
+
+    const first = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey, 0));
+    const second = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey, 1));
+    expect(first.stakeAmount.toNumber()).to.equal(1_000_000);
+    expect(first.positionId.toNumber()).to.equal(0);
+    expect(second.stakeAmount.toNumber()).to.equal(3_000_000);
+    expect(second.positionId.toNumber()).to.equal(1);
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    // Claiming and closing out position 0 leaves position 1 untouched
+    await claimFrom(pool, user, undefined, 0);
+    const firstReward = Number((await getAccount(provider.connection, user.rewardAccount)).amount);
+    await unstakeFrom(pool, user, 1_000_000, 0);
+
+    const secondPending = await program.methods
+      .getPendingRewards(pool.id, new anchor.BN(1))
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey, 1),
+      })
+      .view();
+
+    // Position 1 holds three times the stake, so it has earned at least three times as much
+    expect(firstReward).to.be.greaterThan(0);
+    expect(secondPending.toNumber()).to.be.at.least(3 * firstReward);
+
+    const secondAfter = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey, 1));
+    expect(secondAfter.stakeAmount.toNumber()).to.equal(3_000_000);
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey, 0))).stakeAmount.toNumber()).to.equal(0);
+  });
+
+  it("Starts a decaying rate schedule", async () => {
+    const pool = await createPool(13);
+    const setSchedule = (initialRate: number, decayBps: number, periodSeconds: number) =>
+      program.methods
+        .setRateSchedule(pool.id, new anchor.BN(initialRate), decayBps, new anchor.BN(periodSeconds))
+        .accounts({
+          stakingPool: pool.pda,
+          admin: adminWallet.publicKey,
+        })
+        .rpc();
+
+    // Periods shorter than a day and decay above 100% are rejected
+    await expectError(() => setSchedule(1_000, 1_000, 3600), "InvalidRateSchedule");
+    await expectError(() => setSchedule(1_000, 10_001, 86400), "InvalidRateSchedule");
+
+    await setSchedule(1_000, 1_000, 86400);
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.rateScheduleEnabled).to.equal(true);
+    expect(poolState.initialRate.toNumber()).to.equal(1_000);
+    expect(poolState.decayBpsPerPeriod).to.equal(1_000);
+    expect(poolState.periodSeconds.toNumber()).to.equal(86400);
+    expect(poolState.scheduleStart.toNumber()).to.equal(poolState.lastUpdateTime.toNumber());
+
+    // Going back to a flat rate turns the schedule off
+    await proposeRewardRate(pool.id, pool.pda, 5);
+    await applyRewardRate(pool.id, pool.pda);
+    expect((await program.account.stakingPool.fetch(pool.pda)).rateScheduleEnabled).to.equal(false);
+  });
+
+  it("Rejects stakes past the pool's total stake cap", async () => {
+    const pool = await createPool(14, { maxTotalStaked: new anchor.BN(1_000) });
+    const alice = await createFundedUser(1_000);
+    const bob = await createFundedUser(1_000);
+
+    await stakeInto(pool, alice, 600);
+    await stakeInto(pool, bob, 400);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalStaked.toNumber()).to.equal(1_000);
+
+    await expectError(() => stakeInto(pool, alice, 1), "PoolCapExceeded");
+
+    // Raising the cap lets deposits through again
+    await program.methods
+      .setMaxTotalStaked(pool.id, new anchor.BN(1_100))
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+    await stakeInto(pool, alice, 100);
+    await expectError(() => stakeInto(pool, bob, 1), "PoolCapExceeded");
+  });
+
+  it("Rejects stakes past the per-user stake cap", async () => {
+    const pool = await createPool(15);
+    await program.methods
+      .setMaxStakePerUser(pool.id, new anchor.BN(1_000))
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    const whale = await createFundedUser(2_000);
+    await stakeInto(pool, whale, 600);
+    await stakeInto(pool, whale, 400);
+    await expectError(() => stakeInto(pool, whale, 1), "UserStakeCapExceeded");
+
+    // Other wallets still have their own allowance
+    const minnow = await createFundedUser(1_000);
+    await stakeInto(pool, minnow, 1_000);
+  });
+
+  it("Lets the admin slash part or all of a stake", async () => {
+    const pool = await createPool(16);
+    const user = await createFundedUser(1_000);
+    await stakeInto(pool, user, 1_000);
+
+    const treasury = await createAccount(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      stakeMint,
+      adminWallet.publicKey,
+      Keypair.generate()
+    );
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    const slash = (amount: number, signer?: Keypair) =>
+      program.methods
+        .slash(pool.id, positionId, new anchor.BN(amount))
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePda,
+          admin: signer ? signer.publicKey : adminWallet.publicKey,
+          treasuryTokenAccount: treasury,
+          poolStakeAccount: pool.stakeAccount,
+          stakeMint: pool.stakeMint,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .signers(signer ? [signer] : [])
+        .rpc();
+
+    await expectError(() => slash(100, user.wallet), "Unauthorized");
+
+    await slash(300);
+    expect((await program.account.userStake.fetch(userStakePda)).stakeAmount.toNumber()).to.equal(700);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalStaked.toNumber()).to.equal(700);
+    expect(Number((await getAccount(provider.connection, treasury)).amount)).to.equal(300);
+
+    await expectError(() => slash(701), "InsufficientStakeAmount");
+
+    await slash(700);
+    expect((await program.account.userStake.fetch(userStakePda)).stakeAmount.toNumber()).to.equal(0);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalStaked.toNumber()).to.equal(0);
+    expect(Number((await getAccount(provider.connection, treasury)).amount)).to.equal(1_000);
+    expect(Number((await getAccount(provider.connection, pool.stakeAccount)).amount)).to.equal(0);
+  });
+
+  it("Slashes tokens waiting out the unstake cooldown", async () => {
+    const pool = await createPool(92, { cooldownSeconds: new anchor.BN(3600) });
+    const user = await createFundedUser(1_000);
+    await stakeInto(pool, user, 1_000);
+
+    const treasury = await createAccount(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      stakeMint,
+      adminWallet.publicKey,
+      Keypair.generate()
+    );
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    await program.methods
+      .requestUnstake(pool.id, positionId, new anchor.BN(400))
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePda,
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+        receiptTokenAccount: null,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+    // 600 comes out of the active stake and the other 200 out of the pending 400
+    await program.methods
+      .slash(pool.id, positionId, new anchor.BN(800))
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePda,
+        admin: adminWallet.publicKey,
+        treasuryTokenAccount: treasury,
+        poolStakeAccount: pool.stakeAccount,
+        stakeMint: pool.stakeMint,
+        tokenProgram: pool.stakeTokenProgram,
+      })
+      .rpc();
+
+    const slashed = await program.account.userStake.fetch(userStakePda);
+    expect(slashed.stakeAmount.toNumber()).to.equal(0);
+    expect(slashed.pendingUnstake.toNumber()).to.equal(200);
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalStaked.toNumber()).to.equal(0);
+    expect(poolState.totalPendingUnstake.toNumber()).to.equal(200);
+    expect(Number((await getAccount(provider.connection, treasury)).amount)).to.equal(800);
+  });
+
+  it("Only lets whitelisted wallets stake in a gated pool", async () => {
+    const pool = await createPool(17);
+    const user = await createFundedUser(1_000);
+    const [whitelistPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("whitelist"), pool.pda.toBuffer(), user.wallet.publicKey.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .setRequireWhitelist(pool.id, true)
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    await expectError(() => stakeInto(pool, user, 100), "NotWhitelisted");
+
+    await program.methods
+      .addToWhitelist(pool.id, user.wallet.publicKey)
+      .accounts({
+        stakingPool: pool.pda,
+        whitelist: whitelistPda,
+        admin: adminWallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await stakeBuilder(pool, user, 100, { whitelist: whitelistPda }).rpc();
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey))).stakeAmount.toNumber()).to.equal(100);
+
+    await program.methods
+      .removeFromWhitelist(pool.id, user.wallet.publicKey)
+      .accounts({
+        stakingPool: pool.pda,
+        whitelist: whitelistPda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    await expectError(() => stakeBuilder(pool, user, 100, { whitelist: whitelistPda }).rpc(), "NotWhitelisted");
+
+    // Nor can a position be handed to a wallet the pool hasn't whitelisted
+    const newOwner = await createFundedUser(1);
+    const [newOwnerWhitelistPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("whitelist"), pool.pda.toBuffer(), newOwner.wallet.publicKey.toBuffer()],
+      program.programId
+    );
+    const transferTo = (newOwnerWhitelist: PublicKey | null) =>
+      program.methods
+        .transferPosition(pool.id, positionId, newOwner.wallet.publicKey)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, user.wallet.publicKey),
+          newUserStake: userStakePdaFor(pool, newOwner.wallet.publicKey),
+          userAccount: userAccountPdaFor(user.wallet.publicKey),
+          poolMember: poolMemberPdaFor(pool.pda, user.wallet.publicKey),
+          newUserAccount: userAccountPdaFor(newOwner.wallet.publicKey),
+          newPoolMember: poolMemberPdaFor(pool.pda, newOwner.wallet.publicKey),
+          blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+          newOwnerBlacklist: blacklistPdaFor(pool.pda, newOwner.wallet.publicKey),
+          newOwnerWhitelist,
+          receiptTokenAccount: null,
+          user: user.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user.wallet])
+        .rpc();
+    await expectError(() => transferTo(null), "NotWhitelisted");
+
+    await program.methods
+      .addToWhitelist(pool.id, newOwner.wallet.publicKey)
+      .accounts({
+        stakingPool: pool.pda,
+        whitelist: newOwnerWhitelistPda,
+        admin: adminWallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    await transferTo(newOwnerWhitelistPda);
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, newOwner.wallet.publicKey))).stakeAmount.toNumber())
+      .to.equal(100);
+  });
+
+  it("Records an immutable stake snapshot per epoch", async () => {
+    const pool = await createPool(18);
+    const otherPool = await createPool(101);
+    const user = await createFundedUser(2_000);
+    const newOwner = await createFundedUser(0);
+    await stakeInto(pool, user, 500);
+    await stakeInto(pool, user, 200, { position: 1, lockDays: 90 });
+    await stakeInto(otherPool, user, 300);
+
+    const snapshotPdaFor = (snapshotPool: Pool, wallet: PublicKey, epoch: number) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("snapshot"),
+          snapshotPool.pda.toBuffer(),
+          wallet.toBuffer(),
+          new anchor.BN(epoch).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+    const takeSnapshot = (snapshotPool: Pool, owner: User, epoch: number, positions: number[] = [0]) =>
+      program.methods
+        .snapshot(snapshotPool.id, new anchor.BN(epoch))
+        .accounts({
+          stakingPool: snapshotPool.pda,
+          poolMember: poolMemberPdaFor(snapshotPool.pda, owner.wallet.publicKey),
+          snapshot: snapshotPdaFor(snapshotPool, owner.wallet.publicKey, epoch),
+          user: owner.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(positions.map(position => ({
+          pubkey: userStakePdaFor(snapshotPool, owner.wallet.publicKey, position),
+          isSigner: false,
+          isWritable: true,
+        })))
+        .signers([owner.wallet])
+        .rpc();
+
+    // Only the running epoch can be recorded, and only with every position of the wallet
+    const { epoch } = await provider.connection.getEpochInfo();
+    await expectError(() => takeSnapshot(pool, user, epoch + 1, [0, 1]), "InvalidSnapshotEpoch");
+    await expectError(() => takeSnapshot(pool, user, epoch, [0]), "InvalidPositionAccount");
+    await expectError(() => takeSnapshot(pool, user, epoch, [0, 0]), "InvalidPositionAccount");
+
+    await takeSnapshot(pool, user, epoch, [0, 1]);
+    const recorded = await program.account.stakeSnapshot.fetch(snapshotPdaFor(pool, user.wallet.publicKey, epoch));
+    expect(recorded.owner.toBase58()).to.equal(user.wallet.publicKey.toBase58());
+    expect(recorded.stakingPool.toBase58()).to.equal(pool.pda.toBase58());
+    expect(recorded.epoch.toNumber()).to.equal(epoch);
+    expect(recorded.stakeAmount.toNumber()).to.equal(700);
+    expect(recorded.weightedStake.toNumber()).to.equal(500 * 10_000 + 200 * 15_000);
+    expect(recorded.positions).to.equal(2);
+    expect(recorded.slot.toNumber()).to.be.greaterThan(0);
+
+    // Moving the same tokens into a fresh position can't add them to the epoch again
+    await unstakeFrom(pool, user, 500);
+    await stakeInto(pool, user, 500, { position: 2 });
+    await expectError(() => takeSnapshot(pool, user, epoch, [0, 1, 2]), "SnapshotExists");
+    const unchanged = await program.account.stakeSnapshot.fetch(snapshotPdaFor(pool, user.wallet.publicKey, epoch));
+    expect(unchanged.stakeAmount.toNumber()).to.equal(700);
+    expect(unchanged.slot.toNumber()).to.equal(recorded.slot.toNumber());
+
+    // Another pool keeps its own record for the same wallet and epoch
+    await takeSnapshot(otherPool, user, epoch);
+    expect((await program.account.stakeSnapshot.fetch(snapshotPdaFor(otherPool, user.wallet.publicKey, epoch)))
+      .stakeAmount.toNumber()).to.equal(300);
+
+    // A recorded position still counts for its first owner only after a transfer
+    await program.methods
+      .transferPosition(pool.id, new anchor.BN(1), newOwner.wallet.publicKey)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey, 1),
+        newUserStake: userStakePdaFor(pool, newOwner.wallet.publicKey, 1),
+        userAccount: userAccountPdaFor(user.wallet.publicKey),
+        poolMember: poolMemberPdaFor(pool.pda, user.wallet.publicKey),
+        newUserAccount: userAccountPdaFor(newOwner.wallet.publicKey),
+        newPoolMember: poolMemberPdaFor(pool.pda, newOwner.wallet.publicKey),
+        blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+        newOwnerBlacklist: blacklistPdaFor(pool.pda, newOwner.wallet.publicKey),
+        newOwnerWhitelist: null,
+        receiptTokenAccount: null,
+        user: user.wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user.wallet])
+      .rpc();
+    await takeSnapshot(pool, newOwner, epoch, [1]);
+    const transferred = await program.account.stakeSnapshot.fetch(snapshotPdaFor(pool, newOwner.wallet.publicKey, epoch));
+    expect(transferred.stakeAmount.toNumber()).to.equal(0);
+    expect(transferred.positions).to.equal(0);
+  });
+
+  it("Credits the referrer on a referred user's first stake", async () => {
+    const pool = await createPool(19);
+    await fundPoolRewards(pool, 1_000_000);
+    await program.methods
+      .setReferralBps(pool.id, 500)
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    const referrer = await createFundedUser(1_000);
+    await stakeInto(pool, referrer, 1_000);
+    const referrerStakePda = userStakePdaFor(pool, referrer.wallet.publicKey);
+
+    const referred = await createFundedUser(20_000);
+    await expectError(
+      () => stakeInto(pool, referred, 10_000, { referrer: referred.wallet.publicKey }),
+      "SelfReferral"
+    );
+
+    const debtBefore = (await program.account.userStake.fetch(referrerStakePda)).rewardDebt.toNumber();
+    await stakeInto(pool, referred, 10_000, {
+      referrer: referrer.wallet.publicKey,
+      referrerStake: referrerStakePda,
+    });
+
+    // 5% of the 10,000 staked
+    const debtAfter = (await program.account.userStake.fetch(referrerStakePda)).rewardDebt.toNumber();
+    expect(debtAfter - debtBefore).to.equal(500);
+    const referredStake = await program.account.userStake.fetch(userStakePdaFor(pool, referred.wallet.publicKey));
+    expect(referredStake.referrer.toBase58()).to.equal(referrer.wallet.publicKey.toBase58());
+
+    // Later stakes don't pay the referrer again
+    await stakeInto(pool, referred, 10_000, {
+      referrer: referrer.wallet.publicKey,
+      referrerStake: referrerStakePda,
+    });
+    expect((await program.account.userStake.fetch(referrerStakePda)).rewardDebt.toNumber()).to.equal(debtAfter);
+
+    // The referral reward is claimable like any other reward
+    await claimFrom(pool, referrer);
+    expect(Number((await getAccount(provider.connection, referrer.rewardAccount)).amount)).to.be.at.least(500);
+  });
+
+  it("Reports pool stats in one read-only call", async () => {
+    const pool = await createPool(20);
+    await fundPoolRewards(pool, 5_000);
+    const user = await createFundedUser(1_000);
+    await stakeInto(pool, user, 1_000);
+
+    const stats = await program.methods
+      .getPoolStats(pool.id)
+      .accounts({
+        stakingPool: pool.pda,
+        poolRewardAccount: pool.rewardAccount,
+      })
+      .view();
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+
+    expect(stats.totalStaked.toNumber()).to.equal(1_000);
+    expect(stats.rewardRate.toNumber()).to.equal(rewardRate.toNumber());
+    expect(stats.lastUpdateTime.toNumber()).to.equal(poolState.lastUpdateTime.toNumber());
+    expect(stats.totalRewardsDistributed.toNumber()).to.equal(0);
+    expect(stats.rewardPoolBalance.toNumber()).to.equal(5_000);
+  });
+
+  it("Rejects staking or unstaking zero tokens", async () => {
+    const pool = await createPool(21);
+    const user = await createFundedUser(1_000);
+
+    await expectError(() => stakeInto(pool, user, 0), "ZeroAmount");
+    const userStake = await provider.connection.getAccountInfo(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake).to.be.null;
+
+    await stakeInto(pool, user, 1_000);
+    await expectError(() => unstakeFrom(pool, user, 0), "ZeroAmount");
+  });
+
+  it("Rejects reward rates above the pool's maximum", async () => {
+    const pool = await createPool(22, { maxRewardRate: new anchor.BN(100) });
+
+    await proposeRewardRate(pool.id, pool.pda, 100);
+    await applyRewardRate(pool.id, pool.pda);
+    expect((await program.account.stakingPool.fetch(pool.pda)).rewardRate.toNumber()).to.equal(100);
+
+    await expectError(() => proposeRewardRate(pool.id, pool.pda, 101), "RewardRateTooHigh");
+    expect((await program.account.stakingPool.fetch(pool.pda)).rewardRate.toNumber()).to.equal(100);
+  });
+
+  it("Applies a proposed reward rate only after the timelock", async () => {
+    const pool = await createPool(23, { rateTimelock: new anchor.BN(3600) });
+    await expectError(() => applyRewardRate(pool.id, pool.pda), "NoPendingRewardRate");
+
+    await proposeRewardRate(pool.id, pool.pda, 50);
+    let poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.pendingRewardRate.toNumber()).to.equal(50);
+    expect(poolState.pendingRateEffectiveTime.toNumber()).to.be.greaterThan(poolState.lastUpdateTime.toNumber());
+
+    // Accrual keeps using the current rate while the proposal waits
+    await expectError(() => applyRewardRate(pool.id, pool.pda), "TimelockNotElapsed");
+    expect((await program.account.stakingPool.fetch(pool.pda)).rewardRate.toNumber()).to.equal(rewardRate.toNumber());
+
+    const shortPool = await createPool(24, { rateTimelock: new anchor.BN(1) });
+    await proposeRewardRate(shortPool.id, shortPool.pda, 50);
+    await new Promise(resolve => setTimeout(resolve, 2500));
+    await applyRewardRate(shortPool.id, shortPool.pda);
+
+    poolState = await program.account.stakingPool.fetch(shortPool.pda);
+    expect(poolState.rewardRate.toNumber()).to.equal(50);
+    expect(poolState.pendingRateEffectiveTime.toNumber()).to.equal(0);
+  });
+
+  it("Claims three positions in one call", async () => {
+    const pool = await createPool(25);
+    await fundPoolRewards(pool, 1_000_000_000);
+    const user = await createFundedUser(3_000_000);
+    const other = await createFundedUser(1_000_000);
+
+    for (const position of [0, 1, 2]) {
+      await stakeInto(pool, user, 1_000_000, { position });
+    }
+    await stakeInto(pool, other, 1_000_000);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    const positionPdas = [0, 1, 2].map(position => userStakePdaFor(pool, user.wallet.publicKey, position));
+    const claimAll = (signer: User, stakes: PublicKey[]) =>
+      program.methods
+        .claimAll(pool.id)
+        .accounts({
+          stakingPool: pool.pda,
+          user: signer.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, signer.wallet.publicKey),
+          userRewardAccount: signer.rewardAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .remainingAccounts(stakes.map(pubkey => ({ pubkey, isSigner: false, isWritable: true })))
+        .signers([signer.wallet])
+        .rpc();
+
+    // Someone else's position and repeated positions are rejected
+    await expectError(
+      () => claimAll(user, [...positionPdas, userStakePdaFor(pool, other.wallet.publicKey)]),
+      "Unauthorized"
+    );
+    await expectError(() => claimAll(user, [positionPdas[0], positionPdas[0]]), "InvalidPositionAccount");
+
+    await claimAll(user, positionPdas);
+
+    const claimed = Number((await getAccount(provider.connection, user.rewardAccount)).amount);
+    expect(claimed).to.be.greaterThan(0);
+    for (const pda of positionPdas) {
+      expect((await program.account.userStake.fetch(pda)).rewardDebt.toNumber()).to.equal(0);
+    }
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalRewardsDistributed.toNumber()).to.equal(claimed);
+    expect(poolState.totalRewardDebt.toNumber()).to.equal(0);
+  });
+
+  it("Counts wallets with open stake positions", async () => {
+    const pool = await createPool(26);
+    await fundPoolRewards(pool, 1_000_000_000);
+    const first = await createFundedUser(1_000_000);
+    const second = await createFundedUser(1_000_000);
+
+    await stakeInto(pool, first, 500_000);
+    await stakeInto(pool, second, 1_000_000);
+    // Topping up an existing position or opening another doesn't count the wallet twice
+    await stakeInto(pool, first, 500_000);
+    await stakeInto(pool, first, 1, { position: 1 });
+    expect((await program.account.stakingPool.fetch(pool.pda)).stakerCount.toNumber()).to.equal(2);
+
+    // Empty the second position completely, then close it
+    const secondStake = userStakePdaFor(pool, second.wallet.publicKey);
+    await unstakeFrom(pool, second, 1_000_000);
+    if ((await program.account.userStake.fetch(secondStake)).rewardDebt.toNumber() > 0) {
+      await claimFrom(pool, second);
+    }
+    await program.methods
+      .closeUserStake(pool.id, positionId)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: secondStake,
+        userAccount: userAccountPdaFor(second.wallet.publicKey),
+        poolMember: poolMemberPdaFor(pool.pda, second.wallet.publicKey),
+        user: second.wallet.publicKey,
+      })
+      .signers([second.wallet])
+      .rpc();
+
+    expect((await program.account.stakingPool.fetch(pool.pda)).stakerCount.toNumber()).to.equal(1);
+  });
+
+  it("Restakes part of the pending rewards", async () => {
+    const pool = await createPool(27, { rewardEqualsStake: true }, { rewardMint: stakeMint });
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000_000_000);
+    await stakeInto(pool, user, 1_000_000_000);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    const stakeVaultBefore = Number((await getAccount(provider.connection, pool.stakeAccount)).amount);
+    const rewardVaultBefore = Number((await getAccount(provider.connection, pool.rewardAccount)).amount);
+
+    await restakeFor(pool, user, 100);
+
+    // Only the requested amount moved; the rest of the rewards stay claimable
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake.stakeAmount.toNumber()).to.equal(1_000_000_000 + 100);
+    expect(userStake.rewardDebt.toNumber()).to.be.greaterThan(0);
+    expect(Number((await getAccount(provider.connection, pool.stakeAccount)).amount)).to.equal(stakeVaultBefore + 100);
+    expect(Number((await getAccount(provider.connection, pool.rewardAccount)).amount)).to.equal(rewardVaultBefore - 100);
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalStaked.toNumber()).to.equal(1_000_000_000 + 100);
+    expect(poolState.totalRewardDebt.toNumber()).to.equal(userStake.rewardDebt.toNumber());
+
+    const mismatched = await createPool(28);
+    await fundPoolRewards(mismatched, 1_000_000);
+    const other = await createFundedUser(1_000_000);
+    await stakeInto(mismatched, other, 1_000_000);
+    await expectError(() => restakeFor(mismatched, other, 100), "CompoundMintMismatch");
+  });
+
+  it("Refuses to initialize the same pool twice", async () => {
+    const pool = await createPool(29, { rewardRate: new anchor.BN(7) });
+    expect((await program.account.stakingPool.fetch(pool.pda)).isInitialized).to.equal(true);
+
+    // Anchor's `init` stops the second call before AlreadyInitialized can be reached
+    try {
+      await createPool(29, { rewardRate: new anchor.BN(1_000) });
+      expect.fail("second initialize should fail");
+    } catch (err) {
+      expect(err.logs?.join("\n") ?? String(err)).to.include("already in use");
+    }
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.rewardRate.toNumber()).to.equal(7);
+    expect(poolState.admin.toString()).to.equal(adminWallet.publicKey.toString());
+  });
+
+  it("Skims stake and unstake fees to the fee recipient", async () => {
+    const pool = await createPool(30);
+    const user = await createFundedUser(10_000);
+    const feeRecipient = Keypair.generate();
+    const feeAccount = await createAccount(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      stakeMint,
+      feeRecipient.publicKey
+    );
+    const setFees = (stakeFeeBps: number, unstakeFeeBps: number) =>
+      program.methods
+        .setFees(pool.id, stakeFeeBps, unstakeFeeBps, feeRecipient.publicKey)
+        .accounts({
+          stakingPool: pool.pda,
+          admin: adminWallet.publicKey,
+        })
+        .rpc();
+
+    await expectError(() => setFees(1_001, 0), "FeeTooHigh");
+    await setFees(300, 500);
+
+    // A fee is due, so the fee account is required
+    await expectError(() => stakeInto(pool, user, 10_000), "InvalidFeeAccount");
+
+    // 3% of 10_000 goes to the recipient and the rest is staked
+    await stakeInto(pool, user, 10_000, { feeAccount });
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake.stakeAmount.toNumber()).to.equal(9_700);
+    expect(Number((await getAccount(provider.connection, feeAccount)).amount)).to.equal(300);
+    expect(Number((await getAccount(provider.connection, pool.stakeAccount)).amount)).to.equal(9_700);
+
+    // 5% of the 9_700 withdrawn goes to the recipient
+    await unstakeFrom(pool, user, 9_700, 0, feeAccount);
+    expect(Number((await getAccount(provider.connection, user.stakeAccount)).amount)).to.equal(9_215);
+    expect(Number((await getAccount(provider.connection, feeAccount)).amount)).to.equal(300 + 485);
+    expect(Number((await getAccount(provider.connection, pool.stakeAccount)).amount)).to.equal(0);
+  });
+
+  it("Boosts rewards for holders of a collection NFT", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+
+    // Token-2022 mint that is its own token group, standing in for the collection
+    const createCollection = async () => {
+      const collection = Keypair.generate();
+      const mintLen = getMintLen([ExtensionType.GroupPointer]);
+      await (provider as anchor.AnchorProvider).sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.createAccount({
+            fromPubkey: payer.publicKey,
+            newAccountPubkey: collection.publicKey,
+            space: mintLen,
+            lamports: await connection.getMinimumBalanceForRentExemption(mintLen),
+            programId: TOKEN_2022_PROGRAM_ID,
+          }),
+          createInitializeGroupPointerInstruction(
+            collection.publicKey,
+            payer.publicKey,
+            collection.publicKey,
+            TOKEN_2022_PROGRAM_ID
+          ),
+          createInitializeMintInstruction(collection.publicKey, 0, payer.publicKey, null, TOKEN_2022_PROGRAM_ID)
+        ),
+        [collection]
+      );
+      await tokenGroupInitializeGroupWithRentTransfer(
+        connection,
+        payer,
+        collection.publicKey,
+        payer.publicKey,
+        payer.publicKey,
+        BigInt(10),
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      return collection.publicKey;
+    };
+
+    // 0-decimal member mint of `collection` with one token held by `owner`, and the pool's escrow for it
+    const mintNftTo = async (collection: PublicKey, owner: PublicKey, poolPda: PublicKey) => {
+      const nftMint = Keypair.generate();
+      const mintLen = getMintLen([ExtensionType.GroupMemberPointer]);
+      await (provider as anchor.AnchorProvider).sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.createAccount({
+            fromPubkey: payer.publicKey,
+            newAccountPubkey: nftMint.publicKey,
+            space: mintLen,
+            lamports: await connection.getMinimumBalanceForRentExemption(mintLen),
+            programId: TOKEN_2022_PROGRAM_ID,
+          }),
+          createInitializeGroupMemberPointerInstruction(
+            nftMint.publicKey,
+            payer.publicKey,
+            nftMint.publicKey,
+            TOKEN_2022_PROGRAM_ID
+          ),
+          createInitializeMintInstruction(nftMint.publicKey, 0, payer.publicKey, null, TOKEN_2022_PROGRAM_ID)
+        ),
+        [nftMint]
+      );
+      await tokenGroupMemberInitializeWithRentTransfer(
+        connection,
+        payer,
+        nftMint.publicKey,
+        payer.publicKey,
+        collection,
+        payer.publicKey,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const account = await createAccount(
+        connection,
+        payer,
+        nftMint.publicKey,
+        owner,
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(connection, payer, nftMint.publicKey, account, payer, 1, [], undefined, TOKEN_2022_PROGRAM_ID);
+      const escrow = await createAccount(
+        connection,
+        payer,
+        nftMint.publicKey,
+        poolPda,
+        Keypair.generate(),
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      return { mint: nftMint.publicKey, account, escrow };
+    };
+
+    const collection = await createCollection();
+    const otherCollection = await createCollection();
+    const pool = await createPool(31);
+    await program.methods
+      .setNftBoost(pool.id, collection, 1_000)
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    const holder = await createFundedUser(1_000);
+    const plain = await createFundedUser(1_000);
+    const outsider = await createFundedUser(1_000);
+    const holderNft = await mintNftTo(collection, holder.wallet.publicKey, pool.pda);
+    const outsiderNft = await mintNftTo(otherCollection, outsider.wallet.publicKey, pool.pda);
+    const nftBalance = async (account: PublicKey) =>
+      Number((await getAccount(connection, account, undefined, TOKEN_2022_PROGRAM_ID)).amount);
+    const positionOf = (user: User, position = 0) =>
+      program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey, position));
+
+    await stakeInto(pool, holder, 1_000, { nft: holderNft });
+    await stakeInto(pool, plain, 1_000);
+    await expectError(() => stakeInto(pool, outsider, 1_000, { nft: outsiderNft }), "InvalidNftBoost");
+    // Someone else's NFT doesn't count either
+    await expectError(() => stakeInto(pool, plain, 1, { nft: holderNft }), "InvalidNftBoost");
+
+    // The NFT now sits in the pool's escrow, bound to the boosted position
+    const holderStake = await positionOf(holder);
+    expect(holderStake.boostBps).to.equal(1_000);
+    expect(holderStake.nftMint.toBase58()).to.equal(holderNft.mint.toBase58());
+    expect((await positionOf(plain)).boostBps).to.equal(0);
+    expect(await nftBalance(holderNft.account)).to.equal(0);
+    expect(await nftBalance(holderNft.escrow)).to.equal(1);
+
+    // So a second position can't be boosted with it, and topping up keeps the first one's boost
+    await expectError(() => stakeInto(pool, holder, 1_000, { position: 1, nft: holderNft }), "InvalidNftBoost");
+    await stakeInto(pool, holder, 1_000, { position: 1 });
+    expect((await positionOf(holder, 1)).boostBps).to.equal(0);
+    await stakeInto(pool, holder, 1);
+    expect((await positionOf(holder)).boostBps).to.equal(1_000);
+
+    const withdrawNftBoost = (position: number) =>
+      program.methods
+        .withdrawNftBoost(pool.id, new anchor.BN(position))
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, holder.wallet.publicKey, position),
+          user: holder.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, holder.wallet.publicKey),
+          receiptTokenAccount: null,
+          nftMint: holderNft.mint,
+          userNftAccount: holderNft.account,
+          nftEscrowAccount: holderNft.escrow,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([holder.wallet])
+        .rpc();
+    await expectError(() => withdrawNftBoost(1), "InvalidNftBoost");
+
+    // Taking the NFT back drops the boost, and it can then boost another position
+    await withdrawNftBoost(0);
+    expect((await positionOf(holder)).boostBps).to.equal(0);
+    expect(await nftBalance(holderNft.account)).to.equal(1);
+    await stakeInto(pool, holder, 1, { position: 1, nft: holderNft });
+    expect((await positionOf(holder, 1)).boostBps).to.equal(1_000);
+    expect((await positionOf(holder)).boostBps).to.equal(0);
+  });
+
+  it("Lets a keeper compound opted-in positions for a tip", async () => {
+    const pool = await createPool(32, { rewardEqualsStake: true }, { rewardMint: stakeMint });
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    await expectError(
+      () => program.methods
+        .setKeeperTipBps(pool.id, 501)
+        .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+        .rpc(),
+      "InvalidKeeperTipBps"
+    );
+    await program.methods
+      .setKeeperTipBps(pool.id, 100)
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+
+    const optedIn = await createFundedUser(1_000_000_000);
+    const optedOut = await createFundedUser(1_000_000_000);
+    await stakeInto(pool, optedIn, 1_000_000_000);
+    await stakeInto(pool, optedOut, 1_000_000_000);
+
+    await program.methods
+      .setAutoCompound(pool.id, positionId, true)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, optedIn.wallet.publicKey),
+        user: optedIn.wallet.publicKey,
+      })
+      .signers([optedIn.wallet])
+      .rpc();
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    const keeper = await createFundedUser(0);
+    const keeperCompound = (user: User) =>
+      program.methods
+        .keeperCompound(pool.id, positionId)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, user.wallet.publicKey),
+          keeper: keeper.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+          receiptTokenAccount: null,
+          keeperRewardAccount: keeper.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .signers([keeper.wallet])
+        .rpc();
+
+    await keeperCompound(optedIn);
+
+    // The position grew by the reward minus the 1% tip, which went to the keeper
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, optedIn.wallet.publicKey));
+    const restaked = userStake.stakeAmount.toNumber() - 1_000_000_000;
+    const tip = Number((await getAccount(provider.connection, keeper.stakeAccount)).amount);
+    expect(restaked).to.be.greaterThan(0);
+    expect(userStake.rewardDebt.toNumber()).to.equal(0);
+    expect(tip).to.equal(Math.floor((restaked + tip) / 100));
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalStaked.toNumber()).to.equal(2_000_000_000 + restaked);
+    expect(poolState.totalRewardsDistributed.toNumber()).to.equal(restaked + tip);
+
+    await expectError(() => keeperCompound(optedOut), "AutoCompoundDisabled");
+  });
+
+  it("Migrates the reward mint once no rewards are owed", async () => {
+    const pool = await createPool(33);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000_000_000);
+    await stakeInto(pool, user, 500_000_000);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    // Staking again settles the accrued rewards into reward_debt
+    await stakeInto(pool, user, 500_000_000);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalRewardDebt.toNumber()).to.be.greaterThan(0);
+
+    const newRewardMint = await createMint(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      adminWallet.publicKey,
+      null,
+      9
+    );
+    const newRewardAccount = await createAccount(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      newRewardMint,
+      pool.pda,
+      Keypair.generate()
+    );
+    const setRewardMint = () =>
+      program.methods
+        .setRewardMint(pool.id)
+        .accounts({
+          stakingPool: pool.pda,
+          admin: adminWallet.publicKey,
+          newRewardMint,
+          newPoolRewardAccount: newRewardAccount,
+        })
+        .rpc();
+
+    await expectError(setRewardMint, "OutstandingRewards");
+
+    // Paying out the settled rewards clears the liability
+    await claimFrom(pool, user);
+    await setRewardMint();
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalRewardDebt.toNumber()).to.equal(0);
+    expect(poolState.rewardMint.toString()).to.equal(newRewardMint.toString());
+    expect(poolState.poolRewardAccount.toString()).to.equal(newRewardAccount.toString());
+  });
+
+  it("Reports a frozen destination account before transferring", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+    const freezableMint = await createMint(
+      connection,
+      payer,
+      adminWallet.publicKey,
+      adminWallet.publicKey,
+      9
+    );
+    const pool = await createPool(34, { rewardEqualsStake: true }, { stakeMint: freezableMint, rewardMint: freezableMint });
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(0);
+    user.stakeAccount = await createAccount(connection, payer, freezableMint, user.wallet.publicKey);
+    user.rewardAccount = user.stakeAccount;
+    await mintTo(connection, payer, freezableMint, user.stakeAccount, adminWallet.publicKey, 1_000_000);
+
+    await stakeInto(pool, user, 1_000_000);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    await freezeAccount(connection, payer, user.stakeAccount, freezableMint, adminWallet.publicKey);
+
+    await expectError(() => unstakeFrom(pool, user, 1_000_000), "DestinationAccountFrozen");
+    await expectError(() => claimFrom(pool, user), "DestinationAccountFrozen");
+
+    // Nothing moved, and thawing the account unblocks the withdrawal
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake.stakeAmount.toNumber()).to.equal(1_000_000);
+
+    await thawAccount(connection, payer, user.stakeAccount, freezableMint, adminWallet.publicKey);
+    await unstakeFrom(pool, user, 1_000_000);
+  });
+
+  it("Starts accruing rewards at the configured start time", async () => {
+    const connection = provider.connection;
+    const now = await connection.getBlockTime(await connection.getSlot());
+    const startTime = now + 4;
+    const pool = await createPool(35, {
+      rewardRate: new anchor.BN(1),
+      rewardRateIsPerSecond: true,
+      rewardsStartTime: new anchor.BN(startTime),
+    });
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000);
+    await stakeInto(pool, user, 1_000);
+
+    const pendingFor = () =>
+      program.methods
+        .getPendingRewards(pool.id, positionId)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, user.wallet.publicKey),
+        })
+        .view();
+
+    // Still before launch: the deposit sits idle
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    expect((await pendingFor()).toNumber()).to.equal(0);
+
+    await new Promise(resolve => setTimeout(resolve, 6000));
+    const afterStart = await connection.getBlockTime(await connection.getSlot());
+
+    // 1 token per staked token per second, counted from the start time only
+    const pending = (await pendingFor()).toNumber();
+    expect(pending).to.be.greaterThan(0);
+    expect(pending).to.be.at.most(1_000 * (afterStart - startTime + 1));
+  });
+
+  it("Stops accruing rewards at the configured end time", async () => {
+    const connection = provider.connection;
+    const now = await connection.getBlockTime(await connection.getSlot());
+    const endTime = now + 4;
+
+    await expectError(
+      () => createPool(36, { rewardsStartTime: new anchor.BN(endTime), rewardsEndTime: new anchor.BN(endTime) }),
+      "InvalidRewardsWindow"
+    );
+
+    const pool = await createPool(36, {
+      rewardRate: new anchor.BN(1),
+      rewardRateIsPerSecond: true,
+      rewardsEndTime: new anchor.BN(endTime),
+    });
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000);
+    await stakeInto(pool, user, 1_000);
+    const stakedAt = await connection.getBlockTime(await connection.getSlot());
+
+    // Stake straight through the end time, then claim well after it
+    await new Promise(resolve => setTimeout(resolve, 7000));
+    await claimFrom(pool, user);
+
+    const claimed = Number((await getAccount(connection, user.rewardAccount)).amount);
+    expect(claimed).to.be.greaterThan(0);
+    expect(claimed).to.be.at.most(1_000 * (endTime - stakedAt + 1));
+
+    // Nothing more accrues after the end
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await expectError(() => claimFrom(pool, user), "NoRewardsToClaim");
+  });
+
+  it("Emits the full pool configuration on initialize", async () => {
+    const pool = await createPool(37, { rewardRate: new anchor.BN(42) });
+
+    const tx = await provider.connection.getTransaction(pool.signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    const events = [...parser.parseLogs(tx.meta.logMessages)];
+
+    expect(events.map(event => event.name)).to.deep.equal(["poolInitialized"]);
+    const data = events[0].data;
+    expect(data.pool.toString()).to.equal(pool.pda.toString());
+    expect(data.poolId.toNumber()).to.equal(37);
+    expect(data.admin.toString()).to.equal(adminWallet.publicKey.toString());
+    expect(data.stakeMint.toString()).to.equal(pool.stakeMint.toString());
+    expect(data.rewardMint.toString()).to.equal(pool.rewardMint.toString());
+    expect(data.poolStakeAccount.toString()).to.equal(pool.stakeAccount.toString());
+    expect(data.poolRewardAccount.toString()).to.equal(pool.rewardAccount.toString());
+    expect(data.rewardRate.toNumber()).to.equal(42);
+  });
+
+  it("Rejects the pool's vaults passed as user token accounts", async () => {
+    const pool = await createPool(38);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const aliasedStake = { ...user, stakeAccount: pool.stakeAccount };
+    const aliasedReward = { ...user, rewardAccount: pool.rewardAccount };
+
+    await expectError(() => stakeInto(pool, aliasedStake, 1_000), "InvalidAccountAlias");
+    await expectError(() => unstakeFrom(pool, aliasedStake, 1_000), "InvalidAccountAlias");
+    await expectError(() => claimFrom(pool, aliasedReward), "InvalidAccountAlias");
+
+    // Balances are untouched and the honest accounts still work
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake.stakeAmount.toNumber()).to.equal(1_000_000);
+    await claimFrom(pool, user);
+  });
+
+  it("Counts down the remaining lock time", async () => {
+    const pool = await createPool(39);
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000, { lockDays: 30 });
+
+    const remainingLock = async () =>
+      (await program.methods
+        .getUnlockInfo(pool.id, positionId)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, user.wallet.publicKey),
+        })
+        .view()).toNumber();
+
+    const first = await remainingLock();
+    expect(first).to.be.greaterThan(30 * 86400 - 60);
+    expect(first).to.be.at.most(30 * 86400);
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    expect(await remainingLock()).to.be.lessThan(first);
+
+    // An unlocked position reports zero
+    const unlocked = await createFundedUser(1_000);
+    await stakeInto(pool, unlocked, 1_000);
+    expect((await program.methods
+      .getUnlockInfo(pool.id, positionId)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, unlocked.wallet.publicKey),
+      })
+      .view()).toNumber()).to.equal(0);
+  });
+
+  it("Re-locks the whole position when a top-up raises its multiplier", async () => {
+    const pool = await createPool(100);
+    const upgraded = await createFundedUser(2_000);
+    const topped = await createFundedUser(2_000);
+    await stakeInto(pool, upgraded, 1_000);
+    await stakeInto(pool, topped, 1_000, { lockDays: 90 });
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    // A small top-up into the 1.5x tier earns 1.5x on everything, so everything serves
+    // the full 90 days from now instead of from the blended entry time
+    await stakeInto(pool, upgraded, 10, { lockDays: 90 });
+    const upgradedStake = await program.account.userStake.fetch(userStakePdaFor(pool, upgraded.wallet.publicKey));
+    expect(upgradedStake.lockMultiplierBps).to.equal(15_000);
+    expect(upgradedStake.averageEntryTime.toNumber()).to.be.lessThan(upgradedStake.lastStakeTime.toNumber());
+    expect(upgradedStake.unlockTime.toNumber()).to.equal(upgradedStake.lastStakeTime.toNumber() + 90 * 86400);
+
+    // Topping up within the same tier still only pushes the lock out by the blend
+    await stakeInto(pool, topped, 10, { lockDays: 90 });
+    const toppedStake = await program.account.userStake.fetch(userStakePdaFor(pool, topped.wallet.publicKey));
+    expect(toppedStake.unlockTime.toNumber()).to.equal(toppedStake.averageEntryTime.toNumber() + 90 * 86400);
+    expect(toppedStake.unlockTime.toNumber()).to.be.lessThan(toppedStake.lastStakeTime.toNumber() + 90 * 86400);
+  });
+
+  it("Lets a delegate stake and claim but not unstake", async () => {
+    const pool = await createPool(40);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const owner = await createFundedUser(1_000_000);
+    const manager = await createFundedUser(1_000_000);
+    const ownerStake = userStakePdaFor(pool, owner.wallet.publicKey);
+
+    // A delegate can't open a position on the owner's behalf
+    await expectError(
+      () => stakeInto(pool, manager, 1_000, { positionOwner: owner.wallet.publicKey }),
+      "NotOwnerOrDelegate"
+    );
+
+    await stakeInto(pool, owner, 1_000_000);
+    await expectError(
+      () => stakeInto(pool, manager, 1_000, { positionOwner: owner.wallet.publicKey }),
+      "NotOwnerOrDelegate"
+    );
+
+    await program.methods
+      .setDelegate(pool.id, positionId, manager.wallet.publicKey)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: ownerStake,
+        user: owner.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, owner.wallet.publicKey),
+      })
+      .signers([owner.wallet])
+      .rpc();
+
+    // The manager tops up the owner's position from its own tokens
+    await stakeInto(pool, manager, 500_000, { positionOwner: owner.wallet.publicKey });
+    expect((await program.account.userStake.fetch(ownerStake)).stakeAmount.toNumber()).to.equal(1_500_000);
+    expect(Number((await getAccount(provider.connection, manager.stakeAccount)).amount)).to.equal(500_000);
+
+    // Claims signed by the manager still pay the owner
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await program.methods
+      .claimRewards(pool.id, positionId)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: ownerStake,
+        user: manager.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, owner.wallet.publicKey),
+        receiptTokenAccount: null,
+        rewardOwner: owner.wallet.publicKey,
+        userRewardAccount: owner.rewardAccount,
+        poolRewardAccount: pool.rewardAccount,
+        systemProgram: SystemProgram.programId,
+        rewardMint: pool.rewardMint,
+        tokenProgram: pool.rewardTokenProgram,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([manager.wallet])
+      .rpc();
+    expect(Number((await getAccount(provider.connection, owner.rewardAccount)).amount)).to.be.greaterThan(0);
+    expect((await program.account.userStake.fetch(ownerStake)).rewardDebt.toNumber()).to.equal(0);
+
+    // Unstaking stays with the owner
+    await expectError(
+      () => program.methods
+        .unstake(pool.id, positionId, new anchor.BN(1_000))
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: ownerStake,
+          user: manager.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, owner.wallet.publicKey),
+          receiptTokenAccount: null,
+          userTokenAccount: manager.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          systemProgram: SystemProgram.programId,
+          stakeMint: pool.stakeMint,
+          feeTokenAccount: null,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .signers([manager.wallet])
+        .rpc(),
+      "ConstraintSeeds"
+    );
+    await unstakeFrom(pool, owner, 1_500_000);
+  });
+
+  it("Lets the admin tighten the accrual cap", async () => {
+    const pool = await createPool(41);
+    const setCap = (seconds: number) =>
+      program.methods
+        .setMaxAccrualSeconds(pool.id, new anchor.BN(seconds))
+        .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+        .rpc();
+
+    expect((await program.account.stakingPool.fetch(pool.pda)).maxAccrualSeconds.toNumber()).to.equal(365 * 86400);
+    await expectError(() => setCap(0), "InvalidMaxAccrualSeconds");
+    await expectError(() => setCap(365 * 86400 + 1), "InvalidMaxAccrualSeconds");
+
+    await setCap(30 * 86400);
+    expect((await program.account.stakingPool.fetch(pool.pda)).maxAccrualSeconds.toNumber()).to.equal(30 * 86400);
+  });
+
+  it("Unstakes and claims in one instruction", async () => {
+    const pool = await createPool(42);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    const stakeBefore = Number((await getAccount(provider.connection, user.stakeAccount)).amount);
+    const rewardBefore = Number((await getAccount(provider.connection, user.rewardAccount)).amount);
+
+    await program.methods
+      .unstakeAndClaim(pool.id, positionId, new anchor.BN(400_000))
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey),
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+        receiptTokenAccount: null,
+        userTokenAccount: user.stakeAccount,
+        userRewardAccount: user.rewardAccount,
+        poolStakeAccount: pool.stakeAccount,
+        poolRewardAccount: pool.rewardAccount,
+        stakeMint: pool.stakeMint,
+        rewardMint: pool.rewardMint,
+        feeTokenAccount: null,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: pool.stakeTokenProgram,
+        rewardTokenProgram: pool.rewardTokenProgram,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+    expect(Number((await getAccount(provider.connection, user.stakeAccount)).amount)).to.equal(stakeBefore + 400_000);
+    expect(Number((await getAccount(provider.connection, user.rewardAccount)).amount)).to.be.greaterThan(rewardBefore);
+
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake.stakeAmount.toNumber()).to.equal(600_000);
+    expect(userStake.rewardDebt.toNumber()).to.equal(0);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalRewardDebt.toNumber()).to.equal(0);
+  });
+
+  it("Stores the canonical pool bump", async () => {
+    const pool = await createPool(43);
+    const [address, bump] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("staking_pool"), pool.id.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+
+    expect(address.toString()).to.equal(pool.pda.toString());
+    expect((await program.account.stakingPool.fetch(pool.pda)).bump).to.equal(bump);
+  });
+
+  it("Pays a second reward stream alongside the first", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+    const pool = await createPool(44);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+
+    const secondMint = await createMint(connection, payer, adminWallet.publicKey, null, 6);
+    const secondVault = await createAccount(connection, payer, secondMint, pool.pda, Keypair.generate());
+    const userSecondAccount = await createAccount(connection, payer, secondMint, user.wallet.publicKey);
+    await mintTo(connection, payer, secondMint, secondVault, adminWallet.publicKey, 1_000_000_000);
+
+    const claimSecond = () =>
+      program.methods
+        .claimRewards2(pool.id, positionId)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, user.wallet.publicKey),
+          user: user.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+          receiptTokenAccount: null,
+          userRewardAccount2: userSecondAccount,
+          poolRewardAccount2: secondVault,
+          rewardMint2: secondMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user.wallet])
+        .rpc();
+
+    // Pools without a second mint keep working as before
+    await expectError(claimSecond, "SecondRewardNotConfigured");
+
+    await program.methods
+      .setSecondReward(pool.id, new anchor.BN(5))
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+        rewardMint2: secondMint,
+        poolRewardAccount2: secondVault,
+      })
+      .rpc();
+    // Each stream keeps its own cooldown, so claiming one doesn't hold up the other
+    await program.methods
+      .setClaimCooldown(pool.id, new anchor.BN(3600))
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    await claimFrom(pool, user);
+    await claimSecond();
+
+    expect(Number((await getAccount(connection, user.rewardAccount)).amount)).to.be.greaterThan(0);
+    expect(Number((await getAccount(connection, userSecondAccount)).amount)).to.be.greaterThan(0);
+
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await expectError(() => claimFrom(pool, user), "ClaimCooldownActive");
+    await expectError(claimSecond, "ClaimCooldownActive");
+
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake.rewardDebt.toNumber()).to.equal(0);
+    expect(userStake.rewardDebt2.toNumber()).to.equal(0);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalRewardDebt2.toNumber()).to.equal(0);
+  });
+
+  it("Creates the pool vaults during initialize", async () => {
+    const connection = provider.connection;
+
+    // Nothing is created up front; initialize makes both vaults
+    const [expectedStake, expectedReward] = poolVaultsFor(
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("staking_pool"), new anchor.BN(45).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      )[0]
+    );
+    expect(await connection.getAccountInfo(expectedStake)).to.equal(null);
+
+    const pool = await createPool(45);
+    expect(pool.stakeAccount.toString()).to.equal(expectedStake.toString());
+    expect(pool.rewardAccount.toString()).to.equal(expectedReward.toString());
+
+    const stakeVault = await getAccount(connection, pool.stakeAccount);
+    const rewardVault = await getAccount(connection, pool.rewardAccount);
+    expect(stakeVault.owner.toString()).to.equal(pool.pda.toString());
+    expect(stakeVault.mint.toString()).to.equal(stakeMint.toString());
+    expect(Number(stakeVault.amount)).to.equal(0);
+    expect(rewardVault.owner.toString()).to.equal(pool.pda.toString());
+    expect(rewardVault.mint.toString()).to.equal(rewardMint.toString());
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.poolStakeAccount.toString()).to.equal(pool.stakeAccount.toString());
+    expect(poolState.poolRewardAccount.toString()).to.equal(pool.rewardAccount.toString());
+    expect(poolState.totalRewardsFunded.toNumber()).to.equal(0);
+  });
+
+  it("Divides reward rates by the configured denominator", async () => {
+    await expectError(() => createPool(46, { rewardRateDenominator: new anchor.BN(0) }), "InvalidRewardRateDenominator");
+
+    const pool = await createPool(46, { rewardRateDenominator: new anchor.BN(1_000) });
+    expect((await program.account.stakingPool.fetch(pool.pda)).rewardRateDenominator.toNumber()).to.equal(1_000);
+  });
+
+  it("Pausing deposits blocks only new stake", async () => {
+    const pool = await createPool(47, { rewardRateIsPerSecond: true });
+    await fundPoolRewards(pool, 1_000_000);
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 1_000);
+
+    const setDepositsPaused = (paused: boolean, admin: Keypair = (adminWallet as anchor.Wallet).payer) =>
+      program.methods
+        .setDepositsPaused(pool.id, paused)
+        .accounts({
+          stakingPool: pool.pda,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+    await expectError(() => setDepositsPaused(true, user.wallet), "Unauthorized");
+    await setDepositsPaused(true);
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.depositsPaused).to.equal(true);
+    expect(poolState.paused).to.equal(false);
+
+    await expectError(() => stakeInto(pool, user, 500), "DepositsPaused");
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await claimFrom(pool, user);
+    expect(Number((await getAccount(provider.connection, user.rewardAccount)).amount)).to.be.greaterThan(0);
+    await unstakeFrom(pool, user, 400);
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey))).stakeAmount.toNumber()).to.equal(600);
+
+    await setDepositsPaused(false);
+    await stakeInto(pool, user, 500);
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey))).stakeAmount.toNumber()).to.equal(1_100);
+  });
+
+  it("Tracks lifetime claimed rewards per position", async () => {
+    const pool = await createPool(48, { rewardRateIsPerSecond: true });
+    await fundPoolRewards(pool, 1_000_000);
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 1_000);
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    const rewardBalance = async () => Number((await getAccount(provider.connection, user.rewardAccount)).amount);
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await claimFrom(pool, user);
+    const afterFirst = await rewardBalance();
+    expect((await program.account.userStake.fetch(userStakePda)).totalClaimed.toNumber()).to.equal(afterFirst);
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await claimFrom(pool, user, 1_000);
+    const afterSecond = await rewardBalance();
+    expect(afterSecond - afterFirst).to.equal(1_000);
+    expect((await program.account.userStake.fetch(userStakePda)).totalClaimed.toNumber()).to.equal(afterSecond);
+  });
+
+  it("Reconciles total_staked with the stake vault", async () => {
+    const pool = await createPool(49);
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 1_000);
+
+    // Tokens sent straight to the vault aren't tracked by total_staked
+    await mintTo(
+      provider.connection,
+      (adminWallet as anchor.Wallet).payer,
+      pool.stakeMint,
+      pool.stakeAccount,
+      adminWallet.publicKey,
+      250
+    );
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalStaked.toNumber()).to.equal(1_000);
+
+    const reconcile = (admin: Keypair) =>
+      program.methods
+        .reconcileTotalStaked(pool.id)
+        .accounts({
+          stakingPool: pool.pda,
+          admin: admin.publicKey,
+          poolStakeAccount: pool.stakeAccount,
+        })
+        .signers([admin])
+        .rpc();
+
+    await expectError(() => reconcile(user.wallet), "Unauthorized");
+    const weightedBefore = (await program.account.stakingPool.fetch(pool.pda)).totalWeightedStake.toString();
+    await reconcile((adminWallet as anchor.Wallet).payer);
+    const reconciled = await program.account.stakingPool.fetch(pool.pda);
+    expect(reconciled.totalStaked.toNumber()).to.equal(1_250);
+    // Only the unweighted total moves; the weights still sum the positions alone
+    expect(reconciled.totalWeightedStake.toString()).to.equal(weightedBefore);
+    expect(weightedBefore).to.equal((1_000 * 10_000).toString());
+  });
+
+  it("Creates a shared-emission pool", async () => {
+    const pool = await createPool(50, { sharedEmission: true });
+    expect((await program.account.stakingPool.fetch(pool.pda)).sharedEmission).to.equal(true);
+  });
+
+  it("Pays no more than the shared emission when a staker is boosted", async () => {
+    const pool = await createPool(99, { sharedEmission: true, rewardRateIsPerSecond: true });
+    await fundPoolRewards(pool, 1_000_000_000);
+    const unlocked = await createFundedUser(1_000_000);
+    const locked = await createFundedUser(1_000_000);
+
+    await sendTogether(
+      [stakeBuilder(pool, unlocked, 1_000_000), stakeBuilder(pool, locked, 1_000_000, { lockDays: 90 })],
+      [unlocked.wallet, locked.wallet]
+    );
+    const stakedAt = (await program.account.userStake.fetch(userStakePdaFor(pool, locked.wallet.publicKey)))
+      .lastStakeTime.toNumber();
+
+    await new Promise(resolve => setTimeout(resolve, 3000));
+    await sendTogether(
+      [claimBuilder(pool, unlocked), claimBuilder(pool, locked)],
+      [unlocked.wallet, locked.wallet]
+    );
+    const elapsed = (await program.account.stakingPool.fetch(pool.pda)).lastUpdateTime.toNumber() - stakedAt;
+
+    // The 1.5x position takes 60% of the emission and the unlocked one 40%, with only
+    // each position's rounding left behind
+    const unlockedRewards = Number((await getAccount(provider.connection, unlocked.rewardAccount)).amount);
+    const lockedRewards = Number((await getAccount(provider.connection, locked.rewardAccount)).amount);
+    const emitted = rewardRate.toNumber() * elapsed;
+    expect(unlockedRewards + lockedRewards).to.be.within(emitted - 2, emitted);
+    expect(lockedRewards).to.be.within(Math.floor((emitted * 6) / 10) - 1, Math.floor((emitted * 6) / 10));
+  });
+
+  it("Emergency unstake exits when claiming would fail", async () => {
+    // Nothing is ever funded, so claims abort with InsufficientRewardFunds
+    const pool = await createPool(51, { rewardRateIsPerSecond: true });
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 1_000);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    await expectError(() => claimFrom(pool, user), "InsufficientRewardFunds");
+
+    const balanceBefore = Number((await getAccount(provider.connection, user.stakeAccount)).amount);
+    await program.methods
+      .emergencyUnstake(pool.id, positionId)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey),
+        user: user.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+        receiptTokenAccount: null,
+        userTokenAccount: user.stakeAccount,
+        poolStakeAccount: pool.stakeAccount,
+        stakeMint: pool.stakeMint,
+        feeTokenAccount: null,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: pool.stakeTokenProgram,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+    expect(Number((await getAccount(provider.connection, user.stakeAccount)).amount)).to.equal(balanceBefore + 1_000);
+    const userStake = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(userStake.stakeAmount.toNumber()).to.equal(0);
+    expect(userStake.rewardDebt.toNumber()).to.equal(0);
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalStaked.toNumber()).to.equal(0);
+    expect(poolState.totalRewardDebt.toNumber()).to.equal(0);
+  });
+
+  it("Emergency unstakes a locked position for the penalty but never skips the lock", async () => {
+    const emergencyUnstake = (pool: Pool, user: User) =>
+      program.methods
+        .emergencyUnstake(pool.id, positionId)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, user.wallet.publicKey),
+          user: user.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+          receiptTokenAccount: null,
+          userTokenAccount: user.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          stakeMint: pool.stakeMint,
+          feeTokenAccount: null,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .signers([user.wallet])
+        .rpc();
+    const penalty = (1_000 * earlyUnstakePenaltyBps) / 10_000;
+
+    // A pool-wide lock pays the penalty and leaves, but a lock tier can't be skipped
+    const pool = await createPool(93, { lockDuration: new anchor.BN(3600) });
+    const boosted = await createFundedUser(1_000);
+    await stakeInto(pool, boosted, 1_000, { lockDays: 90 });
+    await expectError(() => emergencyUnstake(pool, boosted), "StakeLocked");
+    const user = await createFundedUser(1_000);
+    await stakeInto(pool, user, 1_000);
+    await emergencyUnstake(pool, user);
+
+    expect(Number((await getAccount(provider.connection, user.stakeAccount)).amount)).to.equal(1_000 - penalty);
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.totalStaked.toNumber()).to.equal(1_000);
+    expect(poolState.totalForfeited.toNumber()).to.equal(penalty);
+
+    // Without a penalty there's nothing to pay for leaving early, so the lock holds
+    const freePool = await createPool(102, { lockDuration: new anchor.BN(3600), earlyUnstakePenaltyBps: 0 });
+    const stuck = await createFundedUser(1_000);
+    await stakeInto(freePool, stuck, 1_000);
+    await expectError(() => emergencyUnstake(freePool, stuck), "StakeLocked");
+    expect((await program.account.userStake.fetch(userStakePdaFor(freePool, stuck.wallet.publicKey))).stakeAmount.toNumber())
+      .to.equal(1_000);
+
+    // With a cooldown the rest waits in pending_unstake instead of leaving at once
+    const cooldownPool = await createPool(94, { lockDuration: new anchor.BN(3600), cooldownSeconds: new anchor.BN(3600) });
+    const queued = await createFundedUser(1_000);
+    await stakeInto(cooldownPool, queued, 1_000);
+    await emergencyUnstake(cooldownPool, queued);
+
+    expect(Number((await getAccount(provider.connection, queued.stakeAccount)).amount)).to.equal(0);
+    const position = await program.account.userStake.fetch(userStakePdaFor(cooldownPool, queued.wallet.publicKey));
+    expect(position.stakeAmount.toNumber()).to.equal(0);
+    expect(position.pendingUnstake.toNumber()).to.equal(1_000 - penalty);
+    expect(position.cooldownEnd.toNumber()).to.be.greaterThan(position.lastStakeTime.toNumber());
+    const cooldownPoolState = await program.account.stakingPool.fetch(cooldownPool.pda);
+    expect(cooldownPoolState.totalPendingUnstake.toNumber()).to.equal(1_000 - penalty);
+    expect(cooldownPoolState.totalForfeited.toNumber()).to.equal(penalty);
+  });
+
+  it("Blends the entry time of repeated deposits by stake", async () => {
+    const pool = await createPool(52);
+    const user = await createFundedUser(10_000);
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+
+    await stakeInto(pool, user, 1_000);
+    const first = await program.account.userStake.fetch(userStakePda);
+    expect(first.averageEntryTime.toNumber()).to.equal(first.lastStakeTime.toNumber());
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await stakeInto(pool, user, 3_000);
+    const second = await program.account.userStake.fetch(userStakePda);
+
+    const firstTime = first.lastStakeTime.toNumber();
+    const secondTime = second.lastStakeTime.toNumber();
+    expect(secondTime).to.be.greaterThan(firstTime);
+    expect(second.averageEntryTime.toNumber()).to.equal(Math.floor((firstTime * 1_000 + secondTime * 3_000) / 4_000));
+  });
+
+  it("Reports the pool's total reward liability", async () => {
+    const pool = await createPool(53, { rewardRateIsPerSecond: true });
+    await fundPoolRewards(pool, 1_000_000_000);
+    const alice = await createFundedUser(10_000);
+    const bob = await createFundedUser(10_000);
+    await stakeInto(pool, alice, 1_000);
+    await stakeInto(pool, bob, 2_000);
+
+    const liability = async () =>
+      (await program.methods
+        .getTotalLiability(pool.id)
+        .accounts({ stakingPool: pool.pda })
+        .view()).toNumber();
+    const debtOf = async (user: User) =>
+      (await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey))).rewardDebt.toNumber();
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    // Topping up settles each position's rewards into its reward_debt
+    await stakeInto(pool, alice, 1);
+    await stakeInto(pool, bob, 1);
+    expect(await debtOf(alice)).to.be.greaterThan(0);
+    expect(await liability()).to.equal((await debtOf(alice)) + (await debtOf(bob)));
+
+    await claimFrom(pool, alice);
+    expect(await debtOf(alice)).to.equal(0);
+    expect(await liability()).to.equal(await debtOf(bob));
+  });
+
+  it("Pays claims to the position's reward destination", async () => {
+    const pool = await createPool(54, { rewardRateIsPerSecond: true });
+    await fundPoolRewards(pool, 1_000_000_000);
+    const user = await createFundedUser(10_000);
+    const cold = await createFundedUser(1);
+    await stakeInto(pool, user, 1_000);
+
+    await program.methods
+      .setRewardDestination(pool.id, positionId, cold.wallet.publicKey)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey),
+        user: user.wallet.publicKey,
+      })
+      .signers([user.wallet])
+      .rpc();
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    const claimTo = (recipient: User) =>
+      program.methods
+        .claimRewards(pool.id, positionId)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, user.wallet.publicKey),
+          user: user.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+          receiptTokenAccount: null,
+          rewardOwner: recipient.wallet.publicKey,
+          userRewardAccount: recipient.rewardAccount,
+          poolRewardAccount: pool.rewardAccount,
+          systemProgram: SystemProgram.programId,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+        .signers([user.wallet])
+        .rpc();
+
+    await expectError(() => claimTo(user), "RewardDestinationMismatch");
+    await claimTo(cold);
+    expect(Number((await getAccount(provider.connection, cold.rewardAccount)).amount)).to.be.greaterThan(0);
+    expect(Number((await getAccount(provider.connection, user.rewardAccount)).amount)).to.equal(0);
+  });
+
+  it("Normalizes reward rates to whole staked tokens across decimals", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+
+    // 6-decimal stake mint against the 9-decimal reward mint
+    const sixDecimalMint = await createMint(connection, payer, adminWallet.publicKey, null, 6);
+    const pool = await createPool(
+      55,
+      { rewardRate: new anchor.BN(1_000), rewardRateIsPerSecond: true, normalizeDecimals: true },
+      { stakeMint: sixDecimalMint }
+    );
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const poolState = await program.account.stakingPool.fetch(pool.pda);
+    expect(poolState.stakeDecimals).to.equal(6);
+    expect(poolState.rewardDecimals).to.equal(9);
+
+    const user = await createFundedUser(0);
+    user.stakeAccount = await createAccount(connection, payer, sixDecimalMint, user.wallet.publicKey);
+    await mintTo(connection, payer, sixDecimalMint, user.stakeAccount, adminWallet.publicKey, 2_000_000);
+
+    // Two whole tokens at 1,000 base units each per second
+    await stakeInto(pool, user, 2_000_000);
+    const stakedAt = (await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey))).lastStakeTime.toNumber();
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await claimFrom(pool, user);
+    const claimedAt = (await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey))).lastStakeTime.toNumber();
+
+    const claimed = Number((await getAccount(connection, user.rewardAccount)).amount);
+    expect(claimed).to.equal(2_000 * (claimedAt - stakedAt));
+  });
+
+  it("Rejects claims within the claim cooldown", async () => {
+    const pool = await createPool(56, { rewardRateIsPerSecond: true });
+    await fundPoolRewards(pool, 1_000_000_000);
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 1_000);
+
+    await program.methods
+      .setClaimCooldown(pool.id, new anchor.BN(3))
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    await claimFrom(pool, user);
+    const firstClaim = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(firstClaim.lastClaimTime.toNumber()).to.be.greaterThan(0);
+
+    await expectError(() => claimFrom(pool, user), "ClaimCooldownActive");
+    await expectError(
+      () =>
+        program.methods
+          .unstakeAndClaim(pool.id, positionId, new anchor.BN(1))
+          .accounts({
+            stakingPool: pool.pda,
+            userStake: userStakePdaFor(pool, user.wallet.publicKey),
+            user: user.wallet.publicKey,
+            blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+            receiptTokenAccount: null,
+            userTokenAccount: user.stakeAccount,
+            userRewardAccount: user.rewardAccount,
+            poolStakeAccount: pool.stakeAccount,
+            poolRewardAccount: pool.rewardAccount,
+            stakeMint: pool.stakeMint,
+            rewardMint: pool.rewardMint,
+            feeTokenAccount: null,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: pool.stakeTokenProgram,
+            rewardTokenProgram: pool.rewardTokenProgram,
+          })
+          .signers([user.wallet])
+          .rpc(),
+      "ClaimCooldownActive"
+    );
+
+    await new Promise(resolve => setTimeout(resolve, 4000));
+    const balanceBefore = Number((await getAccount(provider.connection, user.rewardAccount)).amount);
+    await claimFrom(pool, user);
+    expect(Number((await getAccount(provider.connection, user.rewardAccount)).amount)).to.be.greaterThan(balanceBefore);
+  });
+
+  it("Sweeps forfeited penalties from the stake vault", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(57, { lockDuration: new anchor.BN(3600) });
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 1_000);
+
+    const adminStakeAccount = await createAccount(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      stakeMint,
+      adminWallet.publicKey,
+      Keypair.generate()
+    );
+    const sweep = () =>
+      program.methods
+        .sweepDust(pool.id)
+        .accounts({
+          stakingPool: pool.pda,
+          admin: adminWallet.publicKey,
+          adminTokenAccount: adminStakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          stakeMint: pool.stakeMint,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .rpc();
+
+    await expectError(sweep, "NoDustToSweep");
+
+    // Unstaking early leaves the penalty in the vault
+    await unstakeFrom(pool, user, 400);
+    const penalty = (400 * earlyUnstakePenaltyBps) / 10_000;
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalForfeited.toNumber()).to.equal(penalty);
+
+    await sweep();
+    expect(Number((await getAccount(connection, adminStakeAccount)).amount)).to.equal(penalty);
+    expect(Number((await getAccount(connection, pool.stakeAccount)).amount)).to.equal(600);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalForfeited.toNumber()).to.equal(0);
+  });
+
+  it("Boosts rewards for positions that lock the governance token", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(58, { rewardRateIsPerSecond: true });
+    await fundPoolRewards(pool, 1_000_000_000);
+    const boosted = await createFundedUser(10_000);
+    const plain = await createFundedUser(10_000);
+
+    const governanceMint = await createMint(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      adminWallet.publicKey,
+      null,
+      0
+    );
+    const poolBoostAccount = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("pool_boost"), pool.pda.toBuffer()],
+      program.programId
+    )[0];
+    await program.methods
+      .setBoostConfig(pool.id, new anchor.BN(1), 5_000)
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+        boostMint: governanceMint,
+        poolBoostAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const userBoostAccount = await createAccount(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      governanceMint,
+      boosted.wallet.publicKey
+    );
+    await mintTo(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      governanceMint,
+      userBoostAccount,
+      adminWallet.publicKey,
+      8_000
+    );
+    const boostAccounts = {
+      stakingPool: pool.pda,
+      userStake: userStakePdaFor(pool, boosted.wallet.publicKey),
+      user: boosted.wallet.publicKey,
+      userBoostAccount,
+      poolBoostAccount,
+      boostMint: governanceMint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+
+    await stakeInto(pool, boosted, 1_000);
+    await stakeInto(pool, plain, 1_000);
+
+    // 8000 tokens at 1 per bps would be 80%, but the pool caps the boost at 50%
+    await program.methods
+      .boostLock(pool.id, positionId, new anchor.BN(8_000), new anchor.BN(5))
+      .accounts(boostAccounts)
+      .signers([boosted.wallet])
+      .rpc();
+    const locked = await program.account.userStake.fetch(boostAccounts.userStake);
+    expect(locked.boostAmount.toNumber()).to.equal(8_000);
+    expect(locked.governanceBoostBps).to.equal(5_000);
+    expect(Number((await getAccount(connection, poolBoostAccount)).amount)).to.equal(8_000);
+
+    // Claim both positions together so they share a checkpoint, then again after a wait
+    const claimBoth = async () => {
+      const before = await Promise.all(
+        [boosted, plain].map(async (user) => Number((await getAccount(connection, user.rewardAccount)).amount))
+      );
+      await sendTogether([claimBuilder(pool, boosted), claimBuilder(pool, plain)], [boosted.wallet, plain.wallet]);
+      const after = await Promise.all(
+        [boosted, plain].map(async (user) => Number((await getAccount(connection, user.rewardAccount)).amount))
+      );
+      return after.map((amount, i) => amount - before[i]);
+    };
+    await claimBoth();
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    const [boostedReward, plainReward] = await claimBoth();
+    expect(plainReward).to.be.greaterThan(0);
+    expect(boostedReward).to.equal(plainReward * 1.5);
+
+    const withdraw = () =>
+      program.methods
+        .boostUnlockWithdraw(pool.id, positionId)
+        .accounts({ ...boostAccounts, blacklist: blacklistPdaFor(pool.pda, boosted.wallet.publicKey) })
+        .signers([boosted.wallet])
+        .rpc();
+    await expectError(withdraw, "BoostLocked");
+
+    await new Promise(resolve => setTimeout(resolve, 4000));
+    await withdraw();
+    expect(Number((await getAccount(connection, userBoostAccount)).amount)).to.equal(8_000);
+    const unlocked = await program.account.userStake.fetch(boostAccounts.userStake);
+    expect(unlocked.boostAmount.toNumber()).to.equal(0);
+    expect(unlocked.governanceBoostBps).to.equal(0);
+  });
+
+  it("Reports the APR for fixed and shared-emission pools", async () => {
+    const aprOf = async (pool: Pool) =>
+      (await program.methods
+        .getApr(pool.id)
+        .accounts({ stakingPool: pool.pda })
+        .view()).toNumber();
+
+    // Both test mints share their decimals, so only the rate and mode matter
+    const daily = await createPool(59);
+    expect(await aprOf(daily)).to.equal(10 * 365 * 10_000);
+
+    const scaled = await createPool(60, { rewardRateDenominator: new anchor.BN(100_000) });
+    expect(await aprOf(scaled)).to.equal(365);
+
+    const shared = await createPool(61, { sharedEmission: true, rewardRateIsPerSecond: true });
+    expect(await aprOf(shared)).to.equal(0);
+    const user = await createFundedUser(10_000);
+    await stakeInto(shared, user, 1_000);
+    expect(await aprOf(shared)).to.equal((10 * 365 * 86400 * 10_000) / 1_000);
+  });
+
+  it("Claims rewards from one pool and stakes them into another", async () => {
+    const connection = provider.connection;
+    const source = await createPool(62, { rewardRateIsPerSecond: true });
+    await fundPoolRewards(source, 1_000_000_000);
+    // The target stakes the source's reward mint
+    const target = await createPool(63, { rewardEqualsStake: true }, { stakeMint: rewardMint });
+    const mismatched = await createPool(64);
+
+    const user = await createFundedUser(10_000);
+    await mintTo(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      user.rewardAccount,
+      adminWallet.publicKey,
+      100
+    );
+    await stakeInto(source, user, 1_000);
+    await stakeInto(target, { ...user, stakeAccount: user.rewardAccount }, 100);
+    await stakeInto(mismatched, user, 100);
+
+    const route = (to: Pool) =>
+      program.methods
+        .claimAndStakeInto(source.id, positionId, to.id, positionId)
+        .accounts({
+          stakingPool: source.pda,
+          userStake: userStakePdaFor(source, user.wallet.publicKey),
+          user: user.wallet.publicKey,
+          blacklist: blacklistPdaFor(source.pda, user.wallet.publicKey),
+          receiptTokenAccount: null,
+          poolRewardAccount: source.rewardAccount,
+          rewardMint: source.rewardMint,
+          targetPool: to.pda,
+          targetUserStake: userStakePdaFor(to, user.wallet.publicKey),
+          targetWhitelist: null,
+          targetBlacklist: blacklistPdaFor(to.pda, user.wallet.publicKey),
+          targetPoolStakeAccount: to.stakeAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user.wallet])
+        .rpc();
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await expectError(() => route(mismatched), "CompoundMintMismatch");
+
+    // The target's deposit limits apply to routed rewards too
+    const setTargetMaxSingleStake = (amount: number) =>
+      program.methods
+        .setMaxSingleStake(target.id, new anchor.BN(amount))
+        .accounts({ stakingPool: target.pda, admin: adminWallet.publicKey })
+        .rpc();
+    await setTargetMaxSingleStake(1);
+    await expectError(() => route(target), "StakeTooLarge");
+    await setTargetMaxSingleStake(0);
+
+    const rewardVaultBefore = Number((await getAccount(connection, source.rewardAccount)).amount);
+    await route(target);
+
+    const sourcePosition = await program.account.userStake.fetch(userStakePdaFor(source, user.wallet.publicKey));
+    const targetPosition = await program.account.userStake.fetch(userStakePdaFor(target, user.wallet.publicKey));
+    const routed = sourcePosition.totalClaimed.toNumber();
+    expect(routed).to.be.greaterThan(0);
+    expect(sourcePosition.rewardDebt.toNumber()).to.equal(0);
+    expect(targetPosition.stakeAmount.toNumber()).to.equal(100 + routed);
+    expect((await program.account.stakingPool.fetch(target.pda)).totalStaked.toNumber()).to.equal(100 + routed);
+    expect(Number((await getAccount(connection, source.rewardAccount)).amount)).to.equal(rewardVaultBefore - routed);
+    expect(Number((await getAccount(connection, target.stakeAccount)).amount)).to.equal(100 + routed);
+  });
+
+  it("Rejects unstaking in the same slot as a stake when flash protection is on", async () => {
+    const pool = await createPool(65);
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 1_000);
+
+    const unstakeBuilder = (amount: number) =>
+      program.methods
+        .unstake(pool.id, positionId, new anchor.BN(amount))
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, user.wallet.publicKey),
+          user: user.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+          receiptTokenAccount: null,
+          userTokenAccount: user.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          systemProgram: SystemProgram.programId,
+          stakeMint: pool.stakeMint,
+          feeTokenAccount: null,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .signers([user.wallet]);
+
+    // Without the flag, staking and unstaking in one transaction is fine
+    await sendTogether([stakeBuilder(pool, user, 500), unstakeBuilder(500)], [user.wallet]);
+
+    await program.methods
+      .setPreventFlash(pool.id, true)
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+
+    try {
+      await sendTogether([stakeBuilder(pool, user, 500), unstakeBuilder(500)], [user.wallet]);
+      expect.fail("expected SameSlotStakeUnstake");
+    } catch (err) {
+      expect((err.logs ?? []).join("\n")).to.include("SameSlotStakeUnstake");
+    }
+
+    const position = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(position.lastStakeSlot.toNumber()).to.be.greaterThan(0);
+
+    // A later slot can unstake as usual
+    await stakeInto(pool, user, 500);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await unstakeFrom(pool, user, 500);
+  });
+
+  it("Accrues reward debt beyond u64 and only fails when paying it out whole", async () => {
+    // 10^9 tokens at 10^10 per token per second owe more than u64::MAX within two seconds
+    const pool = await createPool(66, {
+      rewardRateIsPerSecond: true,
+      rewardRate: new anchor.BN(10_000_000_000),
+    });
+    const user = await createFundedUser(1_000_000_001);
+    await stakeInto(pool, user, 1_000_000_000);
+    await new Promise(resolve => setTimeout(resolve, 3000));
+
+    const u64Max = new anchor.BN("18446744073709551615");
+    const pending = await program.methods
+      .getPendingRewards(pool.id, positionId)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey),
+      })
+      .view();
+    expect(pending.gt(u64Max)).to.equal(true);
+
+    // Settling the oversized debt still works
+    await stakeInto(pool, user, 1);
+    const settled = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(settled.rewardDebt.gt(u64Max)).to.equal(true);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalRewardDebt.eq(settled.rewardDebt)).to.equal(true);
+
+    // Paying the whole debt in one transfer can't fit in a u64
+    await expectError(
+      () =>
+        program.methods
+          .unstakeAndClaim(pool.id, positionId, new anchor.BN(1))
+          .accounts({
+            stakingPool: pool.pda,
+            userStake: userStakePdaFor(pool, user.wallet.publicKey),
+            user: user.wallet.publicKey,
+            blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+            receiptTokenAccount: null,
+            userTokenAccount: user.stakeAccount,
+            userRewardAccount: user.rewardAccount,
+            poolStakeAccount: pool.stakeAccount,
+            poolRewardAccount: pool.rewardAccount,
+            stakeMint: pool.stakeMint,
+            rewardMint: pool.rewardMint,
+            feeTokenAccount: null,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: pool.stakeTokenProgram,
+            rewardTokenProgram: pool.rewardTokenProgram,
+          })
+          .signers([user.wallet])
+          .rpc(),
+      "ArithmeticError"
+    );
+  });
+
+  it("Pays each lock tier its own reward rate", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(67, { rewardRateIsPerSecond: true });
+    await fundPoolRewards(pool, 1_000_000_000);
+    const setTierRate = (tier: number, rate: number) =>
+      program.methods
+        .setTierRate(pool.id, tier, new anchor.BN(rate))
+        .accounts({
+          stakingPool: pool.pda,
+          admin: adminWallet.publicKey,
+        })
+        .rpc();
+
+    await expectError(() => setTierRate(4, 10), "InvalidTier");
+    await setTierRate(0, 2);
+    await setTierRate(1, 8);
+
+    const flexible = await createFundedUser(10_000);
+    const locked = await createFundedUser(10_000);
+    await stakeInto(pool, flexible, 1_000);
+    await stakeInto(pool, locked, 1_000, { lockDays: 30 });
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, locked.wallet.publicKey))).rewardTier).to.equal(1);
+
+    // Claim both positions together so they share a checkpoint, then again after a wait
+    const claimBoth = async () => {
+      const before = await Promise.all(
+        [flexible, locked].map(async (user) => Number((await getAccount(connection, user.rewardAccount)).amount))
+      );
+      await sendTogether([claimBuilder(pool, flexible), claimBuilder(pool, locked)], [flexible.wallet, locked.wallet]);
+      const after = await Promise.all(
+        [flexible, locked].map(async (user) => Number((await getAccount(connection, user.rewardAccount)).amount))
+      );
+      return after.map((amount, i) => amount - before[i]);
+    };
+    await claimBoth();
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    const [flexibleReward, lockedReward] = await claimBoth();
+
+    // 4x the rate and the 30-day tier's 1.25x multiplier
+    expect(flexibleReward).to.be.greaterThan(0);
+    expect(lockedReward).to.equal(flexibleReward * 5);
+  });
+
+  it("Partially fills an unstake when the stake vault is short", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+
+    // A permanent delegate can take tokens out of the vault behind the pool's back
+    const shortMint = Keypair.generate();
+    const mintLen = getMintLen([ExtensionType.PermanentDelegate]);
+    await (provider as anchor.AnchorProvider).sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer.publicKey,
+          newAccountPubkey: shortMint.publicKey,
+          space: mintLen,
+          lamports: await connection.getMinimumBalanceForRentExemption(mintLen),
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializePermanentDelegateInstruction(shortMint.publicKey, adminWallet.publicKey, TOKEN_2022_PROGRAM_ID),
+        createInitializeMintInstruction(shortMint.publicKey, 9, adminWallet.publicKey, null, TOKEN_2022_PROGRAM_ID)
+      ),
+      [shortMint]
+    );
+
+    const pool = await createPool(68, {}, {
+      stakeMint: shortMint.publicKey,
+      stakeTokenProgram: TOKEN_2022_PROGRAM_ID,
+    });
+    const user = await createFundedUser(0);
+    user.stakeAccount = await createAccount(
+      connection,
+      payer,
+      shortMint.publicKey,
+      user.wallet.publicKey,
+      Keypair.generate(),
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await mintTo(connection, payer, shortMint.publicKey, user.stakeAccount, adminWallet.publicKey, 1_000, [], undefined, TOKEN_2022_PROGRAM_ID);
+    await stakeInto(pool, user, 1_000);
+
+    // Leave the vault one token short of the recorded stake
+    await burn(connection, payer, pool.stakeAccount, shortMint.publicKey, payer, 1, [], undefined, TOKEN_2022_PROGRAM_ID);
+
+    try {
+      await unstakeFrom(pool, user, 1_000);
+      expect.fail("expected the full unstake to fail");
+    } catch (err) {
+      expect(String(err)).to.not.include("expected the full unstake to fail");
+    }
+
+    await program.methods
+      .setPartialFillUnstake(pool.id, true)
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+      })
+      .rpc();
+    await unstakeFrom(pool, user, 1_000);
+
+    expect(Number((await getAccount(connection, user.stakeAccount, undefined, TOKEN_2022_PROGRAM_ID)).amount)).to.equal(999);
+    const position = await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey));
+    expect(position.stakeAmount.toNumber()).to.equal(1);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalStaked.toNumber()).to.equal(1);
+  });
+
+  it("Distributes a reward drop in proportion to boosted stake", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(69, { rewardRate: new anchor.BN(0) });
+    const small = await createFundedUser(10_000);
+    const large = await createFundedUser(10_000);
+
+    const adminRewardAccount = await createAccount(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      adminWallet.publicKey,
+      Keypair.generate()
+    );
+    await mintTo(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      adminRewardAccount,
+      adminWallet.publicKey,
+      10_000
+    );
+    const distribute = (amount: number) =>
+      program.methods
+        .distribute(pool.id, new anchor.BN(amount))
+        .accounts({
+          stakingPool: pool.pda,
+          admin: adminWallet.publicKey,
+          adminTokenAccount: adminRewardAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .rpc();
+
+    await expectError(() => distribute(4_000), "NoStakers");
+
+    // 1,000 at 1x and 2,000 at the 90-day tier's 1.5x weigh 1,000 and 3,000
+    await stakeInto(pool, small, 1_000);
+    await stakeInto(pool, large, 2_000, { lockDays: 90 });
+    await distribute(4_000);
+    expect(Number((await getAccount(connection, pool.rewardAccount)).amount)).to.equal(4_000);
+
+    await claimFrom(pool, small);
+    await claimFrom(pool, large);
+    expect(Number((await getAccount(connection, small.rewardAccount)).amount)).to.equal(1_000);
+    expect(Number((await getAccount(connection, large.rewardAccount)).amount)).to.equal(3_000);
+  });
+
+  it("Keeps the rewards an emission budget holds back", async () => {
+    const budget = 50;
+    const pool = await createPool(70, { maxTotalRewards: new anchor.BN(budget) });
+    await fundPoolRewards(pool, 10_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+
+    // ~115 tokens per second, so the first claim runs into the cap
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await claimFrom(pool, user);
+    expect(Number((await getAccount(provider.connection, user.rewardAccount)).amount)).to.equal(budget);
+
+    const capped = await program.account.userStake.fetch(userStakePda);
+    const cappedPool = await program.account.stakingPool.fetch(pool.pda);
+    expect(capped.totalClaimed.toNumber()).to.equal(budget);
+    expect(capped.rewardDebt.toNumber()).to.be.greaterThan(0);
+    expect(cappedPool.totalRewardDebt.toString()).to.equal(capped.rewardDebt.toString());
+
+    // A claim that can't pay anything leaves the remainder and the checkpoints alone
+    await expectError(() => claimFrom(pool, user), "NoRewardsToClaim");
+    const after = await program.account.userStake.fetch(userStakePda);
+    expect(after.rewardDebt.toString()).to.equal(capped.rewardDebt.toString());
+    expect(after.lastClaimTime.toNumber()).to.equal(capped.lastClaimTime.toNumber());
+    expect(after.lastStakeTime.toNumber()).to.equal(capped.lastStakeTime.toNumber());
+  });
+
+  it("Waives the early-unstake penalty during the grace period", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(71, { lockDuration: new anchor.BN(60) });
+    await program.methods
+      .setUnstakeGrace(pool.id, new anchor.BN(2))
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+    expect((await program.account.stakingPool.fetch(pool.pda)).unstakeGraceSeconds.toNumber()).to.equal(2);
+
+    const withinGrace = await createFundedUser(10_000);
+    await stakeInto(pool, withinGrace, 10_000);
+    await unstakeFrom(pool, withinGrace, 10_000);
+    expect(Number((await getAccount(connection, withinGrace.stakeAccount)).amount)).to.equal(10_000);
+
+    const afterGrace = await createFundedUser(10_000);
+    await stakeInto(pool, afterGrace, 10_000);
+    await new Promise(resolve => setTimeout(resolve, 3000));
+    await unstakeFrom(pool, afterGrace, 10_000);
+    const penalty = (10_000 * earlyUnstakePenaltyBps) / 10_000;
+    expect(Number((await getAccount(connection, afterGrace.stakeAccount)).amount)).to.equal(10_000 - penalty);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalForfeited.toNumber()).to.equal(penalty);
+  });
+
+  it("Charges no penalty after unlock regardless of the grace period", async () => {
+    const pool = await createPool(72, { lockDuration: new anchor.BN(1) });
+    await program.methods
+      .setUnstakeGrace(pool.id, new anchor.BN(1))
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 10_000);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await unstakeFrom(pool, user, 10_000);
+    expect(Number((await getAccount(provider.connection, user.stakeAccount)).amount)).to.equal(10_000);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalForfeited.toNumber()).to.equal(0);
+  });
+
+  it("Caps the number of positions a wallet can open", async () => {
+    const pool = await createPool(73, { rewardRate: new anchor.BN(0) });
+    await program.methods
+      .setMaxPositionsPerUser(pool.id, 2)
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+
+    const user = await createFundedUser(10_000);
+    const userAccount = userAccountPdaFor(user.wallet.publicKey);
+    await stakeInto(pool, user, 1_000, { position: 0 });
+    await stakeInto(pool, user, 1_000, { position: 1 });
+    expect((await program.account.userAccount.fetch(userAccount)).positions).to.equal(2);
+
+    // Topping up an open position is fine; a third one isn't
+    await stakeInto(pool, user, 1_000, { position: 1 });
+    await expectError(() => stakeInto(pool, user, 1_000, { position: 2 }), "TooManyPositions");
+
+    // Closing a position frees its slot
+    await unstakeFrom(pool, user, 2_000, 1);
+    await program.methods
+      .closeUserStake(pool.id, new anchor.BN(1))
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePdaFor(pool, user.wallet.publicKey, 1),
+        userAccount,
+        user: user.wallet.publicKey,
+      })
+      .signers([user.wallet])
+      .rpc();
+    expect((await program.account.userAccount.fetch(userAccount)).positions).to.equal(1);
+
+    await stakeInto(pool, user, 1_000, { position: 2 });
+    expect((await program.account.userAccount.fetch(userAccount)).positions).to.equal(2);
+  });
+
+  it("Pro-rates a position's rewards across two rate changes", async () => {
+    const pool = await createPool(74);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const amount = 1_000_000_000;
+    const user = await createFundedUser(amount);
+    await stakeInto(pool, user, amount);
+    const stakedAt = (await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey)))
+      .lastStakeTime.toNumber();
+
+    const changeRate = async (rate: number) => {
+      await proposeRewardRate(pool.id, pool.pda, rate);
+      await applyRewardRate(pool.id, pool.pda);
+      return (await program.account.stakingPool.fetch(pool.pda)).lastUpdateTime.toNumber();
+    };
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    const firstChange = await changeRate(20);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    const secondChange = await changeRate(5);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await claimFrom(pool, user);
+    const claimedAt = (await program.account.stakingPool.fetch(pool.pda)).lastUpdateTime.toNumber();
+
+    // One position, three segments, each at the rate in force during it
+    const rateSeconds =
+      BigInt(rewardRate.toNumber()) * BigInt(firstChange - stakedAt) +
+      BigInt(20) * BigInt(secondChange - firstChange) +
+      BigInt(5) * BigInt(claimedAt - secondChange);
+    const precision = BigInt(1_000_000_000_000);
+    const accPerShare = (rateSeconds * precision) / BigInt(86400);
+    const expectedReward = Number((BigInt(amount) * accPerShare) / precision);
+
+    expect(firstChange).to.be.greaterThan(stakedAt);
+    expect(secondChange).to.be.greaterThan(firstChange);
+    expect(Number((await getAccount(provider.connection, user.rewardAccount)).amount)).to.equal(expectedReward);
+  });
+
+  it("Lets a sponsor pay rent for a new stake account", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(75);
+    const user = await createFundedUser(10_000);
+
+    const sponsor = Keypair.generate();
+    await connection.confirmTransaction(
+      await connection.requestAirdrop(sponsor.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL)
+    );
+
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    const userAccount = userAccountPdaFor(user.wallet.publicKey);
+    const userLamportsBefore = await connection.getBalance(user.wallet.publicKey);
+    const sponsorLamportsBefore = await connection.getBalance(sponsor.publicKey);
+
+    await stakeInto(pool, user, 10_000, { payer: sponsor });
+
+    // The provider pays the transaction fee, so the sponsor is down exactly the rent
+    const rent =
+      (await connection.getAccountInfo(userStakePda)).lamports +
+      (await connection.getAccountInfo(userAccount)).lamports;
+    expect(await connection.getBalance(sponsor.publicKey)).to.equal(sponsorLamportsBefore - rent);
+    expect(await connection.getBalance(user.wallet.publicKey)).to.equal(userLamportsBefore);
+
+    // The position and the tokens are still the user's
+    const userStake = await program.account.userStake.fetch(userStakePda);
+    expect(userStake.owner.toBase58()).to.equal(user.wallet.publicKey.toBase58());
+    expect(userStake.stakeAmount.toNumber()).to.equal(10_000);
+    expect(Number((await getAccount(connection, user.stakeAccount)).amount)).to.equal(0);
+  });
+
+  it("Rejects transfers against a mint other than the pool's", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+    const pool = await createPool(76);
+    await fundPoolRewards(pool, 1_000_000);
+    const user = await createFundedUser(10_000);
+
+    // Same owner and program, different decimals: transfer_checked would price it differently
+    const otherMint = await createMint(connection, payer, adminWallet.publicKey, null, 6);
+    const withMints = (stakeMint: PublicKey, rewardMint: PublicKey) => ({ ...pool, stakeMint, rewardMint });
+
+    await expectError(() => stakeInto(withMints(otherMint, pool.rewardMint), user, 10_000), "ConstraintRaw");
+    await stakeInto(pool, user, 10_000);
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await expectError(() => claimFrom(withMints(pool.stakeMint, otherMint), user), "ConstraintTokenMint");
+    await claimFrom(pool, user);
+    expect(Number((await getAccount(connection, user.rewardAccount)).amount)).to.be.greaterThan(0);
+
+    await expectError(() => unstakeFrom(withMints(otherMint, pool.rewardMint), user, 10_000), "ConstraintRaw");
+    await unstakeFrom(pool, user, 10_000);
+    expect(Number((await getAccount(connection, user.stakeAccount)).amount)).to.equal(10_000);
+  });
+
+  it("Rejects a single stake above the pool's limit", async () => {
+    const pool = await createPool(77);
+    await program.methods
+      .setMaxSingleStake(pool.id, new anchor.BN(1_000))
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 1_000);
+    await expectError(() => stakeInto(pool, user, 1_001), "StakeTooLarge");
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey))).stakeAmount.toNumber())
+      .to.equal(1_000);
+  });
+
+  it("Creates the reward account on a first claim", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(78);
+    await fundPoolRewards(pool, 1_000_000_000);
+
+    const user = await createFundedUser(1_000_000);
+    await stakeInto(pool, user, 1_000_000);
+
+    // Start from a wallet that has never held the reward token
+    await closeAccount(connection, user.wallet, user.rewardAccount, user.wallet.publicKey, user.wallet);
+    expect(await connection.getAccountInfo(user.rewardAccount)).to.be.null;
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await claimFrom(pool, user);
+
+    const created = await getAccount(connection, user.rewardAccount);
+    expect(created.owner.toBase58()).to.equal(user.wallet.publicKey.toBase58());
+    expect(created.mint.toBase58()).to.equal(pool.rewardMint.toBase58());
+    expect(Number(created.amount)).to.be.greaterThan(0);
+  });
+
+  it("Reports the rate in effect right now", async () => {
+    const connection = provider.connection;
+    const effectiveRate = async (pool: Pool) =>
+      (await program.methods
+        .getEffectiveRate(pool.id)
+        .accounts({ stakingPool: pool.pda })
+        .view()).toNumber();
+
+    // Nothing accrues until the rewards window opens
+    const now = await connection.getBlockTime(await connection.getSlot());
+    const pool = await createPool(79, { rewardsStartTime: new anchor.BN(now + 3) });
+    expect(await effectiveRate(pool)).to.equal(0);
+    await new Promise(resolve => setTimeout(resolve, 4000));
+    expect(await effectiveRate(pool)).to.equal(rewardRate.toNumber());
+
+    // A schedule starts at its initial rate and decays one period at a time from there
+    await program.methods
+      .setRateSchedule(pool.id, new anchor.BN(1_000), 1_000, new anchor.BN(86400))
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+    expect(await effectiveRate(pool)).to.equal(1_000);
+
+    // Back to a flat rate
+    await proposeRewardRate(pool.id, pool.pda, 25);
+    await applyRewardRate(pool.id, pool.pda);
+    expect(await effectiveRate(pool)).to.equal(25);
+  });
+
+  it("Blocks a blacklisted wallet from staking and claiming", async () => {
+    const pool = await createPool(80);
+    await fundPoolRewards(pool, 1_000_000_000);
+    const user = await createFundedUser(10_000);
+    await stakeInto(pool, user, 5_000);
+
+    const blacklist = blacklistPdaFor(pool.pda, user.wallet.publicKey);
+    const setBlocksUnstake = (enabled: boolean) =>
+      program.methods
+        .setBlacklistBlocksUnstake(pool.id, enabled)
+        .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+        .rpc();
+
+    await program.methods
+      .addToBlacklist(pool.id, user.wallet.publicKey)
+      .accounts({
+        stakingPool: pool.pda,
+        blacklist,
+        admin: adminWallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await expectError(() => stakeInto(pool, user, 1_000), "Blacklisted");
+    await expectError(() => claimFrom(pool, user), "Blacklisted");
+
+    // Exits stay open unless the pool opts into blocking them too
+    await unstakeFrom(pool, user, 1_000);
+    await setBlocksUnstake(true);
+    await expectError(() => unstakeFrom(pool, user, 1_000), "Blacklisted");
+    await setBlocksUnstake(false);
+
+    await program.methods
+      .removeFromBlacklist(pool.id, user.wallet.publicKey)
+      .accounts({ stakingPool: pool.pda, blacklist, admin: adminWallet.publicKey })
+      .rpc();
+    expect(await provider.connection.getAccountInfo(blacklist)).to.be.null;
+
+    await stakeInto(pool, user, 1_000);
+    await claimFrom(pool, user);
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey))).stakeAmount.toNumber())
+      .to.equal(5_000);
+  });
+
+  it("Checks the owner's blacklist entry on every path out of a position", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+    // Rewards are paid in the stake mint so the compounding paths apply too
+    const pool = await createPool(97, { rewardEqualsStake: true, rewardRateIsPerSecond: true }, { rewardMint: stakeMint });
+    const target = await createPool(98);
+    await fundPoolRewards(pool, 1_000_000_000);
+    const owner = await createFundedUser(10_000);
+    const manager = await createFundedUser(1_000);
+    const keeper = await createFundedUser(0);
+    const newOwner = await createFundedUser(0);
+    await stakeInto(pool, owner, 1_000);
+    await stakeInto(target, owner, 100);
+
+    const userStakePda = userStakePdaFor(pool, owner.wallet.publicKey);
+    const blacklist = blacklistPdaFor(pool.pda, owner.wallet.publicKey);
+    const ownerAccounts = {
+      stakingPool: pool.pda,
+      userStake: userStakePda,
+      user: owner.wallet.publicKey,
+      blacklist,
+      receiptTokenAccount: null,
+    };
+    const setDelegate = (delegate: PublicKey | null) =>
+      program.methods
+        .setDelegate(pool.id, positionId, delegate)
+        .accounts({ stakingPool: pool.pda, userStake: userStakePda, user: owner.wallet.publicKey, blacklist })
+        .signers([owner.wallet])
+        .rpc();
+    await setDelegate(manager.wallet.publicKey);
+    await program.methods
+      .setAutoCompound(pool.id, positionId, true)
+      .accounts({ stakingPool: pool.pda, userStake: userStakePda, user: owner.wallet.publicKey })
+      .signers([owner.wallet])
+      .rpc();
+
+    const secondMint = await createMint(connection, payer, adminWallet.publicKey, null, 6);
+    const secondVault = await createAccount(connection, payer, secondMint, pool.pda, Keypair.generate());
+    const ownerSecondAccount = await createAccount(connection, payer, secondMint, owner.wallet.publicKey);
+    await mintTo(connection, payer, secondMint, secondVault, adminWallet.publicKey, 1_000_000_000);
+    await program.methods
+      .setSecondReward(pool.id, new anchor.BN(5))
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+        rewardMint2: secondMint,
+        poolRewardAccount2: secondVault,
+      })
+      .rpc();
+
+    await program.methods
+      .addToBlacklist(pool.id, owner.wallet.publicKey)
+      .accounts({
+        stakingPool: pool.pda,
+        blacklist,
+        admin: adminWallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    // The entry is the owner's, so it holds whoever signs
+    const claimAs = (signer: User) =>
+      program.methods
+        .claimRewards(pool.id, positionId)
+        .accounts({
+          ...ownerAccounts,
+          user: signer.wallet.publicKey,
+          rewardOwner: owner.wallet.publicKey,
+          userRewardAccount: owner.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          systemProgram: SystemProgram.programId,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+        .signers([signer.wallet])
+        .rpc();
+    const unstakeAccounts = {
+      ...ownerAccounts,
+      userTokenAccount: owner.stakeAccount,
+      poolStakeAccount: pool.stakeAccount,
+      stakeMint: pool.stakeMint,
+      feeTokenAccount: null,
+      tokenProgram: pool.stakeTokenProgram,
+    };
+    const restakeAccounts = {
+      ...ownerAccounts,
+      poolStakeAccount: pool.stakeAccount,
+      poolRewardAccount: pool.rewardAccount,
+      rewardMint: pool.rewardMint,
+      tokenProgram: pool.rewardTokenProgram,
+    };
+    const alwaysBlocked: (() => Promise<unknown>)[] = [
+      () => stakeInto(pool, owner, 100),
+      () => stakeInto(pool, manager, 100, { positionOwner: owner.wallet.publicKey }),
+      () => claimAs(owner),
+      () => claimAs(manager),
+      () => claimBuilder(pool, { ...owner, rewardAccount: owner.stakeAccount }, 1).rpc(),
+      () => program.methods
+        .claimRewards2(pool.id, positionId)
+        .accounts({
+          ...ownerAccounts,
+          userRewardAccount2: ownerSecondAccount,
+          poolRewardAccount2: secondVault,
+          rewardMint2: secondMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([owner.wallet])
+        .rpc(),
+      () => program.methods
+        .claimAll(pool.id)
+        .accounts({
+          stakingPool: pool.pda,
+          user: owner.wallet.publicKey,
+          blacklist,
+          userRewardAccount: owner.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .remainingAccounts([{ pubkey: userStakePda, isSigner: false, isWritable: true }])
+        .signers([owner.wallet])
+        .rpc(),
+      () => program.methods
+        .unstakeAndClaim(pool.id, positionId, new anchor.BN(100))
+        .accounts({
+          ...unstakeAccounts,
+          userRewardAccount: owner.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          systemProgram: SystemProgram.programId,
+          rewardTokenProgram: pool.rewardTokenProgram,
+        })
+        .signers([owner.wallet])
+        .rpc(),
+      () => program.methods.compound(pool.id, positionId).accounts(restakeAccounts).signers([owner.wallet]).rpc(),
+      () => program.methods
+        .restakeRewards(pool.id, positionId, new anchor.BN(1))
+        .accounts(restakeAccounts)
+        .signers([owner.wallet])
+        .rpc(),
+      () => program.methods
+        .keeperCompound(pool.id, positionId)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePda,
+          keeper: keeper.wallet.publicKey,
+          blacklist,
+          receiptTokenAccount: null,
+          keeperRewardAccount: keeper.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .signers([keeper.wallet])
+        .rpc(),
+      () => program.methods
+        .claimAndStakeInto(pool.id, positionId, target.id, positionId)
+        .accounts({
+          ...ownerAccounts,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          targetPool: target.pda,
+          targetUserStake: userStakePdaFor(target, owner.wallet.publicKey),
+          targetWhitelist: null,
+          targetBlacklist: blacklistPdaFor(target.pda, owner.wallet.publicKey),
+          targetPoolStakeAccount: target.stakeAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([owner.wallet])
+        .rpc(),
+      () => setDelegate(null),
+      () => program.methods
+        .transferPosition(pool.id, positionId, newOwner.wallet.publicKey)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePda,
+          newUserStake: userStakePdaFor(pool, newOwner.wallet.publicKey),
+          userAccount: userAccountPdaFor(owner.wallet.publicKey),
+          poolMember: poolMemberPdaFor(pool.pda, owner.wallet.publicKey),
+          newUserAccount: userAccountPdaFor(newOwner.wallet.publicKey),
+          newPoolMember: poolMemberPdaFor(pool.pda, newOwner.wallet.publicKey),
+          blacklist,
+          newOwnerBlacklist: blacklistPdaFor(pool.pda, newOwner.wallet.publicKey),
+          newOwnerWhitelist: null,
+          receiptTokenAccount: null,
+          user: owner.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([owner.wallet])
+        .rpc(),
+    ];
+    for (const call of alwaysBlocked) {
+      await expectError(call, "Blacklisted");
+    }
+
+    // Principal exits close only once the pool opts in
+    await program.methods
+      .setBlacklistBlocksUnstake(pool.id, true)
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+    const exits: (() => Promise<unknown>)[] = [
+      () => program.methods
+        .unstake(pool.id, positionId, new anchor.BN(100))
+        .accounts({ ...unstakeAccounts, systemProgram: SystemProgram.programId })
+        .signers([owner.wallet])
+        .rpc(),
+      () => program.methods
+        .emergencyUnstake(pool.id, positionId)
+        .accounts({ ...unstakeAccounts, systemProgram: SystemProgram.programId })
+        .signers([owner.wallet])
+        .rpc(),
+      () => program.methods
+        .requestUnstake(pool.id, positionId, new anchor.BN(100))
+        .accounts(ownerAccounts)
+        .signers([owner.wallet])
+        .rpc(),
+      () => program.methods
+        .withdrawUnstaked(pool.id, positionId)
+        .accounts(unstakeAccounts)
+        .signers([owner.wallet])
+        .rpc(),
+    ];
+    for (const call of exits) {
+      await expectError(call, "Blacklisted");
+    }
+    expect((await program.account.userStake.fetch(userStakePda)).stakeAmount.toNumber()).to.equal(1_000);
+  });
+
+  it("Keeps a position's peak stake after unstaking", async () => {
+    const pool = await createPool(81);
+    const user = await createFundedUser(10_000);
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    const watermark = async () => (await program.account.userStake.fetch(userStakePda)).maxStakeReached.toNumber();
+
+    await stakeInto(pool, user, 3_000);
+    expect(await watermark()).to.equal(3_000);
+    await stakeInto(pool, user, 4_000);
+    expect(await watermark()).to.equal(7_000);
+
+    await unstakeFrom(pool, user, 5_000);
+    expect(await watermark()).to.equal(7_000);
+
+    // Climbing back below the old peak leaves it alone
+    await stakeInto(pool, user, 2_000);
+    expect((await program.account.userStake.fetch(userStakePda)).stakeAmount.toNumber()).to.equal(4_000);
+    expect(await watermark()).to.equal(7_000);
+  });
+
+  it("Accrues nothing while accrual is frozen", async () => {
+    const pool = await createPool(82);
+    await fundPoolRewards(pool, 1_000_000_000);
+    const user = await createFundedUser(1_000_000_001);
+    await stakeInto(pool, user, 1_000_000_000);
+
+    const setFrozen = (frozen: boolean) =>
+      program.methods
+        .setAccrualFrozen(pool.id, frozen)
+        .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+        .rpc();
+    const pending = async () =>
+      BigInt((await program.methods
+        .getPendingRewards(pool.id, positionId)
+        .accounts({ stakingPool: pool.pda, userStake: userStakePdaFor(pool, user.wallet.publicKey) })
+        .view()).toString());
+
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await setFrozen(true);
+    const frozenPool = await program.account.stakingPool.fetch(pool.pda);
+    expect(frozenPool.accrualFrozenAt.toNumber()).to.equal(frozenPool.lastUpdateTime.toNumber());
+    const pendingAtFreeze = await pending();
+    expect(pendingAtFreeze > BigInt(0)).to.equal(true);
+
+    // Neither the view nor a settling action picks up the frozen time
+    await new Promise(resolve => setTimeout(resolve, 3000));
+    expect(await pending()).to.equal(pendingAtFreeze);
+    await stakeInto(pool, user, 1);
+    expect((await program.account.stakingPool.fetch(pool.pda)).accRewardPerShare.toString())
+      .to.equal(frozenPool.accRewardPerShare.toString());
+
+    await setFrozen(false);
+    const resumed = await program.account.stakingPool.fetch(pool.pda);
+    expect(resumed.accrualFrozenAt.toNumber()).to.equal(0);
+    expect(resumed.accRewardPerShare.toString()).to.equal(frozenPool.accRewardPerShare.toString());
+    expect(resumed.lastUpdateTime.toNumber()).to.be.at.least(frozenPool.accrualFrozenAt.toNumber() + 3);
+
+    // Accrual picks up from the unfreeze, not from the freeze
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await claimFrom(pool, user);
+    const claimed = await program.account.stakingPool.fetch(pool.pda);
+    const precision = BigInt(1_000_000_000_000);
+    const sinceUnfreeze = BigInt(claimed.lastUpdateTime.toNumber() - resumed.lastUpdateTime.toNumber());
+    const increment = BigInt(claimed.accRewardPerShare.toString()) - BigInt(resumed.accRewardPerShare.toString());
+    expect(increment > BigInt(0)).to.equal(true);
+    expect(increment <= (BigInt(rewardRate.toNumber()) * sinceUnfreeze * precision) / BigInt(86400) + BigInt(1)).to.equal(true);
+  });
+
+  it("Seeds several positions from one admin transfer", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(83);
+    const users = [await createFundedUser(100), await createFundedUser(100), await createFundedUser(100)];
+    for (const user of users) {
+      await stakeInto(pool, user, 100);
+    }
+
+    const adminStakeAccount = await createAccount(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      stakeMint,
+      adminWallet.publicKey,
+      Keypair.generate()
+    );
+    await mintTo(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      stakeMint,
+      adminStakeAccount,
+      adminWallet.publicKey,
+      6_000
+    );
+    const seed = (amounts: number[], positions: PublicKey[]) =>
+      program.methods
+        .seedStake(pool.id, amounts.map(amount => new anchor.BN(amount)))
+        .accounts({
+          stakingPool: pool.pda,
+          admin: adminWallet.publicKey,
+          adminTokenAccount: adminStakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          stakeMint: pool.stakeMint,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .remainingAccounts(positions.map(pubkey => ({ pubkey, isWritable: true, isSigner: false })))
+        .rpc();
+    const positions = users.map(user => userStakePdaFor(pool, user.wallet.publicKey));
+
+    await expectError(() => seed([1_000, 2_000], positions), "InvalidPositionAccount");
+    await expectError(() => seed([1_000, 2_000, 3_000], [positions[0], positions[0], positions[1]]), "InvalidPositionAccount");
+
+    await seed([1_000, 2_000, 3_000], positions);
+
+    const stakes = await Promise.all(positions.map(pda => program.account.userStake.fetch(pda)));
+    expect(stakes.map(stake => stake.stakeAmount.toNumber())).to.deep.equal([1_100, 2_100, 3_100]);
+    expect(stakes.map(stake => stake.maxStakeReached.toNumber())).to.deep.equal([1_100, 2_100, 3_100]);
+    expect((await program.account.stakingPool.fetch(pool.pda)).totalStaked.toNumber()).to.equal(6_300);
+    expect(Number((await getAccount(connection, pool.stakeAccount)).amount)).to.equal(6_300);
+    expect(Number((await getAccount(connection, adminStakeAccount)).amount)).to.equal(0);
+  });
+
+  it("Rounds a fractional reward by the pool's rounding mode", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(84, { rewardRate: new anchor.BN(0) });
+    const first = await createFundedUser(1);
+    const second = await createFundedUser(1);
+    await stakeInto(pool, first, 1);
+    await stakeInto(pool, second, 1);
+
+    const adminRewardAccount = await createAccount(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      adminWallet.publicKey,
+      Keypair.generate()
+    );
+    await mintTo(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      rewardMint,
+      adminRewardAccount,
+      adminWallet.publicKey,
+      3
+    );
+    // 3 reward base units over 2 staked leaves each position 1.5
+    await program.methods
+      .distribute(pool.id, new anchor.BN(3))
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+        adminTokenAccount: adminRewardAccount,
+        poolRewardAccount: pool.rewardAccount,
+        rewardMint: pool.rewardMint,
+        tokenProgram: pool.rewardTokenProgram,
+      })
+      .rpc();
+
+    const setRoundingMode = (roundingMode: number) =>
+      program.methods
+        .setRoundingMode(pool.id, roundingMode)
+        .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+        .rpc();
+    const pending = async (user: User) =>
+      (await program.methods
+        .getPendingRewards(pool.id, positionId)
+        .accounts({ stakingPool: pool.pda, userStake: userStakePdaFor(pool, user.wallet.publicKey) })
+        .view()).toNumber();
+
+    expect((await program.account.stakingPool.fetch(pool.pda)).roundingMode).to.equal(0);
+    expect(await pending(first)).to.equal(1);
+    await setRoundingMode(1);
+    expect(await pending(first)).to.equal(2);
+    await setRoundingMode(2);
+    expect(await pending(first)).to.equal(2);
+    await expectError(() => setRoundingMode(3), "InvalidRoundingMode");
+
+    // Floor carries the half; ceil pays it out
+    await setRoundingMode(0);
+    await claimFrom(pool, first);
+    expect(Number((await getAccount(connection, first.rewardAccount)).amount)).to.equal(1);
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, first.wallet.publicKey))).rewardRemainder.toString())
+      .to.not.equal("0");
+
+    await setRoundingMode(2);
+    await claimFrom(pool, second);
+    expect(Number((await getAccount(connection, second.rewardAccount)).amount)).to.equal(2);
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, second.wallet.publicKey))).rewardRemainder.toNumber())
+      .to.equal(0);
+  });
+
+  it("Transfers a locked position to another wallet", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(85);
+    const owner = await createFundedUser(1_000);
+    const newOwner = await createFundedUser(1);
+    const other = await createFundedUser(100);
+    await stakeInto(pool, owner, 1_000, { lockDays: 30 });
+    await stakeInto(pool, other, 100);
+
+    const transfer = (user: User, to: PublicKey) =>
+      program.methods
+        .transferPosition(pool.id, positionId, to)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePdaFor(pool, user.wallet.publicKey),
+          newUserStake: userStakePdaFor(pool, to),
+          userAccount: userAccountPdaFor(user.wallet.publicKey),
+          poolMember: poolMemberPdaFor(pool.pda, user.wallet.publicKey),
+          newUserAccount: userAccountPdaFor(to),
+          newPoolMember: poolMemberPdaFor(pool.pda, to),
+          blacklist: blacklistPdaFor(pool.pda, user.wallet.publicKey),
+          newOwnerBlacklist: blacklistPdaFor(pool.pda, to),
+          newOwnerWhitelist: null,
+          receiptTokenAccount: null,
+          user: user.wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user.wallet])
+        .rpc();
+
+    const before = await program.account.userStake.fetch(userStakePdaFor(pool, owner.wallet.publicKey));
+    await transfer(owner, newOwner.wallet.publicKey);
+
+    expect(await connection.getAccountInfo(userStakePdaFor(pool, owner.wallet.publicKey))).to.be.null;
+    const moved = await program.account.userStake.fetch(userStakePdaFor(pool, newOwner.wallet.publicKey));
+    expect(moved.owner.toBase58()).to.equal(newOwner.wallet.publicKey.toBase58());
+    expect(moved.stakeAmount.toNumber()).to.equal(1_000);
+    expect(moved.unlockTime.toNumber()).to.equal(before.unlockTime.toNumber());
+    expect(moved.lockMultiplierBps).to.equal(before.lockMultiplierBps);
+    expect(moved.rewardCheckpoint.toString()).to.equal(before.rewardCheckpoint.toString());
+    expect((await program.account.userAccount.fetch(userAccountPdaFor(owner.wallet.publicKey))).positions).to.equal(0);
+    expect((await program.account.userAccount.fetch(userAccountPdaFor(newOwner.wallet.publicKey))).positions).to.equal(1);
+
+    // A wallet that already has the position id can't receive another
+    await expectError(() => transfer(newOwner, other.wallet.publicKey), "PositionExists");
+
+    // The lock came along, so leaving early still costs the penalty
+    await unstakeFrom(pool, newOwner, 1_000);
+    expect(Number((await getAccount(connection, newOwner.stakeAccount)).amount)).to.equal(1 + 950);
+  });
+
+  it("Only shares the stake mint for rewards when the pool opts in", async () => {
+    const connection = provider.connection;
+    await expectError(() => createPool(86, {}, { rewardMint: stakeMint }), "MintCollision");
+
+    const shared = await createPool(86, { rewardEqualsStake: true, rewardRateIsPerSecond: true }, { rewardMint: stakeMint });
+    expect((await program.account.stakingPool.fetch(shared.pda)).rewardEqualsStake).to.equal(true);
+    await fundPoolRewards(shared, 1_000_000);
+    const user = await createFundedUser(1_000);
+    user.rewardAccount = user.stakeAccount;
+    await stakeInto(shared, user, 1_000);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await claimFrom(shared, user);
+
+    // Rewards came out of the reward vault; the staked principal is untouched
+    expect(Number((await getAccount(connection, shared.stakeAccount)).amount)).to.equal(1_000);
+    expect(Number((await getAccount(connection, shared.rewardAccount)).amount)).to.be.below(1_000_000);
+
+    // Pointing the reward vault at the stake vault is refused outright
+    const setRewardVault = (pool: Pool, mint: PublicKey, vault: PublicKey) =>
+      program.methods
+        .setRewardMint(pool.id)
+        .accounts({
+          stakingPool: pool.pda,
+          admin: adminWallet.publicKey,
+          newRewardMint: mint,
+          newPoolRewardAccount: vault,
+        })
+        .rpc();
+    await expectError(() => setRewardVault(shared, stakeMint, shared.stakeAccount), "InvalidAccountAlias");
+
+    // A pool that didn't opt in can't switch its rewards to the stake mint later either
+    const separate = await createPool(87);
+    expect((await program.account.stakingPool.fetch(separate.pda)).rewardEqualsStake).to.equal(false);
+    const stakeMintVault = await createAccount(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      stakeMint,
+      separate.pda,
+      Keypair.generate()
+    );
+    await expectError(() => setRewardVault(separate, stakeMint, stakeMintVault), "MintCollision");
+  });
+
+  it("Rejects a rate hike the reward vault can't cover for a day", async () => {
+    const pool = await createPool(88, { rewardRate: new anchor.BN(1) });
+    await fundPoolRewards(pool, 5_000);
+    const user = await createFundedUser(1_000);
+    await stakeInto(pool, user, 1_000);
+
+    await program.methods
+      .setRequireSolvencyOnRateChange(pool.id, true)
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+    expect((await program.account.stakingPool.fetch(pool.pda)).requireSolvencyOnRateChange).to.equal(true);
+
+    // A day at 10 per token on 1,000 staked owes 10,000; the vault only holds 5,000
+    await proposeRewardRate(pool.id, pool.pda, 10);
+    await expectError(() => applyRewardRate(pool.id, pool.pda), "InsufficientRewardFunds");
+    const rejected = await program.account.stakingPool.fetch(pool.pda);
+    expect(rejected.pendingRewardRate.toNumber()).to.equal(10);
+
+    await fundPoolRewards(pool, 5_000);
+    await applyRewardRate(pool.id, pool.pda);
+    expect((await program.account.stakingPool.fetch(pool.pda)).rewardRate.toNumber()).to.equal(10);
+  });
+
+  it("Mints a position receipt and requires it for claims", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(89, { rewardRateIsPerSecond: true });
+    await fundPoolRewards(pool, 1_000_000_000);
+    const user = await createFundedUser(1_000);
+    const buyer = await createFundedUser(1);
+    await stakeInto(pool, user, 1_000);
+
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    const [receiptMint] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("receipt"), userStakePda.toBuffer()],
+      program.programId
+    );
+    const receiptAccount = getAssociatedTokenAddressSync(receiptMint, user.wallet.publicKey);
+    await program.methods
+      .mintReceipt(pool.id, positionId)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePda,
+        receiptMint,
+        receiptTokenAccount: receiptAccount,
+        user: user.wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user.wallet])
+      .rpc();
+
+    // Exactly one token exists and no more can be minted
+    const mint = await getMint(connection, receiptMint);
+    expect(Number(mint.supply)).to.equal(1);
+    expect(mint.decimals).to.equal(0);
+    expect(mint.mintAuthority).to.be.null;
+    expect(Number((await getAccount(connection, receiptAccount)).amount)).to.equal(1);
+    expect((await program.account.userStake.fetch(userStakePda)).receiptMint.toBase58()).to.equal(receiptMint.toBase58());
+
+    await program.methods
+      .setReceiptRequired(pool.id, true)
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+
+    await expectError(() => claimFrom(pool, user), "ReceiptRequired");
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await claimBuilder(pool, user, undefined, 0, receiptAccount).rpc();
+    expect(Number((await getAccount(connection, user.rewardAccount)).amount)).to.be.above(0);
+
+    // Once the receipt moves on, the owner can no longer claim with their old account
+    const buyerReceiptAccount = await createAccount(
+      connection,
+      (adminWallet as anchor.Wallet).payer,
+      receiptMint,
+      buyer.wallet.publicKey
+    );
+    await transfer(connection, (adminWallet as anchor.Wallet).payer, receiptAccount, buyerReceiptAccount, user.wallet, 1);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await expectError(() => claimBuilder(pool, user, undefined, 0, receiptAccount).rpc(), "ReceiptRequired");
+  });
+
+  it("Refuses every exit to an owner without the receipt and lets the holder claim", async () => {
+    const connection = provider.connection;
+    const payer = (adminWallet as anchor.Wallet).payer;
+    // Rewards are paid in the stake mint so the compounding paths apply too
+    const pool = await createPool(95, { rewardEqualsStake: true, rewardRateIsPerSecond: true }, { rewardMint: stakeMint });
+    const target = await createPool(96);
+    await fundPoolRewards(pool, 1_000_000_000);
+    const owner = await createFundedUser(10_000);
+    const buyer = await createFundedUser(1);
+    const keeper = await createFundedUser(0);
+    await stakeInto(pool, owner, 1_000);
+    await stakeInto(target, owner, 100);
+
+    const userStakePda = userStakePdaFor(pool, owner.wallet.publicKey);
+    const [receiptMint] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("receipt"), userStakePda.toBuffer()],
+      program.programId
+    );
+    const ownerReceipt = getAssociatedTokenAddressSync(receiptMint, owner.wallet.publicKey);
+    await program.methods
+      .mintReceipt(pool.id, positionId)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePda,
+        receiptMint,
+        receiptTokenAccount: ownerReceipt,
+        user: owner.wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([owner.wallet])
+      .rpc();
+    await program.methods
+      .setAutoCompound(pool.id, positionId, true)
+      .accounts({ stakingPool: pool.pda, userStake: userStakePda, user: owner.wallet.publicKey })
+      .signers([owner.wallet])
+      .rpc();
+
+    const secondMint = await createMint(connection, payer, adminWallet.publicKey, null, 6);
+    const secondVault = await createAccount(connection, payer, secondMint, pool.pda, Keypair.generate());
+    const ownerSecondAccount = await createAccount(connection, payer, secondMint, owner.wallet.publicKey);
+    await mintTo(connection, payer, secondMint, secondVault, adminWallet.publicKey, 1_000_000_000);
+    await program.methods
+      .setSecondReward(pool.id, new anchor.BN(5))
+      .accounts({
+        stakingPool: pool.pda,
+        admin: adminWallet.publicKey,
+        rewardMint2: secondMint,
+        poolRewardAccount2: secondVault,
+      })
+      .rpc();
+    await program.methods
+      .setReceiptRequired(pool.id, true)
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+
+    // The receipt changes hands, so the owner's account is now empty
+    const buyerReceipt = await createAccount(connection, payer, receiptMint, buyer.wallet.publicKey);
+    await transfer(connection, payer, ownerReceipt, buyerReceipt, owner.wallet, 1);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+
+    const ownerAccounts = {
+      stakingPool: pool.pda,
+      userStake: userStakePda,
+      user: owner.wallet.publicKey,
+      blacklist: blacklistPdaFor(pool.pda, owner.wallet.publicKey),
+      receiptTokenAccount: ownerReceipt,
+    };
+    const refused: [string, () => Promise<unknown>][] = [
+      ["unstake", () => program.methods
+        .unstake(pool.id, positionId, new anchor.BN(100))
+        .accounts({
+          ...ownerAccounts,
+          userTokenAccount: owner.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          systemProgram: SystemProgram.programId,
+          stakeMint: pool.stakeMint,
+          feeTokenAccount: null,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .signers([owner.wallet])
+        .rpc()],
+      ["unstakeAndClaim", () => program.methods
+        .unstakeAndClaim(pool.id, positionId, new anchor.BN(100))
+        .accounts({
+          ...ownerAccounts,
+          userTokenAccount: owner.stakeAccount,
+          userRewardAccount: owner.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          stakeMint: pool.stakeMint,
+          rewardMint: pool.rewardMint,
+          feeTokenAccount: null,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: pool.stakeTokenProgram,
+          rewardTokenProgram: pool.rewardTokenProgram,
+        })
+        .signers([owner.wallet])
+        .rpc()],
+      ["emergencyUnstake", () => program.methods
+        .emergencyUnstake(pool.id, positionId)
+        .accounts({
+          ...ownerAccounts,
+          userTokenAccount: owner.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          stakeMint: pool.stakeMint,
+          feeTokenAccount: null,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .signers([owner.wallet])
+        .rpc()],
+      ["requestUnstake", () => program.methods
+        .requestUnstake(pool.id, positionId, new anchor.BN(100))
+        .accounts(ownerAccounts)
+        .signers([owner.wallet])
+        .rpc()],
+      ["withdrawUnstaked", () => program.methods
+        .withdrawUnstaked(pool.id, positionId)
+        .accounts({
+          ...ownerAccounts,
+          userTokenAccount: owner.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          stakeMint: pool.stakeMint,
+          feeTokenAccount: null,
+          tokenProgram: pool.stakeTokenProgram,
+        })
+        .signers([owner.wallet])
+        .rpc()],
+      ["claimRewards", () => claimBuilder(pool, { ...owner, rewardAccount: owner.stakeAccount }, undefined, 0, ownerReceipt).rpc()],
+      ["claimRewardsPartial", () => claimBuilder(pool, { ...owner, rewardAccount: owner.stakeAccount }, 1, 0, ownerReceipt).rpc()],
+      ["claimRewards2", () => program.methods
+        .claimRewards2(pool.id, positionId)
+        .accounts({
+          ...ownerAccounts,
+          userRewardAccount2: ownerSecondAccount,
+          poolRewardAccount2: secondVault,
+          rewardMint2: secondMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([owner.wallet])
+        .rpc()],
+      ["claimAll", () => program.methods
+        .claimAll(pool.id)
+        .accounts({
+          stakingPool: pool.pda,
+          user: owner.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, owner.wallet.publicKey),
+          userRewardAccount: owner.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .remainingAccounts([
+          { pubkey: userStakePda, isSigner: false, isWritable: true },
+          { pubkey: ownerReceipt, isSigner: false, isWritable: false },
+        ])
+        .signers([owner.wallet])
+        .rpc()],
+      ["compound", () => program.methods
+        .compound(pool.id, positionId)
+        .accounts({
+          ...ownerAccounts,
+          poolStakeAccount: pool.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .signers([owner.wallet])
+        .rpc()],
+      ["restakeRewards", () => program.methods
+        .restakeRewards(pool.id, positionId, new anchor.BN(1))
+        .accounts({
+          ...ownerAccounts,
+          poolStakeAccount: pool.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .signers([owner.wallet])
+        .rpc()],
+      ["keeperCompound", () => program.methods
+        .keeperCompound(pool.id, positionId)
+        .accounts({
+          stakingPool: pool.pda,
+          userStake: userStakePda,
+          keeper: keeper.wallet.publicKey,
+          blacklist: blacklistPdaFor(pool.pda, owner.wallet.publicKey),
+          receiptTokenAccount: ownerReceipt,
+          keeperRewardAccount: keeper.stakeAccount,
+          poolStakeAccount: pool.stakeAccount,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          tokenProgram: pool.rewardTokenProgram,
+        })
+        .signers([keeper.wallet])
+        .rpc()],
+      ["claimAndStakeInto", () => program.methods
+        .claimAndStakeInto(pool.id, positionId, target.id, positionId)
+        .accounts({
+          ...ownerAccounts,
+          poolRewardAccount: pool.rewardAccount,
+          rewardMint: pool.rewardMint,
+          targetPool: target.pda,
+          targetUserStake: userStakePdaFor(target, owner.wallet.publicKey),
+          targetWhitelist: null,
+          targetBlacklist: blacklistPdaFor(target.pda, owner.wallet.publicKey),
+          targetPoolStakeAccount: target.stakeAccount,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([owner.wallet])
+        .rpc()],
+      ["transferPosition", () => program.methods
+        .transferPosition(pool.id, positionId, keeper.wallet.publicKey)
+        .accounts({
+          ...ownerAccounts,
+          newUserStake: userStakePdaFor(pool, keeper.wallet.publicKey),
+          userAccount: userAccountPdaFor(owner.wallet.publicKey),
+          poolMember: poolMemberPdaFor(pool.pda, owner.wallet.publicKey),
+          newUserAccount: userAccountPdaFor(keeper.wallet.publicKey),
+          newPoolMember: poolMemberPdaFor(pool.pda, keeper.wallet.publicKey),
+          newOwnerBlacklist: blacklistPdaFor(pool.pda, keeper.wallet.publicKey),
+          newOwnerWhitelist: null,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([owner.wallet])
+        .rpc()],
+    ];
+    for (const [, call] of refused) {
+      await expectError(call, "ReceiptRequired");
+    }
+
+    // The claim rights went with the receipt: the holder claims and is paid directly
+    const holder = { ...buyer, rewardAccount: buyer.stakeAccount };
+    const holderBalanceBefore = Number((await getAccount(connection, buyer.stakeAccount)).amount);
+    await program.methods
+      .claimRewards(pool.id, positionId)
+      .accounts({
+        stakingPool: pool.pda,
+        userStake: userStakePda,
+        user: holder.wallet.publicKey,
+        blacklist: blacklistPdaFor(pool.pda, owner.wallet.publicKey),
+        receiptTokenAccount: buyerReceipt,
+        rewardOwner: holder.wallet.publicKey,
+        userRewardAccount: holder.rewardAccount,
+        poolRewardAccount: pool.rewardAccount,
+        systemProgram: SystemProgram.programId,
+        rewardMint: pool.rewardMint,
+        tokenProgram: pool.rewardTokenProgram,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([holder.wallet])
+      .rpc();
+    expect(Number((await getAccount(connection, buyer.stakeAccount)).amount)).to.be.above(holderBalanceBefore);
+    expect((await program.account.userStake.fetch(userStakePda)).rewardDebt.toNumber()).to.equal(0);
+  });
+
+  it("Lets the admin shorten a lock but only extend it when allowed", async () => {
+    const pool = await createPool(90);
+    const user = await createFundedUser(1_000);
+    await stakeInto(pool, user, 1_000, { lockDays: 30 });
+    const userStakePda = userStakePdaFor(pool, user.wallet.publicKey);
+    const staked = await program.account.userStake.fetch(userStakePda);
+
+    const adjustLock = (newUnlockTime: number) =>
+      program.methods
+        .adjustLock(pool.id, positionId, new anchor.BN(newUnlockTime))
+        .accounts({ stakingPool: pool.pda, userStake: userStakePda, admin: adminWallet.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+    const shortened = staked.lastStakeTime.toNumber() + 3600;
+    const signature = await adjustLock(shortened);
+    expect((await program.account.userStake.fetch(userStakePda)).unlockTime.toNumber()).to.equal(shortened);
+
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    const events = [...parser.parseLogs(tx.meta.logMessages)];
+    expect(events.map(event => event.name)).to.deep.equal(["lockAdjusted"]);
+    expect(events[0].data.user.toString()).to.equal(user.wallet.publicKey.toString());
+    expect(events[0].data.oldUnlockTime.toNumber()).to.equal(staked.unlockTime.toNumber());
+    expect(events[0].data.newUnlockTime.toNumber()).to.equal(shortened);
+
+    await expectError(() => adjustLock(staked.lastStakeTime.toNumber() - 100), "InvalidUnlockTime");
+    await expectError(() => adjustLock(shortened + 1), "LockExtensionNotAllowed");
+
+    await program.methods
+      .setAdminCanExtendLock(pool.id, true)
+      .accounts({ stakingPool: pool.pda, admin: adminWallet.publicKey })
+      .rpc();
+    await adjustLock(staked.unlockTime.toNumber() + 86400);
+    expect((await program.account.userStake.fetch(userStakePdaFor(pool, user.wallet.publicKey))).unlockTime.toNumber())
+      .to.equal(staked.unlockTime.toNumber() + 86400);
+  });
+
+  it("Reports display units that match the pool's mints", async () => {
+    const connection = provider.connection;
+    const pool = await createPool(91);
+    const user = await createFundedUser(1_000);
+    await stakeInto(pool, user, 1_000);
+
+    const info = await program.methods
+      .getDisplayInfo(pool.id)
+      .accounts({ stakingPool: pool.pda })
+      .view();
+
+    const stakeDecimals = (await getMint(connection, pool.stakeMint)).decimals;
+    expect(info.stakeDecimals).to.equal(stakeDecimals);
+    expect(info.rewardDecimals).to.equal((await getMint(connection, pool.rewardMint)).decimals);
+    expect(info.totalStaked.toNumber()).to.equal(1_000);
+    // 10 reward base units per staked base unit per day, per whole staked token
+    expect(info.rewardPerTokenPerDay.toString())
+      .to.equal(new anchor.BN(rewardRate.toNumber()).mul(new anchor.BN(10).pow(new anchor.BN(stakeDecimals))).toString());
   });
 });